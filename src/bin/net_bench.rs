@@ -0,0 +1,475 @@
+// 压力测试工具：模拟大量WebSocket客户端连接正在运行的服务器，
+// 按设定的速率发送房间消息与私聊，并模拟换房间和集中重连，最后报告吞吐量、投递延迟和丢失情况。
+//
+// 每条测试消息的文本为 "bench <客户端编号> <序号> <发送时间(微秒)>"。
+// 服务器会把房间消息和私聊都回显给发送方，发送方据此统计回显延迟与丢失；
+// 其他客户端收到的消息用于统计投递延迟（所有客户端在同一进程中，使用同一时钟）。
+use futures_util::{SinkExt, StreamExt};
+use net_app::protocol::ChatMessage;
+use rand::Rng;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::Message;
+
+const USAGE: &str = "用法: net_bench [选项] [WebSocket地址]\n\
+                     \x20 --clients N        模拟客户端数量（默认 50）\n\
+                     \x20 --rooms M          客户端分布的房间数（默认 5）\n\
+                     \x20 --rate R           每个客户端每秒发送的消息数（默认 1）\n\
+                     \x20 --private P        私聊消息所占比例，0 到 1（默认 0.1）\n\
+                     \x20 --churn C          每个客户端每秒换房间的概率（默认 0）\n\
+                     \x20 --storm S          每隔 S 秒触发一次集中重连，0 表示不触发（默认 0）\n\
+                     \x20 --storm-fraction F 每次集中重连断开的客户端比例（默认 0.5）\n\
+                     \x20 --duration D       测试时长，秒（默认 30）\n\
+                     \x20 --ramp MS          相邻客户端启动的间隔，毫秒（默认 5）\n\
+                     \x20 --grace G          测试结束后等待回显的时间，秒（默认 3）\n\
+                     默认连接 ws://127.0.0.1:8080/ws";
+
+const BENCH_PREFIX: &str = "bench ";
+const CONNECT_RETRY: Duration = Duration::from_secs(1);
+
+struct Config {
+    url: String,
+    clients: usize,
+    rooms: usize,
+    rate: f64,
+    private: f64,
+    churn: f64,
+    storm: f64,
+    storm_fraction: f64,
+    duration: Duration,
+    ramp: Duration,
+    grace: Duration,
+}
+
+fn exit_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn parse_args() -> Config {
+    let mut config = Config {
+        url: "ws://127.0.0.1:8080/ws".to_string(),
+        clients: 50,
+        rooms: 5,
+        rate: 1.0,
+        private: 0.1,
+        churn: 0.0,
+        storm: 0.0,
+        storm_fraction: 0.5,
+        duration: Duration::from_secs(30),
+        ramp: Duration::from_millis(5),
+        grace: Duration::from_secs(3),
+    };
+    
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        if !arg.starts_with("--") {
+            config.url = arg;
+            continue;
+        }
+        let Some(value) = args.next() else {
+            exit_usage();
+        };
+        let number = || value.parse::<f64>().ok().filter(|number| *number >= 0.0).unwrap_or_else(|| exit_usage());
+        match arg.as_str() {
+            "--clients" => config.clients = number() as usize,
+            "--rooms" => config.rooms = (number() as usize).max(1),
+            "--rate" => config.rate = number(),
+            "--private" => config.private = number().min(1.0),
+            "--churn" => config.churn = number(),
+            "--storm" => config.storm = number(),
+            "--storm-fraction" => config.storm_fraction = number().min(1.0),
+            "--duration" => config.duration = Duration::from_secs_f64(number()),
+            "--ramp" => config.ramp = Duration::from_secs_f64(number() / 1000.0),
+            "--grace" => config.grace = Duration::from_secs_f64(number()),
+            _ => exit_usage(),
+        }
+    }
+    config
+}
+
+fn now_micros() -> u64 {
+    chrono::Utc::now().timestamp_micros() as u64
+}
+
+fn room_name(index: usize) -> String {
+    format!("bench-{}", index)
+}
+
+// 所有客户端共享的状态：用户名表（用于选择私聊对象）与实时计数
+struct Shared {
+    usernames: RwLock<Vec<String>>,
+    connected: AtomicUsize,
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+// 单个客户端的统计，测试结束后汇总
+#[derive(Default)]
+struct ClientStats {
+    sent_room: u64,
+    sent_private: u64,
+    echoed: u64,
+    lost: u64,        // 测试结束并等待后仍未收到回显的消息
+    rejected: u64,    // 私聊对象已离线，服务器拒绝投递的消息
+    interrupted: u64, // 集中重连时尚未收到回显、随连接一起丢弃的消息
+    deliveries: u64,  // 收到的其他客户端的消息
+    joins: u64,
+    reconnects: u64,
+    disconnects: u64, // 非主动断开的连接
+    connect_failures: u64,
+    echo_latency: Vec<u64>,     // 微秒
+    delivery_latency: Vec<u64>, // 微秒
+    connect_latency: Vec<u64>,  // 从发起连接到收到服务器分配的用户名，微秒
+}
+
+impl ClientStats {
+    fn merge(&mut self, other: ClientStats) {
+        self.sent_room += other.sent_room;
+        self.sent_private += other.sent_private;
+        self.echoed += other.echoed;
+        self.lost += other.lost;
+        self.rejected += other.rejected;
+        self.interrupted += other.interrupted;
+        self.deliveries += other.deliveries;
+        self.joins += other.joins;
+        self.reconnects += other.reconnects;
+        self.disconnects += other.disconnects;
+        self.connect_failures += other.connect_failures;
+        self.echo_latency.extend(other.echo_latency);
+        self.delivery_latency.extend(other.delivery_latency);
+        self.connect_latency.extend(other.connect_latency);
+    }
+}
+
+// 解析测试消息文本，返回 (客户端编号, 序号, 发送时间)
+fn parse_bench(text: &str) -> Option<(usize, u64, u64)> {
+    let mut parts = text.strip_prefix(BENCH_PREFIX)?.split(' ');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+// 一次连接的结束原因
+enum SessionEnd {
+    Finished,   // 测试时间到
+    Storm,      // 集中重连，主动断开
+    Lost,       // 连接意外断开
+}
+
+type Socket = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+async fn send(socket: &mut Socket, message: ChatMessage) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    socket.send(Message::Text(serde_json::to_string(&message).unwrap())).await
+}
+
+// 连接服务器并等待服务器分配用户名
+async fn connect(url: &str) -> Result<(Socket, String), String> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(url).await.map_err(|e| e.to_string())?;
+    send(&mut socket, ChatMessage::new("chat", "", "", "")).await.map_err(|e| e.to_string())?;
+    while let Some(message) = socket.next().await {
+        let Message::Text(text) = message.map_err(|e| e.to_string())? else {
+            continue;
+        };
+        if let Ok(message) = serde_json::from_str::<ChatMessage>(&text) {
+            if message.msg_type == "chat" && message.text.is_empty() {
+                return Ok((socket, message.username));
+            }
+        }
+    }
+    Err("连接在收到用户名前关闭".to_string())
+}
+
+async fn client(index: usize, config: Arc<Config>, shared: Arc<Shared>, deadline: Instant, mut storms: watch::Receiver<u64>) -> ClientStats {
+    let mut stats = ClientStats::default();
+    let mut seq = 0u64;
+    let mut room = index % config.rooms;
+    
+    while Instant::now() < deadline {
+        let started = Instant::now();
+        let (mut socket, username) = match connect(&config.url).await {
+            Ok(connected) => connected,
+            Err(e) => {
+                stats.connect_failures += 1;
+                log::debug!("Client {} failed to connect: {}", index, e);
+                tokio::time::sleep(CONNECT_RETRY).await;
+                continue;
+            }
+        };
+        stats.connect_latency.push(started.elapsed().as_micros() as u64);
+        shared.usernames.write().unwrap()[index] = username.clone();
+        shared.connected.fetch_add(1, Ordering::Relaxed);
+        
+        let _ = send(&mut socket, ChatMessage::new("join", &username, &room_name(room), "")).await;
+        stats.joins += 1;
+        
+        // 发送间隔加入随机偏移，避免所有客户端同时发送
+        let mut send_interval = (config.rate > 0.0).then(|| {
+            let period = Duration::from_secs_f64(1.0 / config.rate);
+            tokio::time::interval_at(tokio::time::Instant::now() + period.mul_f64(rand::thread_rng().gen()), period)
+        });
+        let mut churn_interval = tokio::time::interval(Duration::from_secs(1));
+        let mut pending: HashSet<u64> = HashSet::new();
+        // 尚未回显的私聊序号，按发送顺序排列。服务器按顺序处理同一连接的消息，
+        // 收到“不在线”提示时被拒绝的总是其中最早的一条
+        let mut pending_private: VecDeque<u64> = VecDeque::new();
+        storms.mark_unchanged();
+        
+        let end = loop {
+            tokio::select! {
+                _ = async { send_interval.as_mut().unwrap().tick().await }, if send_interval.is_some() => {
+                    seq += 1;
+                    let text = format!("{}{} {} {}", BENCH_PREFIX, index, seq, now_micros());
+                    let mut message = ChatMessage::new("chat", &username, &room_name(room), &text);
+                    if config.clients > 1 && rand::thread_rng().gen_bool(config.private) {
+                        let target = (index + rand::thread_rng().gen_range(1..config.clients)) % config.clients;
+                        let target = shared.usernames.read().unwrap()[target].clone();
+                        if !target.is_empty() {
+                            message.msg_type = "private".to_string();
+                            message.target = Some(target);
+                        }
+                    }
+                    if message.msg_type == "private" {
+                        stats.sent_private += 1;
+                        pending_private.push_back(seq);
+                    } else {
+                        stats.sent_room += 1;
+                    }
+                    pending.insert(seq);
+                    shared.sent.fetch_add(1, Ordering::Relaxed);
+                    if send(&mut socket, message).await.is_err() {
+                        break SessionEnd::Lost;
+                    }
+                }
+                
+                _ = churn_interval.tick(), if config.churn > 0.0 && config.rooms > 1 => {
+                    if rand::thread_rng().gen_bool(config.churn.min(1.0)) {
+                        room = (room + rand::thread_rng().gen_range(1..config.rooms)) % config.rooms;
+                        stats.joins += 1;
+                        if send(&mut socket, ChatMessage::new("join", &username, &room_name(room), "")).await.is_err() {
+                            break SessionEnd::Lost;
+                        }
+                    }
+                }
+                
+                _ = storms.changed() => {
+                    if rand::thread_rng().gen_bool(config.storm_fraction) {
+                        break SessionEnd::Storm;
+                    }
+                }
+                
+                _ = tokio::time::sleep_until(deadline.into()) => break SessionEnd::Finished,
+                
+                message = socket.next() => {
+                    let Some(Ok(message)) = message else {
+                        break SessionEnd::Lost;
+                    };
+                    let Message::Text(text) = message else {
+                        continue;
+                    };
+                    let Ok(message) = serde_json::from_str::<ChatMessage>(&text) else {
+                        continue;
+                    };
+                    match message.msg_type.as_str() {
+                        // 回复服务器心跳，避免长时间测试中会话被清理
+                        "ping" if send(&mut socket, ChatMessage::new("pong", &username, "", &message.text)).await.is_err() => {
+                            break SessionEnd::Lost;
+                        }
+                        "chat" | "private" => {
+                            if let Some((sender, sent_seq, sent_at)) = parse_bench(&message.text) {
+                                if sender == index && message.msg_type == "private" {
+                                    pending_private.retain(|seq| *seq != sent_seq);
+                                }
+                                record(&mut stats, &shared, index, &mut pending, sender, sent_seq, sent_at);
+                            }
+                        }
+                        "system" if message.text.ends_with("不在线或不存在") => {
+                            if let Some(rejected) = pending_private.pop_front() {
+                                pending.remove(&rejected);
+                                stats.rejected += 1;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        };
+        
+        match end {
+            SessionEnd::Finished => {
+                // 等待尚未收到的回显
+                let grace = tokio::time::sleep(config.grace);
+                tokio::pin!(grace);
+                while !pending.is_empty() {
+                    tokio::select! {
+                        _ = &mut grace => break,
+                        message = socket.next() => {
+                            let text = match message {
+                                Some(Ok(Message::Text(text))) => text,
+                                Some(Ok(_)) => continue,
+                                _ => break,
+                            };
+                            if let Ok(message) = serde_json::from_str::<ChatMessage>(&text) {
+                                if let Some((sender, sent_seq, sent_at)) = parse_bench(&message.text) {
+                                    record(&mut stats, &shared, index, &mut pending, sender, sent_seq, sent_at);
+                                }
+                            }
+                        }
+                    }
+                }
+                stats.lost += pending.len() as u64;
+                let _ = socket.close(None).await;
+            }
+            SessionEnd::Storm => {
+                stats.reconnects += 1;
+                stats.interrupted += pending.len() as u64;
+                // 直接丢弃连接，不发送关闭帧，模拟网络中断
+                drop(socket);
+            }
+            SessionEnd::Lost => {
+                stats.disconnects += 1;
+                stats.interrupted += pending.len() as u64;
+            }
+        }
+        shared.usernames.write().unwrap()[index].clear();
+        shared.connected.fetch_sub(1, Ordering::Relaxed);
+    }
+    stats
+}
+
+fn record(stats: &mut ClientStats, shared: &Shared, index: usize, pending: &mut HashSet<u64>, sender: usize, seq: u64, sent_at: u64) {
+    let latency = now_micros().saturating_sub(sent_at);
+    shared.received.fetch_add(1, Ordering::Relaxed);
+    if sender != index {
+        stats.deliveries += 1;
+        stats.delivery_latency.push(latency);
+    } else if pending.remove(&seq) {
+        stats.echoed += 1;
+        stats.echo_latency.push(latency);
+    }
+}
+
+// 百分位数，samples 需已排序
+fn percentile(samples: &[u64], p: f64) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let rank = ((p / 100.0) * (samples.len() - 1) as f64).round() as usize;
+    samples[rank] as f64 / 1000.0
+}
+
+fn latency_line(label: &str, samples: &mut [u64]) -> String {
+    samples.sort_unstable();
+    if samples.is_empty() {
+        return format!("{}: 无样本", label);
+    }
+    format!(
+        "{}: p50 {:.2} ms  p90 {:.2} ms  p99 {:.2} ms  最大 {:.2} ms  ({} 个样本)",
+        label,
+        percentile(samples, 50.0),
+        percentile(samples, 90.0),
+        percentile(samples, 99.0),
+        percentile(samples, 100.0),
+        samples.len(),
+    )
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("warn"));
+    let config = Arc::new(parse_args());
+    println!(
+        "压力测试 {}：{} 个客户端，{} 个房间，每客户端每秒 {} 条消息（私聊 {:.0}%），换房间概率 {}/秒，时长 {} 秒",
+        config.url, config.clients, config.rooms, config.rate, config.private * 100.0, config.churn, config.duration.as_secs_f64(),
+    );
+    if config.storm > 0.0 {
+        println!("每 {} 秒断开 {:.0}% 的客户端并立即重连", config.storm, config.storm_fraction * 100.0);
+    }
+    
+    let shared = Arc::new(Shared {
+        usernames: RwLock::new(vec![String::new(); config.clients]),
+        connected: AtomicUsize::new(0),
+        sent: AtomicU64::new(0),
+        received: AtomicU64::new(0),
+    });
+    let started = Instant::now();
+    // 测试时长从全部客户端开始启动后计算
+    let deadline = started + config.ramp * config.clients as u32 + config.duration;
+    let (storm_sender, storms) = watch::channel(0u64);
+    
+    let mut tasks = Vec::with_capacity(config.clients);
+    for index in 0..config.clients {
+        let delay = config.ramp * index as u32;
+        let task = client(index, config.clone(), shared.clone(), deadline, storms.clone());
+        tasks.push(tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            task.await
+        }));
+    }
+    
+    // 每秒打印一次进度，并按设定间隔触发集中重连
+    let progress_shared = shared.clone();
+    let storm_every = (config.storm > 0.0).then(|| Duration::from_secs_f64(config.storm));
+    let progress = tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        let mut last_storm = Instant::now();
+        let mut generation = 0u64;
+        let (mut last_sent, mut last_received) = (0, 0);
+        loop {
+            tick.tick().await;
+            let sent = progress_shared.sent.load(Ordering::Relaxed);
+            let received = progress_shared.received.load(Ordering::Relaxed);
+            eprintln!(
+                "[{:>4}s] 在线 {:>5}  发送 {:>7} 条/秒  收到 {:>8} 条/秒",
+                started.elapsed().as_secs(),
+                progress_shared.connected.load(Ordering::Relaxed),
+                sent - last_sent,
+                received - last_received,
+            );
+            (last_sent, last_received) = (sent, received);
+            if let Some(storm_every) = storm_every {
+                if last_storm.elapsed() >= storm_every && Instant::now() < deadline {
+                    generation += 1;
+                    last_storm = Instant::now();
+                    eprintln!("[{:>4}s] 触发集中重连 #{}", started.elapsed().as_secs(), generation);
+                    let _ = storm_sender.send(generation);
+                }
+            }
+        }
+    });
+    
+    let mut total = ClientStats::default();
+    for task in tasks {
+        if let Ok(stats) = task.await {
+            total.merge(stats);
+        }
+    }
+    progress.abort();
+    let elapsed = (deadline - started).as_secs_f64();
+    
+    let sent = total.sent_room + total.sent_private;
+    let settled = sent - total.interrupted - total.rejected;
+    println!();
+    println!("发送消息: {} 条（房间 {}，私聊 {}），{:.1} 条/秒", sent, total.sent_room, total.sent_private, sent as f64 / elapsed);
+    println!("投递消息: {} 条（不含回显），{:.1} 条/秒", total.deliveries, total.deliveries as f64 / elapsed);
+    println!(
+        "回显: 收到 {} 条，丢失 {} 条（{:.3}%），集中重连或断线中断 {} 条，私聊对象离线 {} 条",
+        total.echoed,
+        total.lost,
+        if settled > 0 { total.lost as f64 * 100.0 / settled as f64 } else { 0.0 },
+        total.interrupted,
+        total.rejected,
+    );
+    println!("{}", latency_line("回显延迟", &mut total.echo_latency));
+    println!("{}", latency_line("投递延迟", &mut total.delivery_latency));
+    println!("{}", latency_line("建立连接", &mut total.connect_latency));
+    println!(
+        "加入房间 {} 次，集中重连 {} 次，意外断开 {} 次，连接失败 {} 次",
+        total.joins, total.reconnects, total.disconnects, total.connect_failures,
+    );
+}
@@ -0,0 +1,865 @@
+// 终端聊天客户端：通过 /ws 接口连接服务器，提供房间列表、用户列表、消息区与命令补全。
+// 标准输入或输出不是终端（或指定 --plain）时使用逐行模式，便于在脚本中使用：
+// 每行输入发送一条消息，收到的消息逐行打印到标准输出，连接状态打印到标准错误。
+use crossterm::event::{Event as TermEvent, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use futures_util::{SinkExt, StreamExt};
+use net_app::protocol::{command_name, ChatMessage};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::io::IsTerminal;
+use std::time::Duration;
+use tokio::io::AsyncBufReadExt;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+const DEFAULT_URL: &str = "ws://127.0.0.1:8080/ws";
+const LOBBY: &str = "大厅";
+// 客户端测量RTT的ping间隔
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+// 后台刷新房间列表的间隔
+const ROOMS_INTERVAL: Duration = Duration::from_secs(10);
+// 重连退避时间从1秒开始翻倍，最长30秒
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// 断开连接后等待服务器回复关闭帧的最长时间
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+// 消息区保留的最大行数
+const MAX_LINES: usize = 2000;
+
+const USAGE: &str = "用法: net_chat [--room 房间] [--plain] [WebSocket地址]\n\
+                     默认连接 ws://127.0.0.1:8080/ws，可用 net_app discover 查找局域网中的服务器";
+
+// 连接任务发给界面的事件
+enum Event {
+    Connecting,
+    Connected,
+    Disconnected { reason: String, retry_in: Duration },
+    Rtt(Duration),
+    Frame(ChatMessage),
+    Closed,
+}
+
+// 界面发给连接任务的请求
+enum Outgoing {
+    Frame(ChatMessage),
+    Close,
+}
+
+struct Options {
+    url: String,
+    room: String,
+    plain: bool,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options {
+        url: DEFAULT_URL.to_string(),
+        room: LOBBY.to_string(),
+        plain: !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal(),
+    };
+    
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--room" | "-r" => options.room = args.next().unwrap_or_else(|| exit_usage()),
+            "--plain" => options.plain = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            url if !url.starts_with('-') => options.url = url.to_string(),
+            _ => exit_usage(),
+        }
+    }
+    options
+}
+
+fn exit_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn now_micros() -> i64 {
+    chrono::Utc::now().timestamp_micros()
+}
+
+fn to_text(message: &ChatMessage) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap())
+}
+
+// 测速的控制消息：下载帧的确认、上传的文本数据与上传结束
+fn speedtest_message(name: &str, room: &str, op: &str, test: &str, text: &str) -> ChatMessage {
+    let mut message = ChatMessage::new("speedtest", name, room, text);
+    message.data = Some(serde_json::json!({ "op": op, "test": test }));
+    message
+}
+
+// 上传测速：在服务器给出的时长内连续发送数据帧，最后发送结束消息。
+// 每一帧都等待写入完成，发送速率受限于实际的网络吞吐
+async fn speedtest_upload<S>(socket: &mut S, name: &str, room: &str, begin: &serde_json::Value) -> Result<(), S::Error>
+where
+    S: futures_util::Sink<Message> + Unpin,
+{
+    let test = begin["test"].as_str().unwrap_or_default();
+    let size = begin["size"].as_u64().unwrap_or_default() as usize;
+    let duration = Duration::from_millis(begin["duration_ms"].as_u64().unwrap_or_default());
+    let frame = if begin["binary"] == true {
+        Message::Binary(vec![0; size])
+    } else {
+        to_text(&speedtest_message(name, room, "data", test, &"x".repeat(size)))
+    };
+    
+    let end = tokio::time::Instant::now() + duration;
+    while tokio::time::Instant::now() < end {
+        socket.send(frame.clone()).await?;
+    }
+    socket.send(to_text(&speedtest_message(name, room, "end", test, ""))).await
+}
+
+// 维持与服务器的连接：断开后按指数退避重连，重连后回到之前的房间。
+// 服务器的ping由这里直接回复，客户端ping的往返时间以 Rtt 事件报告给界面
+async fn connection(
+    url: String,
+    mut room: String,
+    events: mpsc::UnboundedSender<Event>,
+    mut outgoing: mpsc::UnboundedReceiver<Outgoing>,
+) {
+    let mut backoff = MIN_BACKOFF;
+    // 用户名由服务器分配，每次重连都可能不同
+    let mut name = String::new();
+    // 断开期间产生的消息，重连后依次发送
+    let mut pending: VecDeque<ChatMessage> = VecDeque::new();
+    
+    loop {
+        let _ = events.send(Event::Connecting);
+        let reason = match tokio_tungstenite::connect_async(url.as_str()).await {
+            Ok((mut socket, _)) => {
+                backoff = MIN_BACKOFF;
+                let _ = events.send(Event::Connected);
+                
+                // 与网页客户端相同，连接后先发送一条空的chat消息
+                let mut greeting = vec![ChatMessage::new("chat", &name, LOBBY, "")];
+                if room != LOBBY {
+                    greeting.push(ChatMessage::new("join", &name, &room, ""));
+                }
+                
+                let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+                let mut queue: VecDeque<ChatMessage> = greeting.into_iter().chain(pending.drain(..)).collect();
+                // 正在进行的下载测速，收到的数据帧逐帧确认
+                let mut speedtest: Option<String> = None;
+                
+                let reason = 'session: loop {
+                    while let Some(message) = queue.pop_front() {
+                        if let Err(e) = socket.send(to_text(&message)).await {
+                            pending.push_back(message);
+                            pending.extend(queue.drain(..));
+                            break 'session e.to_string();
+                        }
+                    }
+                    
+                    tokio::select! {
+                        _ = ping_interval.tick() => {
+                            queue.push_back(ChatMessage::new("ping", &name, &room, &now_micros().to_string()));
+                        }
+                        
+                        request = outgoing.recv() => match request {
+                            Some(Outgoing::Frame(message)) => {
+                                if message.msg_type == "join" {
+                                    room = message.room.clone();
+                                }
+                                queue.push_back(message);
+                            }
+                            Some(Outgoing::Close) | None => {
+                                // 等待服务器回复关闭帧，之前命令的回复仍会送达
+                                let _ = socket.send(Message::Close(None)).await;
+                                let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                                    while let Some(Ok(message)) = socket.next().await {
+                                        let Message::Text(text) = message else {
+                                            continue;
+                                        };
+                                        match serde_json::from_str::<ChatMessage>(&text) {
+                                            Ok(message) if message.msg_type != "ping" && message.msg_type != "pong" => {
+                                                let _ = events.send(Event::Frame(message));
+                                            }
+                                            _ => {}
+                                        }
+                                    }
+                                }).await;
+                                let _ = events.send(Event::Closed);
+                                return;
+                            }
+                        },
+                        
+                        message = socket.next() => match message {
+                            Some(Ok(Message::Text(text))) => {
+                                let Ok(message) = serde_json::from_str::<ChatMessage>(&text) else {
+                                    continue;
+                                };
+                                match message.msg_type.as_str() {
+                                    // 空文本的chat消息为服务器分配的用户名
+                                    "chat" if message.text.is_empty() => name = message.username.clone(),
+                                    "ping" => {
+                                        queue.push_back(ChatMessage::new("pong", &name, &room, &message.text));
+                                        if message.text.is_empty() {
+                                            continue; // 服务器心跳，不显示
+                                        }
+                                    }
+                                    "pong" => {
+                                        if let Ok(sent) = message.text.parse::<i64>() {
+                                            let rtt = Duration::from_micros(now_micros().saturating_sub(sent).max(0) as u64);
+                                            let _ = events.send(Event::Rtt(rtt));
+                                        }
+                                        continue;
+                                    }
+                                    // 完整用户列表总是针对当前所在的房间
+                                    "userlist" if message.data.as_ref().is_some_and(|data| data["op"] == "snapshot") => {
+                                        room = message.room.clone();
+                                    }
+                                    "speedtest" => {
+                                        let data = message.data.clone().unwrap_or_default();
+                                        let test = data["test"].as_str().unwrap_or_default();
+                                        match data["op"].as_str() {
+                                            Some("data") => {
+                                                queue.push_back(speedtest_message(&name, &room, "ack", test, ""));
+                                                continue;
+                                            }
+                                            Some("begin") if data["direction"] == "upload" => {
+                                                let _ = events.send(Event::Frame(message));
+                                                if let Err(e) = speedtest_upload(&mut socket, &name, &room, &data).await {
+                                                    break 'session e.to_string();
+                                                }
+                                                continue;
+                                            }
+                                            Some("begin") => speedtest = Some(test.to_string()),
+                                            Some("report") => speedtest = None,
+                                            _ => {}
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                                let _ = events.send(Event::Frame(message));
+                            }
+                            Some(Ok(Message::Binary(_))) => {
+                                if let Some(test) = &speedtest {
+                                    queue.push_back(speedtest_message(&name, &room, "ack", test, ""));
+                                }
+                            }
+                            Some(Ok(Message::Close(close))) => {
+                                break close.map(|close| format!("服务器关闭连接: {}", close.reason))
+                                    .unwrap_or_else(|| "服务器关闭连接".to_string());
+                            }
+                            Some(Ok(_)) => {}
+                            Some(Err(e)) => break e.to_string(),
+                            None => break "连接已断开".to_string(),
+                        },
+                    }
+                };
+                reason
+            }
+            Err(e) => e.to_string(),
+        };
+        
+        let _ = events.send(Event::Disconnected { reason, retry_in: backoff });
+        
+        // 退避等待期间仍需响应退出请求
+        let retry = tokio::time::sleep(backoff);
+        tokio::pin!(retry);
+        loop {
+            tokio::select! {
+                _ = &mut retry => break,
+                request = outgoing.recv() => match request {
+                    Some(Outgoing::Frame(message)) => {
+                        if message.msg_type == "join" {
+                            room = message.room.clone();
+                        }
+                        pending.push_back(message);
+                    }
+                    Some(Outgoing::Close) | None => {
+                        let _ = events.send(Event::Closed);
+                        return;
+                    }
+                },
+            }
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+// 用户输入的解析结果
+enum Input {
+    Send(ChatMessage),
+    Quit,
+    Invalid(String),
+    Empty,
+}
+
+// /join、/msg 和 /quit 由客户端处理，其他斜杠命令交给服务器
+fn parse_input(line: &str, username: &str, room: &str) -> Input {
+    let line = line.trim();
+    if line.is_empty() {
+        return Input::Empty;
+    }
+    if !line.starts_with('/') {
+        return Input::Send(ChatMessage::new("chat", username, room, line));
+    }
+    
+    let mut parts = line.splitn(3, ' ');
+    match parts.next().unwrap_or_default() {
+        "/quit" => Input::Quit,
+        "/join" => match parts.next().filter(|room| !room.is_empty()) {
+            Some(new_room) => Input::Send(ChatMessage::new("join", username, new_room, "")),
+            None => Input::Invalid("用法: /join <房间名>".to_string()),
+        },
+        "/msg" => match (parts.next(), parts.next().map(str::trim).filter(|text| !text.is_empty())) {
+            (Some(target), Some(text)) => {
+                let mut message = ChatMessage::new("private", username, "私聊", text);
+                message.target = Some(target.to_string());
+                Input::Send(message)
+            }
+            _ => Input::Invalid("用法: /msg <用户名> <消息>".to_string()),
+        },
+        _ => Input::Send(ChatMessage::new("command", username, room, line)),
+    }
+}
+
+fn format_time(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|time| time.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+// 消息区中的一行
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Chat,
+    Own,
+    Private,
+    Webhook,
+    System,
+    Error,
+}
+
+struct ChatLine {
+    time: String,
+    kind: LineKind,
+    text: String,
+}
+
+impl ChatLine {
+    fn new(timestamp: u64, kind: LineKind, text: String) -> Self {
+        ChatLine { time: format_time(timestamp), kind, text }
+    }
+    
+    fn local(kind: LineKind, text: String) -> Self {
+        ChatLine::new(chrono::Utc::now().timestamp() as u64, kind, text)
+    }
+}
+
+// 把导出的聊天记录保存到当前目录，返回提示信息
+fn save_export(message: &ChatMessage) -> Option<String> {
+    let data = message.data.as_ref()?;
+    let filename = data["filename"].as_str()?;
+    let content = data["content"].as_str()?;
+    // 只取文件名部分，避免写到当前目录之外
+    let filename = std::path::Path::new(filename).file_name()?;
+    Some(match std::fs::write(filename, content) {
+        Ok(()) => format!("已保存到 {}", filename.to_string_lossy()),
+        Err(e) => format!("保存 {} 失败: {}", filename.to_string_lossy(), e),
+    })
+}
+
+// 把服务器消息转换为要显示的行，username 为自己的用户名
+fn describe(message: &ChatMessage, username: &str) -> Vec<ChatLine> {
+    match message.msg_type.as_str() {
+        "chat" if !message.text.is_empty() => {
+            let kind = if message.username == username { LineKind::Own } else { LineKind::Chat };
+            vec![ChatLine::new(message.timestamp, kind, format!("{}: {}", message.username, message.text))]
+        }
+        "private" if !message.text.is_empty() => {
+            let target = message.target.as_deref().unwrap_or_default();
+            vec![ChatLine::new(message.timestamp, LineKind::Private, format!("[私聊] {} → {}: {}", message.username, target, message.text))]
+        }
+        // 外部脚本通过入站Webhook发到房间的消息
+        "webhook" => vec![ChatLine::new(message.timestamp, LineKind::Webhook, format!("[机器人] {}: {}", message.username, message.text))],
+        "system" | "speedtest" | "heartbeat" => vec![ChatLine::new(message.timestamp, LineKind::System, message.text.clone())],
+        "ping" => vec![ChatLine::new(message.timestamp, LineKind::System, "收到服务器ping，已回复pong".to_string())],
+        "history" => message.data.as_ref()
+            .and_then(|data| data["messages"].as_array())
+            .map(|messages| {
+                messages.iter()
+                    .filter_map(|value| serde_json::from_value::<ChatMessage>(value.clone()).ok())
+                    .flat_map(|message| describe(&message, username))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        "export" => {
+            let mut lines = vec![ChatLine::new(message.timestamp, LineKind::System, message.text.clone())];
+            lines.extend(save_export(message).map(|text| ChatLine::local(LineKind::System, text)));
+            lines
+        }
+        _ => Vec::new(),
+    }
+}
+
+// 逐行模式：适合脚本与管道，标准输入结束后断开连接并退出
+async fn run_plain(
+    options: Options,
+    mut events: mpsc::UnboundedReceiver<Event>,
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+) {
+    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open = true;
+    let mut username = String::new();
+    let mut room = options.room.clone();
+    
+    loop {
+        tokio::select! {
+            line = stdin.next_line(), if stdin_open => {
+                let input = match line {
+                    Ok(Some(line)) => parse_input(&line, &username, &room),
+                    _ => Input::Quit,
+                };
+                match input {
+                    Input::Send(message) => {
+                        let _ = outgoing.send(Outgoing::Frame(message));
+                    }
+                    Input::Quit => {
+                        stdin_open = false;
+                        let _ = outgoing.send(Outgoing::Close);
+                    }
+                    Input::Invalid(text) => eprintln!("{}", text),
+                    Input::Empty => {}
+                }
+            }
+            
+            event = events.recv() => match event {
+                Some(Event::Connecting) => eprintln!("正在连接 {} ...", options.url),
+                Some(Event::Connected) => eprintln!("已连接 {}", options.url),
+                Some(Event::Disconnected { reason, retry_in }) => {
+                    eprintln!("连接断开: {}，{} 秒后重连", reason, retry_in.as_secs());
+                }
+                Some(Event::Rtt(_)) => {}
+                Some(Event::Frame(message)) => {
+                    if message.msg_type == "chat" && message.text.is_empty() {
+                        username = message.username.clone();
+                    }
+                    if message.msg_type == "userlist" {
+                        room = message.room.clone();
+                    }
+                    for line in describe(&message, &username) {
+                        println!("[{}] {}", line.time, line.text);
+                    }
+                }
+                Some(Event::Closed) | None => break,
+            },
+        }
+    }
+}
+
+// 用户列表中的一项，字段与服务器的 userlist 负载对应
+#[derive(serde::Deserialize, Clone)]
+struct UserEntry {
+    id: String,
+    username: String,
+    #[serde(default)]
+    role: String,
+    #[serde(default)]
+    presence: String,
+    rtt_ms: Option<u64>,
+}
+
+enum ConnectionState {
+    Connecting,
+    Connected,
+    Retrying(Duration), // 等待重连
+}
+
+struct App {
+    url: String,
+    username: String,
+    room: String,
+    state: ConnectionState,
+    rtt: Option<Duration>,
+    rooms: Vec<(String, usize)>,
+    users: Vec<UserEntry>,
+    lines: VecDeque<ChatLine>,
+    input: String,
+    hint: String,     // 命令补全的候选提示
+    scroll: u16,      // 距离消息区底部的行数
+    silent_rooms: usize, // 后台刷新房间列表发出、回复不显示的 /rooms 数量
+    commands: Vec<String>, // 服务器支持的命令名，从 /help 的回复中获取
+    silent_help: bool,     // 连接后自动发出的 /help，回复不显示
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+}
+
+impl App {
+    fn push(&mut self, line: ChatLine) {
+        self.lines.push_back(line);
+        if self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+        }
+    }
+    
+    fn send(&self, message: ChatMessage) {
+        let _ = self.outgoing.send(Outgoing::Frame(message));
+    }
+    
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Connecting => self.state = ConnectionState::Connecting,
+            Event::Connected => {
+                self.state = ConnectionState::Connected;
+                self.silent_rooms = 0;
+                self.silent_help = false;
+                self.push(ChatLine::local(LineKind::System, format!("已连接 {}", self.url)));
+            }
+            Event::Disconnected { reason, retry_in } => {
+                self.push(ChatLine::local(LineKind::Error, format!("连接断开: {}", reason)));
+                self.state = ConnectionState::Retrying(retry_in);
+                self.rtt = None;
+                self.users.clear();
+            }
+            Event::Rtt(rtt) => self.rtt = Some(rtt),
+            Event::Frame(message) => self.handle_frame(message),
+            Event::Closed => {}
+        }
+    }
+    
+    fn handle_frame(&mut self, message: ChatMessage) {
+        match message.msg_type.as_str() {
+            // 空文本的chat消息为服务器分配的用户名
+            "chat" if message.text.is_empty() => {
+                self.username = message.username.clone();
+                return;
+            }
+            "system" if message.text.starts_with("可用房间:") => {
+                self.rooms = message.text.lines().skip(1).filter_map(parse_room).collect();
+                if self.silent_rooms > 0 {
+                    self.silent_rooms -= 1;
+                    return;
+                }
+            }
+            "system" if message.text.starts_with("可用命令:") => {
+                self.commands = message.text.lines().skip(1).map(|line| command_name(line).to_string()).collect();
+                if std::mem::take(&mut self.silent_help) {
+                    return;
+                }
+            }
+            "userlist" => {
+                self.update_users(&message);
+                return;
+            }
+            _ => {}
+        }
+        for line in describe(&message, &self.username) {
+            self.push(line);
+        }
+    }
+    
+    fn update_users(&mut self, message: &ChatMessage) {
+        let Some(data) = &message.data else {
+            return;
+        };
+        let entry = || serde_json::from_value::<UserEntry>(data["user"].clone()).ok();
+        match data["op"].as_str() {
+            Some("snapshot") => {
+                if self.room != message.room {
+                    self.room = message.room.clone();
+                    // 切换房间后立即刷新房间列表中的人数
+                    self.refresh_rooms();
+                }
+                self.users = serde_json::from_value(data["users"].clone()).unwrap_or_default();
+            }
+            Some("join") => self.users.extend(entry()),
+            Some("update") => {
+                if let Some(entry) = entry() {
+                    match self.users.iter_mut().find(|user| user.id == entry.id) {
+                        Some(user) => *user = entry,
+                        None => self.users.push(entry),
+                    }
+                }
+            }
+            Some("leave") => {
+                let id = data["id"].as_str().unwrap_or_default();
+                self.users.retain(|user| user.id != id);
+            }
+            _ => {}
+        }
+    }
+    
+    fn refresh_rooms(&mut self) {
+        if matches!(self.state, ConnectionState::Connected) {
+            self.silent_rooms += 1;
+            self.send(ChatMessage::new("command", &self.username, &self.room, "/rooms"));
+        }
+    }
+    
+    // 连接后获取服务器的命令列表，用于补全
+    fn refresh_commands(&mut self) {
+        if matches!(self.state, ConnectionState::Connected) {
+            self.silent_help = true;
+            self.send(ChatMessage::new("command", &self.username, &self.room, "/help"));
+        }
+    }
+    
+    // Tab补全：第一个词补全命令名，/join 后补全房间名，/msg 后补全用户名
+    fn complete(&mut self) {
+        let words: Vec<&str> = self.input.split(' ').collect();
+        let (prefix, candidates): (String, Vec<String>) = match words.as_slice() {
+            [word] if word.starts_with('/') => (
+                String::new(),
+                self.commands.iter()
+                    .cloned()
+                    .chain(std::iter::once("/quit".to_string()))
+                    .collect(),
+            ),
+            ["/join", _] => ("/join ".to_string(), self.rooms.iter().map(|(name, _)| name.clone()).collect()),
+            ["/msg", _] => ("/msg ".to_string(), self.users.iter().map(|user| user.username.clone()).collect()),
+            _ => return,
+        };
+        
+        let partial = words.last().copied().unwrap_or_default();
+        let matches: Vec<&String> = candidates.iter().filter(|candidate| candidate.starts_with(partial)).collect();
+        match matches.as_slice() {
+            [] => self.hint = "没有匹配项".to_string(),
+            [only] => {
+                self.input = format!("{}{} ", prefix, only);
+                self.hint.clear();
+            }
+            _ => {
+                // 补全到所有候选的公共前缀
+                let mut common = matches[0].clone();
+                for candidate in &matches[1..] {
+                    while !candidate.starts_with(common.as_str()) {
+                        common.pop();
+                    }
+                }
+                self.input = format!("{}{}", prefix, common);
+                self.hint = matches.iter().map(|candidate| candidate.as_str()).collect::<Vec<_>>().join("  ");
+            }
+        }
+    }
+    
+    // 处理按键，返回 false 表示退出
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Esc => return false,
+            KeyCode::Tab => {
+                self.complete();
+                return true;
+            }
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut self.input);
+                self.scroll = 0;
+                match parse_input(&line, &self.username, &self.room) {
+                    Input::Send(message) => {
+                        if message.msg_type == "command" && message.text.trim() == "/rooms" {
+                            self.silent_rooms = 0;
+                        }
+                        self.send(message);
+                    }
+                    Input::Quit => return false,
+                    Input::Invalid(text) => self.push(ChatLine::local(LineKind::Error, text)),
+                    Input::Empty => {}
+                }
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Char(c) => self.input.push(c),
+            _ => {}
+        }
+        self.hint.clear();
+        true
+    }
+    
+    fn draw(&mut self, frame: &mut Frame) {
+        let [status_area, body_area, input_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(3),
+        ]).areas(frame.area());
+        let [rooms_area, messages_area, users_area] = Layout::horizontal([
+            Constraint::Length(20),
+            Constraint::Min(20),
+            Constraint::Length(26),
+        ]).areas(body_area);
+        
+        // 状态栏：连接状态、用户名、房间与RTT
+        let (state_text, state_color) = match &self.state {
+            ConnectionState::Connecting => ("连接中".to_string(), Color::Yellow),
+            ConnectionState::Connected => ("已连接".to_string(), Color::Green),
+            ConnectionState::Retrying(retry_in) => (format!("已断开，{} 秒后重连", retry_in.as_secs()), Color::Red),
+        };
+        let rtt = self.rtt.map(|rtt| format!("{:.1} ms", rtt.as_secs_f64() * 1000.0)).unwrap_or_else(|| "-".to_string());
+        let status = Line::from(vec![
+            Span::styled(format!(" {} ", state_text), Style::new().fg(Color::Black).bg(state_color)),
+            Span::raw(format!(" {}  用户: {}  房间: {}  RTT: {}", self.url, self.username, self.room, rtt)),
+        ]);
+        frame.render_widget(Paragraph::new(status), status_area);
+        
+        let rooms: Vec<ListItem> = self.rooms.iter()
+            .map(|(name, count)| {
+                let style = if *name == self.room { Style::new().add_modifier(Modifier::BOLD).fg(Color::Cyan) } else { Style::new() };
+                ListItem::new(Line::styled(format!("{} ({})", name, count), style))
+            })
+            .collect();
+        frame.render_widget(List::new(rooms).block(Block::bordered().title(" 房间 ")), rooms_area);
+        
+        let users: Vec<ListItem> = self.users.iter()
+            .map(|user| {
+                let mut text = user.username.clone();
+                if user.role == "admin" {
+                    text.push_str(" [管理员]");
+                }
+                if let Some(rtt_ms) = user.rtt_ms {
+                    text.push_str(&format!(" {}ms", rtt_ms));
+                }
+                let style = match user.presence.as_str() {
+                    "idle" => Style::new().fg(Color::DarkGray),
+                    _ if user.username == self.username => Style::new().fg(Color::Cyan),
+                    _ => Style::new(),
+                };
+                ListItem::new(Line::styled(text, style))
+            })
+            .collect();
+        let users_title = format!(" 用户 ({}) ", self.users.len());
+        frame.render_widget(List::new(users).block(Block::bordered().title(users_title)), users_area);
+        
+        let lines: Vec<Line> = self.lines.iter()
+            .map(|line| {
+                let style = match line.kind {
+                    LineKind::Chat => Style::new(),
+                    LineKind::Own => Style::new().fg(Color::Cyan),
+                    LineKind::Private => Style::new().fg(Color::Magenta),
+                    LineKind::Webhook => Style::new().fg(Color::Green),
+                    LineKind::System => Style::new().fg(Color::Yellow),
+                    LineKind::Error => Style::new().fg(Color::Red),
+                };
+                Line::from(vec![
+                    Span::styled(format!("{} ", line.time), Style::new().fg(Color::DarkGray)),
+                    Span::styled(line.text.clone(), style),
+                ])
+            })
+            .collect();
+        let title = if self.scroll > 0 { format!(" {} (已向上滚动 {} 行) ", self.room, self.scroll) } else { format!(" {} ", self.room) };
+        let messages = Paragraph::new(lines).wrap(Wrap { trim: false });
+        // 按折行后的总行数计算滚动位置，默认显示最新消息
+        let total = messages.line_count(messages_area.width.saturating_sub(2)) as u16;
+        let visible = messages_area.height.saturating_sub(2);
+        let max_scroll = total.saturating_sub(visible);
+        self.scroll = self.scroll.min(max_scroll);
+        frame.render_widget(
+            messages.block(Block::bordered().title(title)).scroll((max_scroll - self.scroll, 0)),
+            messages_area,
+        );
+        
+        let input_title = if self.hint.is_empty() {
+            " 输入消息，Tab 补全命令，Esc 退出 ".to_string()
+        } else {
+            format!(" {} ", self.hint)
+        };
+        frame.render_widget(Paragraph::new(self.input.as_str()).block(Block::bordered().title(input_title)), input_area);
+        frame.set_cursor_position((
+            input_area.x + 1 + Line::raw(self.input.as_str()).width() as u16,
+            input_area.y + 1,
+        ));
+    }
+}
+
+fn parse_room(line: &str) -> Option<(String, usize)> {
+    let (name, count) = line.trim().rsplit_once(" (")?;
+    let count = count.trim_end_matches(" 人在线)").parse().ok()?;
+    Some((name.to_string(), count))
+}
+
+async fn run_tui(
+    terminal: &mut DefaultTerminal,
+    options: Options,
+    mut events: mpsc::UnboundedReceiver<Event>,
+    outgoing: mpsc::UnboundedSender<Outgoing>,
+) -> std::io::Result<()> {
+    let mut app = App {
+        url: options.url,
+        username: String::new(),
+        room: options.room,
+        state: ConnectionState::Connecting,
+        rtt: None,
+        rooms: Vec::new(),
+        users: Vec::new(),
+        lines: VecDeque::new(),
+        input: String::new(),
+        hint: String::new(),
+        scroll: 0,
+        silent_rooms: 0,
+        commands: Vec::new(),
+        silent_help: false,
+        outgoing,
+    };
+    let mut keys = EventStream::new();
+    let mut rooms_interval = tokio::time::interval(ROOMS_INTERVAL);
+    
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        tokio::select! {
+            key = keys.next() => match key {
+                Some(Ok(TermEvent::Key(key))) if key.kind == KeyEventKind::Press => {
+                    if !app.handle_key(key) {
+                        break;
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e),
+                None => break,
+            },
+            
+            event = events.recv() => match event {
+                Some(Event::Connected) => {
+                    app.handle_event(Event::Connected);
+                    app.refresh_rooms();
+                    app.refresh_commands();
+                }
+                Some(event) => app.handle_event(event),
+                None => break,
+            },
+            
+            _ = rooms_interval.tick() => app.refresh_rooms(),
+        }
+    }
+    
+    let _ = app.outgoing.send(Outgoing::Close);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let options = parse_args();
+    
+    let (event_sender, events) = mpsc::unbounded_channel();
+    let (outgoing, outgoing_receiver) = mpsc::unbounded_channel();
+    let connection = tokio::spawn(connection(
+        options.url.clone(),
+        options.room.clone(),
+        event_sender,
+        outgoing_receiver,
+    ));
+    
+    if options.plain {
+        run_plain(options, events, outgoing).await;
+        return Ok(());
+    }
+    
+    let mut terminal = ratatui::init();
+    let result = run_tui(&mut terminal, options, events, outgoing).await;
+    ratatui::restore();
+    // 给连接任务一点时间发送关闭帧
+    let _ = tokio::time::timeout(Duration::from_millis(500), connection).await;
+    result
+}
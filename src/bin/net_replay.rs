@@ -0,0 +1,268 @@
+// 流量回放工具：把服务器录制的WebSocket会话（NET_APP_CAPTURE）按原始或加速的节奏重新发给一个服务器，
+// 用于复现问题或在课堂上演示协议交互；--dump 只把录制内容逐条打印出来，
+// --pcapng 把录制转换为pcapng文件，用Wireshark查看。
+//
+// 回放时每个录制的会话建立一条新连接，在原来的时刻发送客户端当时发出的帧。
+// 服务器重新分配的用户名与录制时不同，发送前把帧中的 username 与 target 换成对应会话的新用户名。
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use net_app::capture::{self, Kind, Opcode, Record};
+use net_app::clock::{Clock, ScaledClock};
+use net_app::pcap;
+use net_app::protocol::ChatMessage;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+const USAGE: &str = "用法: net_replay [--speed 倍数] [--dump] [--pcapng 输出文件 [--session 序号]] 录制文件 [WebSocket地址]\n\
+                     \x20 --speed S      回放速度，2 表示两倍速（默认 1）\n\
+                     \x20 --dump         只打印录制内容，不连接服务器\n\
+                     \x20 --pcapng FILE  把录制转换为pcapng文件，不连接服务器\n\
+                     \x20 --session N    转换时只导出序号为 N 的会话\n\
+                     默认回放到 ws://127.0.0.1:8080/ws";
+
+// 连接后等待服务器分配用户名的最长时间
+const INIT_TIMEOUT: Duration = Duration::from_secs(5);
+// 最后一条记录之后继续接收服务器消息的时间
+const GRACE: Duration = Duration::from_secs(1);
+
+type Sink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+struct Options {
+    path: PathBuf,
+    url: String,
+    speed: f64,
+    dump: bool,
+    pcapng: Option<PathBuf>,
+    session: Option<u32>,
+}
+
+fn exit_usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(2);
+}
+
+fn parse_args() -> Options {
+    let mut path = None;
+    let mut options = Options {
+        path: PathBuf::new(),
+        url: "ws://127.0.0.1:8080/ws".to_string(),
+        speed: 1.0,
+        dump: false,
+        pcapng: None,
+        session: None,
+    };
+    
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            "--speed" => {
+                options.speed = args.next()
+                    .and_then(|value| value.parse::<f64>().ok())
+                    .filter(|speed| *speed > 0.0)
+                    .unwrap_or_else(|| exit_usage());
+            }
+            "--dump" => options.dump = true,
+            "--pcapng" => options.pcapng = Some(PathBuf::from(args.next().unwrap_or_else(|| exit_usage()))),
+            "--session" => {
+                options.session = Some(args.next().and_then(|value| value.parse().ok()).unwrap_or_else(|| exit_usage()));
+            }
+            value if value.starts_with('-') => exit_usage(),
+            value if path.is_none() => path = Some(PathBuf::from(value)),
+            value => options.url = value.to_string(),
+        }
+    }
+    options.path = path.unwrap_or_else(|| exit_usage());
+    options
+}
+
+// 服务器连接后发送的初始化消息（文本为空的chat）中带有分配的用户名
+fn init_username(frame: &str) -> Option<String> {
+    let message: ChatMessage = serde_json::from_str(frame).ok()?;
+    (message.msg_type == "chat" && message.text.is_empty()).then_some(message.username)
+}
+
+fn dump(start_micros: i64, records: &[Record]) {
+    let start = chrono::DateTime::from_timestamp_micros(start_micros)
+        .map(|dt| dt.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default();
+    println!("录制开始于 {}，共 {} 条记录", start, records.len());
+    for record in records {
+        let time = format!("+{:>9.3}s", record.offset.as_secs_f64());
+        match record.kind {
+            Kind::Open => {
+                let info = record.session_info().unwrap();
+                match info.peer {
+                    Some(peer) => println!("{} #{} 打开会话 {} ({}, {})", time, record.session, info.id, info.client_addr, peer),
+                    None => println!("{} #{} 打开会话 {} ({})", time, record.session, info.id, info.client_addr),
+                }
+            }
+            Kind::Inbound => println!("{} #{} → {}", time, record.session, frame_summary(record)),
+            Kind::Outbound => println!("{} #{} ← {}", time, record.session, frame_summary(record)),
+            Kind::Close => println!("{} #{} 关闭会话", time, record.session),
+        }
+    }
+}
+
+// 文本帧打印内容，控制帧打印类型与负载长度
+fn frame_summary(record: &Record) -> String {
+    match record.opcode {
+        Opcode::Text => record.text().into_owned(),
+        opcode => format!("[{:?}] {} 字节", opcode, record.payload.len()),
+    }
+}
+
+// 把帧中录制时的用户名换成回放时的用户名，无法解析的帧原样发送
+fn rewrite(frame: &str, names: &HashMap<String, String>) -> String {
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(frame) else {
+        return frame.to_string();
+    };
+    for field in ["username", "target"] {
+        if let Some(new_name) = value[field].as_str().and_then(|name| names.get(name)) {
+            value[field] = serde_json::Value::String(new_name.clone());
+        }
+    }
+    value.to_string()
+}
+
+// 建立一个回放会话：等待服务器分配用户名，之后在后台统计收到的帧
+async fn open(url: &str, received: Arc<AtomicUsize>) -> Result<(Sink, String), String> {
+    let (socket, _) = tokio_tungstenite::connect_async(url).await.map_err(|e| e.to_string())?;
+    let (sink, mut stream) = socket.split();
+    let username = tokio::time::timeout(INIT_TIMEOUT, async {
+        while let Some(Ok(message)) = stream.next().await {
+            if let Message::Text(text) = message {
+                received.fetch_add(1, Ordering::Relaxed);
+                if let Some(username) = init_username(&text) {
+                    return Some(username);
+                }
+            }
+        }
+        None
+    }).await.ok().flatten().ok_or("服务器没有分配用户名")?;
+    
+    tokio::spawn(async move {
+        while let Some(Ok(message)) = stream.next().await {
+            if message.is_text() {
+                received.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+    Ok((sink, username))
+}
+
+async fn replay(options: &Options, records: &[Record]) {
+    // 录制时每个会话的用户名与服务器发出的帧数
+    let mut recorded_names: HashMap<u32, String> = HashMap::new();
+    let mut recorded_out: HashMap<u32, usize> = HashMap::new();
+    for record in records.iter().filter(|record| record.kind == Kind::Outbound && record.opcode == Opcode::Text) {
+        *recorded_out.entry(record.session).or_default() += 1;
+        if let (Entry::Vacant(entry), Some(username)) = (recorded_names.entry(record.session), init_username(&record.text())) {
+            entry.insert(username);
+        }
+    }
+    
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut sinks: HashMap<u32, Sink> = HashMap::new();
+    let mut received: Vec<(u32, Arc<AtomicUsize>)> = Vec::new();
+    let mut sent = 0;
+    
+    // 按倍速运行的时钟上的时刻与录制时的偏移一一对应
+    let clock = ScaledClock::new(options.speed);
+    let start = clock.now();
+    for record in records.iter().filter(|record| record.kind != Kind::Outbound) {
+        clock.sleep_until(start + record.offset).await;
+        match record.kind {
+            Kind::Open => {
+                let counter = Arc::new(AtomicUsize::new(0));
+                match open(&options.url, counter.clone()).await {
+                    Ok((sink, username)) => {
+                        eprintln!("会话 #{} 已连接，用户名 {}", record.session, username);
+                        if let Some(old_name) = recorded_names.get(&record.session) {
+                            names.insert(old_name.clone(), username);
+                        }
+                        sinks.insert(record.session, sink);
+                        received.push((record.session, counter));
+                    }
+                    Err(e) => eprintln!("会话 #{} 连接失败: {}", record.session, e),
+                }
+            }
+            Kind::Inbound => {
+                let Some(sink) = sinks.get_mut(&record.session) else {
+                    continue;
+                };
+                // 关闭帧由关闭会话的记录处理
+                let message = match record.opcode {
+                    Opcode::Text => Message::Text(rewrite(&record.text(), &names)),
+                    Opcode::Ping => Message::Ping(record.payload.clone()),
+                    Opcode::Pong => Message::Pong(record.payload.clone()),
+                    Opcode::Binary => Message::Binary(record.payload.clone()),
+                    Opcode::Close => continue,
+                };
+                if let Err(e) = sink.send(message).await {
+                    eprintln!("会话 #{} 发送失败: {}", record.session, e);
+                    sinks.remove(&record.session);
+                } else {
+                    sent += 1;
+                }
+            }
+            Kind::Close => {
+                if let Some(mut sink) = sinks.remove(&record.session) {
+                    let _ = sink.close().await;
+                }
+            }
+            Kind::Outbound => {}
+        }
+    }
+    
+    tokio::time::sleep(GRACE).await;
+    for (_, mut sink) in sinks.drain() {
+        let _ = sink.close().await;
+    }
+    
+    println!("回放完成：{} 个会话，发送 {} 帧", received.len(), sent);
+    for (session, counter) in received {
+        println!(
+            "会话 #{}: 录制时收到 {} 帧，回放时收到 {} 帧",
+            session, recorded_out.get(&session).copied().unwrap_or(0), counter.load(Ordering::Relaxed)
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let options = parse_args();
+    let (start_micros, records) = match capture::read(&options.path) {
+        Ok(capture) => capture,
+        Err(e) => {
+            eprintln!("无法读取录制文件 {}: {}", options.path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    
+    if let Some(output) = &options.pcapng {
+        let written = std::fs::File::create(output)
+            .and_then(|file| pcap::write_pcapng(std::io::BufWriter::new(file), start_micros, &records, options.session));
+        match written {
+            Ok(packets) => println!("已写入 {}，共 {} 个数据包", output.display(), packets),
+            Err(e) => {
+                eprintln!("无法写入 {}: {}", output.display(), e);
+                std::process::exit(1);
+            }
+        }
+    } else if options.dump {
+        dump(start_micros, &records);
+    } else {
+        replay(&options, &records).await;
+    }
+}
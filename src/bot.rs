@@ -0,0 +1,236 @@
+// 进程内机器人：每个机器人是一个传输方式为 Bot 的会话，像普通客户端一样出现在房间与用户列表中，
+// 通过通道接收服务器发给它的消息，回复则作为该会话发出的消息交给 handle_chat_message，与用户消息走同一条广播路径。
+// 机器人不参与心跳，只在被 /bot remove 移除时断开。管理员用 /bot 管理，启动时也可以通过 NET_APP_BOTS 加入
+use crate::commands::{Arg, Call, CommandSpec, Permission, Registry};
+use crate::{find_user_by_name, handle_chat_message, handle_disconnect, join_room, open_session};
+use crate::{AppState, ChatMessage, SessionSink, Transport, UserSession};
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// 机器人对一条消息的回应
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Reply {
+    Room(String),                        // 在机器人所在的房间发言
+    Private { to: String, text: String }, // 私聊指定用户
+    Reaction(String),                    // 对触发的消息添加表情回应
+}
+
+// 机器人只会收到其他用户在所在房间的聊天消息与发给它的私聊，自己与其他机器人的消息不会交给它
+pub trait Bot: Send + Sync {
+    // 机器人的用户名，同一时间只能有一个同名的会话
+    fn name(&self) -> &str;
+    fn on_message(&self, message: &ChatMessage) -> Vec<Reply>;
+}
+
+// 回声机器人：房间中以 @回声 开头的消息原样复述，私聊则原样回复，可作为测试私聊延迟与模拟路由的对端
+pub struct EchoBot;
+
+impl Bot for EchoBot {
+    fn name(&self) -> &str {
+        "回声"
+    }
+    
+    fn on_message(&self, message: &ChatMessage) -> Vec<Reply> {
+        if message.msg_type == "private" {
+            return vec![Reply::Private { to: message.username.clone(), text: message.text.clone() }];
+        }
+        match mention(message, self.name()) {
+            Some(text) if !text.is_empty() => vec![Reply::Room(format!("{}: {}", message.username, text))],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// 骰子机器人：@骰子 [NdM] 掷N个M面骰子（默认1d6），回复点数并给原消息加上 🎲
+pub struct DiceBot;
+
+impl Bot for DiceBot {
+    fn name(&self) -> &str {
+        "骰子"
+    }
+    
+    fn on_message(&self, message: &ChatMessage) -> Vec<Reply> {
+        let Some(spec) = mention(message, self.name()) else {
+            return Vec::new();
+        };
+        let spec = if spec.is_empty() { "1d6" } else { spec };
+        let parsed = spec.split_once('d')
+            .and_then(|(count, sides)| Some((if count.is_empty() { 1 } else { count.parse().ok()? }, sides.parse().ok()?)))
+            .filter(|(count, sides): &(u32, u32)| (1..=20).contains(count) && (2..=1000).contains(sides));
+        let Some((count, sides)) = parsed else {
+            return vec![Reply::Room(format!("{}: 用法 @{} [NdM]，例如 2d6，最多20个骰子、1000面", message.username, self.name()))];
+        };
+        let rolls: Vec<u32> = (0..count).map(|_| rand::random::<u32>() % sides + 1).collect();
+        let total: u32 = rolls.iter().sum();
+        let detail: Vec<String> = rolls.iter().map(u32::to_string).collect();
+        vec![
+            Reply::Reaction("🎲".to_string()),
+            Reply::Room(format!("{} 掷出 {}: {} = {}", message.username, spec, detail.join(" + "), total)),
+        ]
+    }
+}
+
+// 以 @名称 开头的房间消息，返回去掉提及后的文本
+fn mention<'a>(message: &'a ChatMessage, name: &str) -> Option<&'a str> {
+    if message.msg_type != "chat" {
+        return None;
+    }
+    message.text.strip_prefix('@')?.strip_prefix(name).map(str::trim)
+}
+
+// 按类型名创建内置机器人
+pub fn builtin(kind: &str) -> Option<Arc<dyn Bot>> {
+    match kind {
+        "echo" => Some(Arc::new(EchoBot)),
+        "dice" => Some(Arc::new(DiceBot)),
+        _ => None,
+    }
+}
+
+// 解析 NET_APP_BOTS 的值：逗号分隔的 类型@房间，省略房间时加入大厅，例如 echo@大厅,dice@书房
+pub fn parse_list(value: &str) -> Vec<(String, String)> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| match item.split_once('@') {
+            Some((kind, room)) => (kind.to_string(), room.to_string()),
+            None => (item.to_string(), "大厅".to_string()),
+        })
+        .collect()
+}
+
+// 登记机器人会话并加入房间，启动处理消息的任务，返回会话ID；已有同名会话时返回错误
+pub async fn spawn(app_state: Arc<AppState>, bot: Arc<dyn Bot>, room: &str) -> Result<String, String> {
+    let name = bot.name().to_string();
+    if find_user_by_name(&name, &app_state).is_some() {
+        return Err(format!("已有名为 {} 的用户", name));
+    }
+    
+    let id = Uuid::new_v4().to_string();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    // 地址按名称区分，避免同一地址的陈旧连接清理误删其他机器人
+    let mut user_session = UserSession::new(id.clone(), format!("bot/{}", name), SessionSink::Channel(sender), Transport::Bot, app_state.clock.as_ref());
+    user_session.username = name.clone();
+    open_session(&app_state, user_session, "bot").await;
+    if room != "大厅" {
+        join_room(&id, room, &app_state).await;
+    }
+    log::info!("Bot {} joined room {} as session {}", name, room, id);
+    
+    let bot_id = id.clone();
+    actix_web::rt::spawn(async move {
+        // 会话移除后通道关闭，任务随之结束
+        while let Some(json) = receiver.recv().await {
+            let Ok(message) = serde_json::from_str::<ChatMessage>(&json) else {
+                continue;
+            };
+            let addressed = match message.msg_type.as_str() {
+                "chat" => !message.text.is_empty(),
+                "private" => message.target.as_deref() == Some(name.as_str()),
+                _ => false,
+            };
+            if !addressed || message.username == name || is_bot(&app_state, &message.username) {
+                continue;
+            }
+            
+            for reply in bot.on_message(&message) {
+                let mut reply_msg = ChatMessage {
+                    msg_type: "chat".to_string(),
+                    username: name.clone(),
+                    room: message.room.clone(),
+                    text: String::new(),
+                    timestamp: app_state.clock.timestamp(),
+                    id: Uuid::new_v4().to_string(),
+                    target: None,
+                    data: None,
+                };
+                match reply {
+                    Reply::Room(text) => reply_msg.text = text,
+                    Reply::Private { to, text } => {
+                        reply_msg.msg_type = "private".to_string();
+                        reply_msg.target = Some(to);
+                        reply_msg.text = text;
+                    }
+                    Reply::Reaction(emoji) => {
+                        reply_msg.msg_type = "reaction".to_string();
+                        reply_msg.id = message.id.clone();
+                        reply_msg.text = emoji;
+                    }
+                }
+                if !handle_chat_message(reply_msg, &bot_id, &app_state).await {
+                    break;
+                }
+            }
+        }
+        log::info!("Bot {} stopped", name);
+    });
+    
+    Ok(id)
+}
+
+// 发送者是否为本节点的机器人，机器人之间不互相回应，避免循环
+fn is_bot(app_state: &AppState, username: &str) -> bool {
+    app_state.sessions.lock().unwrap().values().any(|user_session| user_session.username == username && user_session.transport == Transport::Bot)
+}
+
+// 本节点的机器人：(会话ID, 用户名, 房间)
+fn list(app_state: &AppState) -> Vec<(String, String, String)> {
+    let mut bots: Vec<(String, String, String)> = app_state.sessions.lock().unwrap().values()
+        .filter(|user_session| user_session.transport == Transport::Bot)
+        .map(|user_session| (user_session.id.clone(), user_session.username.clone(), user_session.room.clone()))
+        .collect();
+    bots.sort_by(|a, b| a.1.cmp(&b.1));
+    bots
+}
+
+// 注册 /bot 命令
+pub(crate) fn register(registry: &mut Registry) {
+    registry.add_fn(
+        CommandSpec {
+            name: "/bot",
+            args: &[Arg::Required("list|add|remove"), Arg::Optional("类型或名称"), Arg::Optional("房间")],
+            help: "管理进程内机器人（echo、dice）",
+            permission: Permission::Admin,
+        },
+        bot_command,
+    );
+}
+
+fn bot_command(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        match (call.args[0], call.args.get(1)) {
+            ("list", _) => {
+                let bots = list(call.app_state);
+                if bots.is_empty() {
+                    return "当前没有机器人".to_string();
+                }
+                let lines: Vec<String> = bots.iter().map(|(_, name, room)| format!("{} (房间 {})", name, room)).collect();
+                format!("机器人:\n{}", lines.join("\n"))
+            }
+            ("add", Some(kind)) => {
+                let Some(bot) = builtin(kind) else {
+                    return format!("未知的机器人类型: {}，可用类型: echo、dice", kind);
+                };
+                let room = match call.args.get(2) {
+                    Some(room) => room.to_string(),
+                    None => call.room().unwrap_or_else(|| "大厅".to_string()),
+                };
+                let name = bot.name().to_string();
+                match spawn(call.app_state.clone(), bot, &room).await {
+                    Ok(_) => format!("机器人 {} 已加入房间 {}", name, room),
+                    Err(e) => e,
+                }
+            }
+            ("remove", Some(name)) => {
+                let Some((id, _, _)) = list(call.app_state).into_iter().find(|(_, bot_name, _)| bot_name == name) else {
+                    return format!("没有名为 {} 的机器人", name);
+                };
+                handle_disconnect(&id, call.app_state).await;
+                format!("已移除机器人 {}", name)
+            }
+            _ => "用法: /bot list | /bot add <echo|dice> [房间] | /bot remove <名称>".to_string(),
+        }
+    })
+}
@@ -0,0 +1,259 @@
+// WebSocket会话的流量录制。录制文件为紧凑的二进制格式：
+// 文件头为魔数与录制开始的Unix时间（微秒），之后每条记录依次为
+// 相对开始的微秒数(u64)、记录类型(u8)、会话序号(u32)、负载长度(u32)与负载，整数均为小端序。
+// 记录类型的低4位为方向，高4位为WebSocket帧类型（文本帧为0）。
+// 会话ID只在打开会话的记录中出现一次，之后的记录用序号引用
+use crate::clock::Clock;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 8] = b"NETCAP1\n";
+
+// 记录类型
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Open,     // 新会话，负载为 "会话ID\t客户端地址\t对端套接字地址\t本地套接字地址"
+    Inbound,  // 客户端发来的帧
+    Outbound, // 服务器发出的帧
+    Close,    // 会话结束，负载为空
+}
+
+impl Kind {
+    fn code(self) -> u8 {
+        match self {
+            Kind::Open => 0,
+            Kind::Inbound => 1,
+            Kind::Outbound => 2,
+            Kind::Close => 3,
+        }
+    }
+    
+    fn from_code(code: u8) -> Option<Kind> {
+        match code {
+            0 => Some(Kind::Open),
+            1 => Some(Kind::Inbound),
+            2 => Some(Kind::Outbound),
+            3 => Some(Kind::Close),
+            _ => None,
+        }
+    }
+}
+
+// 帧类型。控制帧的负载与WebSocket线路格式相同，关闭帧为2字节关闭码加原因
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Opcode {
+    Text,
+    Ping,
+    Pong,
+    Close,
+    Binary,
+}
+
+impl Opcode {
+    fn code(self) -> u8 {
+        match self {
+            Opcode::Text => 0,
+            Opcode::Ping => 1,
+            Opcode::Pong => 2,
+            Opcode::Close => 3,
+            Opcode::Binary => 4,
+        }
+    }
+    
+    // 协议监视流与流量统计中使用的名称
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Opcode::Text => "text",
+            Opcode::Ping => "ping",
+            Opcode::Pong => "pong",
+            Opcode::Close => "close",
+            Opcode::Binary => "binary",
+        }
+    }
+    
+    fn from_code(code: u8) -> Option<Opcode> {
+        match code {
+            0 => Some(Opcode::Text),
+            1 => Some(Opcode::Ping),
+            2 => Some(Opcode::Pong),
+            3 => Some(Opcode::Close),
+            4 => Some(Opcode::Binary),
+            _ => None,
+        }
+    }
+}
+
+// 打开会话记录中的连接信息。较早的录制文件没有套接字地址
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    pub id: String,
+    pub client_addr: String,
+    pub peer: Option<SocketAddr>,
+    pub local: Option<SocketAddr>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub offset: Duration, // 相对录制开始的时间
+    pub kind: Kind,
+    pub opcode: Opcode,
+    pub session: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Record {
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.payload)
+    }
+    
+    // 打开会话记录中的连接信息
+    pub fn session_info(&self) -> Option<SessionInfo> {
+        if self.kind != Kind::Open {
+            return None;
+        }
+        let text = self.text();
+        let mut fields = text.split('\t');
+        Some(SessionInfo {
+            id: fields.next()?.to_string(),
+            client_addr: fields.next().unwrap_or_default().to_string(),
+            peer: fields.next().and_then(|addr| addr.parse().ok()),
+            local: fields.next().and_then(|addr| addr.parse().ok()),
+        })
+    }
+}
+
+struct RecorderState {
+    writer: BufWriter<File>,
+    sessions: HashMap<String, u32>, // 会话ID -> 序号，只包含录制中的会话
+    next_session: u32,
+    failed: bool, // 写入失败后停止录制，只记录一次错误
+}
+
+// 录制器：由 AppState 持有，记录WebSocket会话收发的每一帧
+pub struct Recorder {
+    start: Instant,
+    state: Mutex<RecorderState>,
+}
+
+impl Recorder {
+    // 创建录制文件并写入文件头，录制时间以给定时钟为准
+    pub fn create(path: &Path, clock: &dyn Clock) -> io::Result<Recorder> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&clock.utc().timestamp_micros().to_le_bytes())?;
+        writer.flush()?;
+        Ok(Recorder {
+            start: clock.now(),
+            state: Mutex::new(RecorderState {
+                writer,
+                sessions: HashMap::new(),
+                next_session: 0,
+                failed: false,
+            }),
+        })
+    }
+    
+    // peer 与 local 为TCP连接两端的套接字地址，导出pcapng时用于合成TCP/IP头
+    pub(crate) fn open(&self, clock: &dyn Clock, id: &str, client_addr: &str, peer: Option<SocketAddr>, local: Option<SocketAddr>) {
+        let mut state = self.state.lock().unwrap();
+        let session = state.next_session;
+        state.next_session += 1;
+        state.sessions.insert(id.to_string(), session);
+        let format_addr = |addr: Option<SocketAddr>| addr.map(|addr| addr.to_string()).unwrap_or_default();
+        let payload = format!("{}\t{}\t{}\t{}", id, client_addr, format_addr(peer), format_addr(local));
+        self.write(&mut state, clock, Kind::Open, Opcode::Text, session, payload.as_bytes());
+    }
+    
+    pub(crate) fn inbound(&self, clock: &dyn Clock, id: &str, opcode: Opcode, payload: &[u8]) {
+        self.frame(clock, Kind::Inbound, opcode, id, payload);
+    }
+    
+    // 不在录制中的会话（如其他传输方式）直接忽略
+    pub(crate) fn outbound(&self, clock: &dyn Clock, id: &str, opcode: Opcode, payload: &[u8]) {
+        self.frame(clock, Kind::Outbound, opcode, id, payload);
+    }
+    
+    pub(crate) fn close(&self, clock: &dyn Clock, id: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(session) = state.sessions.remove(id) {
+            self.write(&mut state, clock, Kind::Close, Opcode::Text, session, &[]);
+        }
+    }
+    
+    fn frame(&self, clock: &dyn Clock, kind: Kind, opcode: Opcode, id: &str, payload: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(&session) = state.sessions.get(id) {
+            self.write(&mut state, clock, kind, opcode, session, payload);
+        }
+    }
+    
+    // 每条记录写入后立即刷新，服务器异常退出时录制文件仍然完整
+    fn write(&self, state: &mut RecorderState, clock: &dyn Clock, kind: Kind, opcode: Opcode, session: u32, payload: &[u8]) {
+        if state.failed {
+            return;
+        }
+        let offset = clock.elapsed(self.start).as_micros() as u64;
+        let result = (|| {
+            state.writer.write_all(&offset.to_le_bytes())?;
+            state.writer.write_all(&[kind.code() | opcode.code() << 4])?;
+            state.writer.write_all(&session.to_le_bytes())?;
+            state.writer.write_all(&(payload.len() as u32).to_le_bytes())?;
+            state.writer.write_all(payload)?;
+            state.writer.flush()
+        })();
+        if let Err(e) = result {
+            log::error!("Failed to write capture record, recording stopped: {:?}", e);
+            state.failed = true;
+        }
+    }
+}
+
+// 读取录制文件，返回录制开始的Unix时间（微秒）与全部记录。
+// 文件末尾不完整的记录（如录制时服务器被强制结束）会被忽略
+pub fn read(path: &Path) -> io::Result<(i64, Vec<Record>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a capture file"));
+    }
+    let mut start = [0u8; 8];
+    reader.read_exact(&mut start)?;
+    
+    let mut records = Vec::new();
+    let mut header = [0u8; 17];
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        let offset = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let (kind, opcode) = match (Kind::from_code(header[8] & 0x0f), Opcode::from_code(header[8] >> 4)) {
+            (Some(kind), Some(opcode)) => (kind, opcode),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown record type {}", header[8]))),
+        };
+        let session = u32::from_le_bytes(header[9..13].try_into().unwrap());
+        let len = u32::from_le_bytes(header[13..17].try_into().unwrap()) as usize;
+        let mut payload = vec![0u8; len];
+        match reader.read_exact(&mut payload) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        records.push(Record {
+            offset: Duration::from_micros(offset),
+            kind,
+            opcode,
+            session,
+            payload,
+        });
+    }
+    Ok((i64::from_le_bytes(start), records))
+}
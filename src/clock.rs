@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// 会话计时与消息时间戳使用的时钟。服务器默认使用系统时钟，
+// 测试可以换成手动推进的虚拟时钟，回放工具可以换成加速运行的时钟
+pub trait Clock: Send + Sync {
+    // 单调时间，用于心跳、超时、空闲判断与RTT
+    fn now(&self) -> Instant;
+    
+    // 墙上时间，用于消息时间戳
+    fn utc(&self) -> DateTime<Utc>;
+    
+    // 等待到本时钟上的指定时刻
+    fn sleep_until(&self, deadline: Instant) -> Sleep;
+    
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+    
+    // 消息时间戳（Unix时间，秒）
+    fn timestamp(&self) -> u64 {
+        self.utc().timestamp() as u64
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    
+    fn utc(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+    
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+// 只在调用 advance 时前进的虚拟时钟，从创建时的真实时间开始
+pub struct ManualClock {
+    start: Instant,
+    start_utc: DateTime<Utc>,
+    state: Mutex<ManualState>,
+}
+
+struct ManualState {
+    offset: Duration,
+    waiters: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        ManualClock {
+            start: Instant::now(),
+            start_utc: Utc::now(),
+            state: Mutex::new(ManualState { offset: Duration::ZERO, waiters: Vec::new() }),
+        }
+    }
+    
+    // 时钟前进指定时长，唤醒所有到期的等待
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.offset += duration;
+        let now = self.start + state.offset;
+        let (due, waiting) = std::mem::take(&mut state.waiters).into_iter()
+            .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+        state.waiters = waiting;
+        drop(state);
+        for (_, waiter) in due {
+            let _ = waiter.send(());
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + self.state.lock().unwrap().offset
+    }
+    
+    fn utc(&self) -> DateTime<Utc> {
+        self.start_utc + self.state.lock().unwrap().offset
+    }
+    
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let mut state = self.state.lock().unwrap();
+        if deadline <= self.start + state.offset {
+            return Box::pin(std::future::ready(()));
+        }
+        // 顺便清理已被取消的等待（如 select! 中未被选中的分支）
+        state.waiters.retain(|(_, waiter)| !waiter.is_closed());
+        let (sender, receiver) = oneshot::channel();
+        state.waiters.push((deadline, sender));
+        Box::pin(async move {
+            let _ = receiver.await;
+        })
+    }
+}
+
+// 按固定倍数加速（或减速）运行的时钟，从创建时的真实时间开始
+pub struct ScaledClock {
+    start: Instant,
+    start_utc: DateTime<Utc>,
+    factor: f64,
+}
+
+impl ScaledClock {
+    pub fn new(factor: f64) -> Self {
+        assert!(factor > 0.0, "时钟倍速必须为正数");
+        ScaledClock { start: Instant::now(), start_utc: Utc::now(), factor }
+    }
+    
+    fn scaled_elapsed(&self) -> Duration {
+        self.start.elapsed().mul_f64(self.factor)
+    }
+}
+
+impl Clock for ScaledClock {
+    fn now(&self) -> Instant {
+        self.start + self.scaled_elapsed()
+    }
+    
+    fn utc(&self) -> DateTime<Utc> {
+        self.start_utc + self.scaled_elapsed()
+    }
+    
+    fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let real = deadline.saturating_duration_since(self.now()).div_f64(self.factor);
+        Box::pin(tokio::time::sleep(real))
+    }
+}
+
+// 按时钟定期触发，第一次立即触发。错过的触发不补发，下一次从当前时刻重新计算
+pub struct Ticker {
+    clock: Arc<dyn Clock>,
+    period: Duration,
+    next: Instant,
+}
+
+impl Ticker {
+    pub fn new(clock: Arc<dyn Clock>, period: Duration) -> Self {
+        let next = clock.now();
+        Ticker { clock, period, next }
+    }
+    
+    // 可以安全地用在 select! 中：被取消时不改变下一次触发的时刻
+    pub async fn tick(&mut self) {
+        self.clock.sleep_until(self.next).await;
+        let now = self.clock.now();
+        self.next += self.period;
+        if self.next <= now {
+            self.next = now + self.period;
+        }
+    }
+}
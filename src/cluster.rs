@@ -0,0 +1,523 @@
+// 多实例集群：节点之间通过TCP长连接（每行一个JSON帧）交换在线用户与房间目录，
+// 并把房间广播和发给远端用户的消息转发到对方节点，各节点的用户因此可以互相聊天。
+// 设置共享口令后，握手时双方用HMAC对对方的随机数签名，不知道口令的连接会被拒绝；
+// 未设置口令时任何能连上集群端口的程序都可以加入，集群端口只能开放在可信的网络中
+use crate::{trace_outbound, traffic, deliver_to_room, deliver_user_list_diff, webhook, AppState, ChatMessage, Ticker, UserListEntry, UserSession};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use uuid::Uuid;
+
+// 定期发送完整的用户目录，同时作为节点间的心跳
+const GOSSIP_INTERVAL: Duration = Duration::from_secs(5);
+// 超过该时间没有收到对方的任何帧，视为节点故障
+const PEER_TIMEOUT: Duration = Duration::from_secs(15);
+// 连接失败或断开后重新连接的间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+// 单帧的最大长度（字节），完整目录可能较大
+const MAX_LINE: usize = 1 << 20;
+
+// 远端节点上的一个会话：所在房间与用户列表信息（含地址，由本节点决定是否展示）
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct RemoteUser {
+    room: String,
+    #[serde(flatten)]
+    entry: UserListEntry,
+}
+
+impl RemoteUser {
+    fn new(user_session: &UserSession) -> Self {
+        RemoteUser {
+            room: user_session.room.clone(),
+            entry: UserListEntry::new(user_session, true),
+        }
+    }
+}
+
+// 节点间的帧
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Frame {
+    Hello { node: String, nonce: String },
+    Auth { proof: String }, // 用共享口令对对方随机数的签名，未设置口令时为空
+    Snapshot { users: Vec<RemoteUser> },
+    Upsert { user: RemoteUser },
+    Remove { id: String },
+    Room { room: String, message: ChatMessage },
+    User { id: String, message: ChatMessage },
+}
+
+impl Frame {
+    fn line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap();
+        line.push('\n');
+        line
+    }
+}
+
+// 已连接的节点
+struct Peer {
+    link_id: String,
+    initiator: String, // 发起连接的节点，两个节点互相连接时按它决定保留哪条连接
+    sender: mpsc::UnboundedSender<String>,
+    users: HashMap<String, RemoteUser>, // 会话ID -> 会话
+}
+
+// 集群状态：本节点ID与各节点的用户目录。锁在会话、房间与历史之后获取
+pub struct Cluster {
+    node_id: String,
+    secret: Option<String>, // 节点间共享的口令
+    peers: Mutex<HashMap<String, Peer>>,
+    shutdown: watch::Sender<bool>,
+}
+
+// 远端用户目录的变化，转换为本节点用户列表的增量
+enum Change {
+    Join(RemoteUser),
+    Update(RemoteUser),
+    Leave(String, String), // (房间, 会话ID)
+}
+
+impl Cluster {
+    pub(crate) fn new(node_id: String) -> Self {
+        Cluster {
+            node_id,
+            secret: None,
+            peers: Mutex::new(HashMap::new()),
+            shutdown: watch::channel(false).0,
+        }
+    }
+    
+    pub(crate) fn node_id(&self) -> &str {
+        &self.node_id
+    }
+    
+    pub(crate) fn set_secret(&mut self, secret: String) {
+        self.secret = Some(secret);
+    }
+    
+    // 签名内容包括签名方的节点ID，对方不能把本节点的签名原样发回来冒充本节点
+    fn proof(&self, nonce: &str, node: &str) -> String {
+        match &self.secret {
+            Some(secret) => {
+                let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+                webhook::hex(hmac::sign(&key, format!("{}:{}", nonce, node).as_bytes()).as_ref())
+            }
+            None => String::new(),
+        }
+    }
+    
+    // 验证对方对本节点随机数的签名，未设置口令时不验证
+    fn verify(&self, nonce: &str, node: &str, proof: &str) -> bool {
+        let Some(secret) = &self.secret else {
+            return true;
+        };
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        unhex(proof).is_some_and(|tag| hmac::verify(&key, format!("{}:{}", nonce, node).as_bytes(), &tag).is_ok())
+    }
+    
+    fn broadcast(&self, frame: &Frame) {
+        let peers = self.peers.lock().unwrap();
+        if peers.is_empty() {
+            return;
+        }
+        let line = frame.line();
+        for peer in peers.values() {
+            let _ = peer.sender.send(line.clone());
+        }
+    }
+    
+    // 本节点会话的信息或所在房间发生变化
+    pub(crate) fn publish_session(&self, user_session: &UserSession) {
+        self.broadcast(&Frame::Upsert { user: RemoteUser::new(user_session) });
+    }
+    
+    // 本节点会话已断开
+    pub(crate) fn publish_removal(&self, id: &str) {
+        self.broadcast(&Frame::Remove { id: id.to_string() });
+    }
+    
+    // 转发房间广播给该房间有成员的节点，返回转发的节点数
+    pub(crate) fn forward_room(&self, room: &str, message: &ChatMessage) -> usize {
+        let peers = self.peers.lock().unwrap();
+        let targets: Vec<&Peer> = peers.values()
+            .filter(|peer| peer.users.values().any(|user| user.room == room))
+            .collect();
+        if !targets.is_empty() {
+            let line = Frame::Room { room: room.to_string(), message: message.clone() }.line();
+            for peer in &targets {
+                let _ = peer.sender.send(line.clone());
+            }
+        }
+        targets.len()
+    }
+    
+    // 转发给远端节点上的会话，该会话不在任何节点上时返回false
+    pub(crate) fn forward_user(&self, id: &str, message: &ChatMessage) -> bool {
+        let peers = self.peers.lock().unwrap();
+        let Some(peer) = peers.values().find(|peer| peer.users.contains_key(id)) else {
+            return false;
+        };
+        let _ = peer.sender.send(Frame::User { id: id.to_string(), message: message.clone() }.line());
+        true
+    }
+    
+    pub(crate) fn find_user_by_name(&self, username: &str) -> Option<String> {
+        let peers = self.peers.lock().unwrap();
+        peers.values()
+            .flat_map(|peer| peer.users.values())
+            .find(|user| user.entry.username == username)
+            .map(|user| user.entry.id.clone())
+    }
+    
+    // 远端会话所在的房间
+    pub(crate) fn user_room(&self, id: &str) -> Option<String> {
+        let peers = self.peers.lock().unwrap();
+        peers.values().find_map(|peer| peer.users.get(id)).map(|user| user.room.clone())
+    }
+    
+    // 远端节点上位于指定房间的成员
+    pub(crate) fn room_users(&self, room: &str) -> Vec<UserListEntry> {
+        let peers = self.peers.lock().unwrap();
+        peers.values()
+            .flat_map(|peer| peer.users.values())
+            .filter(|user| user.room == room)
+            .map(|user| user.entry.clone())
+            .collect()
+    }
+    
+    // 远端节点上各房间的在线人数
+    pub(crate) fn room_counts(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for user in self.peers.lock().unwrap().values().flat_map(|peer| peer.users.values()) {
+            *counts.entry(user.room.clone()).or_default() += 1;
+        }
+        counts
+    }
+    
+    // 已连接的节点及其会话数
+    pub(crate) fn peer_summary(&self) -> Vec<(String, usize)> {
+        let mut summary: Vec<(String, usize)> = self.peers.lock().unwrap().iter()
+            .map(|(node, peer)| (node.clone(), peer.users.len()))
+            .collect();
+        summary.sort();
+        summary
+    }
+    
+    // 登记新建立的连接。与同一节点已有连接时，两端都保留由ID较小的节点发起的那条；
+    // 同一节点重新发起的连接替换旧连接（旧连接可能已失效但尚未超时）。返回是否登记成功
+    fn register(&self, node: &str, link_id: &str, initiator: &str, sender: mpsc::UnboundedSender<String>) -> bool {
+        let mut peers = self.peers.lock().unwrap();
+        let users = match peers.remove(node) {
+            Some(existing) if existing.initiator.as_str() < initiator => {
+                peers.insert(node.to_string(), existing);
+                return false;
+            }
+            Some(existing) => existing.users,
+            None => HashMap::new(),
+        };
+        peers.insert(node.to_string(), Peer {
+            link_id: link_id.to_string(),
+            initiator: initiator.to_string(),
+            sender,
+            users,
+        });
+        true
+    }
+    
+    // 连接结束时移除节点，返回该节点上的会话。连接已被替换时返回None
+    fn unregister(&self, node: &str, link_id: &str) -> Option<Vec<RemoteUser>> {
+        let mut peers = self.peers.lock().unwrap();
+        if peers.get(node)?.link_id != link_id {
+            return None;
+        }
+        peers.remove(node).map(|peer| peer.users.into_values().collect())
+    }
+    
+    fn is_connected(&self, node: &str) -> bool {
+        self.peers.lock().unwrap().contains_key(node)
+    }
+    
+    // 更新某个节点的用户目录。full 为真时 users 是完整目录，未出现的会话视为已离开
+    fn apply(&self, node: &str, users: Vec<RemoteUser>, full: bool) -> Vec<Change> {
+        let mut peers = self.peers.lock().unwrap();
+        let Some(peer) = peers.get_mut(node) else {
+            return Vec::new();
+        };
+        
+        let mut changes = Vec::new();
+        let mut previous = if full { std::mem::take(&mut peer.users) } else { HashMap::new() };
+        for user in users {
+            let old = previous.remove(&user.entry.id).or_else(|| peer.users.remove(&user.entry.id));
+            match old {
+                None => changes.push(Change::Join(user.clone())),
+                Some(old) if old.room != user.room => {
+                    changes.push(Change::Leave(old.room, old.entry.id));
+                    changes.push(Change::Join(user.clone()));
+                }
+                Some(old) if old != user => changes.push(Change::Update(user.clone())),
+                Some(_) => {}
+            }
+            peer.users.insert(user.entry.id.clone(), user);
+        }
+        for (id, old) in previous {
+            changes.push(Change::Leave(old.room, id));
+        }
+        changes
+    }
+    
+    fn remove(&self, node: &str, id: &str) -> Option<RemoteUser> {
+        self.peers.lock().unwrap().get_mut(node)?.users.remove(id)
+    }
+}
+
+// 本节点全部会话的完整目录
+fn snapshot(app_state: &AppState) -> Frame {
+    let sessions = app_state.sessions.lock().unwrap();
+    Frame::Snapshot {
+        users: sessions.values().map(RemoteUser::new).collect(),
+    }
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| text.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect()
+}
+
+// 已连接的节点ID
+pub fn nodes(app_state: &AppState) -> Vec<String> {
+    app_state.cluster.peer_summary().into_iter().map(|(node, _)| node).collect()
+}
+
+// 退出集群：断开所有节点连接并停止监听与重连，其他节点会把本节点的会话从用户列表中移除
+pub fn shutdown(app_state: &AppState) {
+    app_state.cluster.shutdown.send_replace(true);
+    app_state.cluster.peers.lock().unwrap().clear();
+}
+
+// 接受其他节点的连接
+pub async fn listen(listener: TcpListener, app_state: Arc<AppState>) {
+    if let Ok(addr) = listener.local_addr() {
+        log::info!("Cluster node {} listening on {}", app_state.cluster.node_id, addr);
+    }
+    if app_state.cluster.secret.is_none() {
+        log::warn!("No cluster secret configured; any host that can reach the cluster port can join, keep it on a trusted network");
+    }
+    let mut shutdown = app_state.cluster.shutdown.subscribe();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    log::info!("Incoming cluster connection from {}", peer);
+                    actix_web::rt::spawn(run_link(stream, false, app_state.clone()));
+                }
+                Err(e) => log::error!("Failed to accept cluster connection: {:?}", e),
+            },
+            _ = shutdown.wait_for(|stopped| *stopped) => return,
+        }
+    }
+}
+
+// 连接配置的节点地址，断开后自动重连
+pub async fn dial(addr: String, app_state: Arc<AppState>) {
+    let mut shutdown = app_state.cluster.shutdown.subscribe();
+    let mut known_node: Option<String> = None;
+    loop {
+        // 已经通过对方发起的连接互联时不再重复连接
+        let linked = known_node.as_deref().is_some_and(|node| app_state.cluster.is_connected(node));
+        if !linked {
+            match TcpStream::connect(&addr).await {
+                Ok(stream) => {
+                    if let Some(node) = run_link(stream, true, app_state.clone()).await {
+                        known_node = Some(node);
+                    }
+                }
+                Err(e) => log::debug!("Failed to connect to cluster peer {}: {:?}", addr, e),
+            }
+        }
+        
+        let retry_at = app_state.clock.now() + RETRY_INTERVAL;
+        tokio::select! {
+            _ = app_state.clock.sleep_until(retry_at) => {}
+            _ = shutdown.wait_for(|stopped| *stopped) => return,
+        }
+    }
+}
+
+// 读取一帧。被取消时已读到的部分保留在line中；连接关闭返回None
+async fn read_frame(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>, line: &mut Vec<u8>) -> std::io::Result<Option<Frame>> {
+    loop {
+        let mut limited = (&mut *reader).take((MAX_LINE - line.len()) as u64);
+        if limited.read_until(b'\n', line).await? == 0 {
+            return Ok(None);
+        }
+        if line.last() == Some(&b'\n') {
+            let frame = serde_json::from_slice(line).map_err(std::io::Error::from);
+            line.clear();
+            return frame.map(Some);
+        }
+        if line.len() >= MAX_LINE {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "cluster frame too long"));
+        }
+    }
+}
+
+// 处理一条节点间连接，返回对方的节点ID（握手失败时为None）
+async fn run_link(stream: TcpStream, dialed: bool, app_state: Arc<AppState>) -> Option<String> {
+    let cluster = &app_state.cluster;
+    let peer_addr = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    
+    // 握手：双方互相发送节点ID与随机数，再各自发送对对方随机数的签名
+    let nonce = Uuid::new_v4().simple().to_string();
+    let hello = Frame::Hello { node: cluster.node_id.clone(), nonce: nonce.clone() }.line();
+    if let Err(e) = writer.write_all(hello.as_bytes()).await {
+        log::warn!("Cluster handshake with {} failed: {:?}", peer_addr, e);
+        return None;
+    }
+    let (node, peer_nonce) = match tokio::time::timeout(HELLO_TIMEOUT, read_frame(&mut reader, &mut line)).await {
+        Ok(Ok(Some(Frame::Hello { node, nonce }))) => (node, nonce),
+        result => {
+            log::warn!("Cluster handshake with {} failed: {:?}", peer_addr, result.map(|read| read.map(|_| ())));
+            return None;
+        }
+    };
+    if node == cluster.node_id {
+        log::error!("Cluster peer {} has the same node id {} as this node", peer_addr, node);
+        return None;
+    }
+    let auth = Frame::Auth { proof: cluster.proof(&peer_nonce, &cluster.node_id) }.line();
+    if let Err(e) = writer.write_all(auth.as_bytes()).await {
+        log::warn!("Cluster handshake with {} failed: {:?}", peer_addr, e);
+        return None;
+    }
+    let proof = match tokio::time::timeout(HELLO_TIMEOUT, read_frame(&mut reader, &mut line)).await {
+        Ok(Ok(Some(Frame::Auth { proof }))) => proof,
+        result => {
+            log::warn!("Cluster handshake with {} failed: {:?}", peer_addr, result.map(|read| read.map(|_| ())));
+            return None;
+        }
+    };
+    if !cluster.verify(&nonce, &node, &proof) {
+        log::warn!("Cluster peer {} at {} failed authentication, check NET_APP_CLUSTER_SECRET", node, peer_addr);
+        return None;
+    }
+    
+    // 登记前先放入完整目录，对方连接后立即获得本节点的全部会话
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    let _ = sender.send(snapshot(&app_state).line());
+    let link_id = Uuid::new_v4().to_string();
+    let initiator = if dialed { cluster.node_id.clone() } else { node.clone() };
+    if !cluster.register(&node, &link_id, &initiator, sender) {
+        log::debug!("Already linked with cluster node {}, dropping duplicate connection from {}", node, peer_addr);
+        return Some(node);
+    }
+    log::info!("Linked with cluster node {} at {}", node, peer_addr);
+    
+    let mut gossip = Ticker::new(app_state.clock.clone(), GOSSIP_INTERVAL);
+    let mut shutdown = cluster.shutdown.subscribe();
+    let mut last_seen = app_state.clock.now();
+    loop {
+        tokio::select! {
+            read = read_frame(&mut reader, &mut line) => {
+                match read {
+                    Ok(Some(frame)) => {
+                        last_seen = app_state.clock.now();
+                        handle_frame(frame, &node, &app_state).await;
+                    }
+                    Ok(None) => {
+                        log::info!("Cluster node {} closed the connection", node);
+                        break;
+                    }
+                    Err(e) => {
+                        log::warn!("Cluster link with {} failed: {:?}", node, e);
+                        break;
+                    }
+                }
+            }
+            
+            // 通道关闭说明连接已被替换或本节点退出集群
+            outgoing = receiver.recv() => {
+                let Some(outgoing) = outgoing else {
+                    break;
+                };
+                if let Err(e) = writer.write_all(outgoing.as_bytes()).await {
+                    log::warn!("Cluster write to {} failed: {:?}", node, e);
+                    break;
+                }
+            }
+            
+            _ = gossip.tick() => {
+                if app_state.clock.elapsed(last_seen) > PEER_TIMEOUT {
+                    log::warn!("Cluster node {} timed out", node);
+                    break;
+                }
+                if let Err(e) = writer.write_all(snapshot(&app_state).line().as_bytes()).await {
+                    log::warn!("Cluster write to {} failed: {:?}", node, e);
+                    break;
+                }
+            }
+            
+            _ = shutdown.wait_for(|stopped| *stopped) => break,
+        }
+    }
+    let _ = writer.shutdown().await;
+    
+    // 节点断开或故障，把它的会话从本节点的用户列表中移除
+    if let Some(users) = cluster.unregister(&node, &link_id) {
+        log::info!("Cluster node {} left, removing {} remote sessions", node, users.len());
+        for user in users {
+            deliver_user_list_diff(&app_state, &user.room, &user.entry.id, "leave", None).await;
+        }
+    }
+    Some(node)
+}
+
+async fn handle_frame(frame: Frame, node: &str, app_state: &Arc<AppState>) {
+    let changes = match frame {
+        Frame::Hello { .. } | Frame::Auth { .. } => return,
+        Frame::Snapshot { users } => app_state.cluster.apply(node, users, true),
+        Frame::Upsert { user } => app_state.cluster.apply(node, vec![user], false),
+        Frame::Remove { id } => match app_state.cluster.remove(node, &id) {
+            Some(user) => vec![Change::Leave(user.room, id)],
+            None => Vec::new(),
+        },
+        Frame::Room { room, message } => {
+            deliver_to_room(app_state, &room, serde_json::to_string(&message).unwrap()).await;
+            return;
+        }
+        Frame::User { id, message } => {
+            // 只投递给本节点的会话，避免在节点之间来回转发
+            let session = app_state.sessions.lock().unwrap()
+                .get(&id)
+                .map(|user_session| user_session.session.clone());
+            if let Some(mut session) = session {
+                let message_json = serde_json::to_string(&message).unwrap();
+                trace_outbound(app_state, &id, &message_json);
+                if let Err(e) = session.text(message_json).await {
+                    log::error!("Error sending forwarded message to {}: {:?}", id, e);
+                    traffic::record_send_error(app_state, &id);
+                }
+            }
+            return;
+        }
+    };
+    
+    for change in changes {
+        match change {
+            Change::Join(user) => deliver_user_list_diff(app_state, &user.room, &user.entry.id, "join", Some(user.entry.clone())).await,
+            Change::Update(user) => deliver_user_list_diff(app_state, &user.room, &user.entry.id, "update", Some(user.entry.clone())).await,
+            Change::Leave(room, id) => deliver_user_list_diff(app_state, &room, &id, "leave", None).await,
+        }
+    }
+}
@@ -0,0 +1,523 @@
+// 斜杠命令注册表：每个命令声明名称、参数、说明与所需权限，由各模块在 Registry::builtin 中注册，
+// 也可以通过 AppState::with_command 注册自定义命令。/help 根据注册表生成，参数不足与权限不够时由注册表统一回复
+use crate::{handle_chat_message, join_room, send_message_to_user, send_user_list, send_user_list_diff};
+use crate::export::{self, ExportFormat};
+use crate::search::{self, SearchPage, SearchQuery, Viewer};
+use crate::{AppState, ChatMessage, Role, UserListDiff};
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+use uuid::Uuid;
+
+// 执行命令需要的权限
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Permission {
+    User,
+    Admin,
+}
+
+// 参数的说明，用于生成用法并检查必填参数的个数
+#[derive(Clone, Copy, Debug)]
+pub enum Arg {
+    Required(&'static str),
+    Optional(&'static str),
+}
+
+// 命令的声明
+#[derive(Clone, Copy, Debug)]
+pub struct CommandSpec {
+    pub name: &'static str, // 包括开头的斜杠
+    pub args: &'static [Arg],
+    pub help: &'static str,
+    pub permission: Permission,
+}
+
+impl CommandSpec {
+    // 用法，必填参数写作 <参数>，可选参数写作 [参数]
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.to_string();
+        for arg in self.args {
+            match arg {
+                Arg::Required(name) => usage.push_str(&format!(" <{}>", name)),
+                Arg::Optional(name) => usage.push_str(&format!(" [{}]", name)),
+            }
+        }
+        usage
+    }
+}
+
+// 一次命令调用：发出命令的会话与命令名之后的参数
+pub struct Call<'a> {
+    pub app_state: &'a Arc<AppState>,
+    pub user_id: &'a str,
+    pub args: &'a [&'a str],
+}
+
+impl Call<'_> {
+    pub fn username(&self) -> Option<String> {
+        self.app_state.sessions.lock().unwrap().get(self.user_id).map(|user_session| user_session.username.clone())
+    }
+    
+    pub fn room(&self) -> Option<String> {
+        self.app_state.sessions.lock().unwrap().get(self.user_id).map(|user_session| user_session.room.clone())
+    }
+    
+    pub fn is_admin(&self) -> bool {
+        self.app_state.sessions.lock().unwrap().get(self.user_id).is_some_and(|user_session| user_session.role == Role::Admin)
+    }
+}
+
+// 斜杠命令。run 返回回复文本，返回空字符串表示已自行发送回复（或不需要回复）
+pub trait Command: Send + Sync {
+    fn spec(&self) -> &CommandSpec;
+    fn run<'a>(&'a self, call: Call<'a>) -> BoxFuture<'a, String>;
+}
+
+type Handler = for<'a> fn(Call<'a>) -> BoxFuture<'a, String>;
+
+// 由函数实现的命令，内置命令都使用这种形式
+struct FnCommand {
+    spec: CommandSpec,
+    handler: Handler,
+}
+
+impl Command for FnCommand {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+    
+    fn run<'a>(&'a self, call: Call<'a>) -> BoxFuture<'a, String> {
+        (self.handler)(call)
+    }
+}
+
+// 已注册的命令，按注册顺序显示在 /help 中
+#[derive(Default)]
+pub struct Registry {
+    commands: Vec<Box<dyn Command>>,
+}
+
+impl Registry {
+    // 服务器的全部内置命令
+    pub(crate) fn builtin() -> Registry {
+        let mut registry = Registry::default();
+        registry.add_fn(CommandSpec { name: "/help", args: &[Arg::Optional("命令")], help: "显示帮助", permission: Permission::User }, help);
+        registry.add_fn(CommandSpec { name: "/rooms", args: &[], help: "显示所有房间", permission: Permission::User }, rooms);
+        registry.add_fn(CommandSpec { name: "/join", args: &[Arg::Required("房间名")], help: "加入指定房间", permission: Permission::User }, join);
+        registry.add_fn(CommandSpec { name: "/users", args: &[], help: "显示当前房间用户", permission: Permission::User }, users);
+        registry.add_fn(CommandSpec { name: "/msg", args: &[Arg::Required("用户名"), Arg::Required("消息")], help: "发送私聊消息", permission: Permission::User }, private);
+        registry.add_fn(CommandSpec { name: "/ping", args: &[], help: "测试网络连接", permission: Permission::User }, ping);
+        crate::heartbeat::register(&mut registry);
+        crate::speedtest::register(&mut registry);
+        crate::subnet::register(&mut registry);
+        registry.add_fn(
+            CommandSpec { name: "/stats", args: &[Arg::Optional("me|<用户名>")], help: "显示网络统计信息；me 显示本会话的流量统计，管理员可查看其他用户", permission: Permission::User },
+            stats,
+        );
+        registry.add_fn(
+            CommandSpec {
+                name: "/search",
+                args: &[Arg::Required("关键词"), Arg::Optional("房间"), Arg::Optional("from:用户"), Arg::Optional("before:日期"), Arg::Optional("after:日期"), Arg::Optional("page:页码")],
+                help: "搜索历史消息",
+                permission: Permission::User,
+            },
+            search,
+        );
+        registry.add_fn(
+            CommandSpec {
+                name: "/export",
+                args: &[Arg::Optional("json|csv|html|md"), Arg::Optional("房间"), Arg::Optional("after:日期"), Arg::Optional("before:日期")],
+                help: "导出聊天记录",
+                permission: Permission::User,
+            },
+            export,
+        );
+        registry.add_fn(CommandSpec { name: "/admin", args: &[Arg::Required("口令")], help: "获取管理员权限", permission: Permission::User }, admin);
+        crate::bot::register(&mut registry);
+        crate::incoming::register(&mut registry);
+        registry
+    }
+    
+    // 注册命令，与已有命令同名时替换原来的命令
+    pub fn add(&mut self, command: Box<dyn Command>) {
+        let name = command.spec().name;
+        match self.commands.iter_mut().find(|existing| existing.spec().name == name) {
+            Some(existing) => *existing = command,
+            None => self.commands.push(command),
+        }
+    }
+    
+    pub(crate) fn add_fn(&mut self, spec: CommandSpec, handler: Handler) {
+        self.add(Box::new(FnCommand { spec, handler }));
+    }
+    
+    pub fn get(&self, name: &str) -> Option<&dyn Command> {
+        self.commands.iter().find(|command| command.spec().name == name).map(Box::as_ref)
+    }
+    
+    pub fn specs(&self) -> impl Iterator<Item = &CommandSpec> {
+        self.commands.iter().map(|command| command.spec())
+    }
+    
+    // 执行一行命令：查找命令，检查权限与必填参数后调用
+    pub(crate) async fn dispatch(&self, line: &str, user_id: &str, app_state: &Arc<AppState>) -> String {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let Some(name) = parts.first() else {
+            return "请输入有效命令".to_string();
+        };
+        let Some(command) = self.get(name) else {
+            return format!("未知命令: {}", line);
+        };
+        let spec = command.spec();
+        let call = Call { app_state, user_id, args: &parts[1..] };
+        
+        if spec.permission == Permission::Admin && !call.is_admin() {
+            log::warn!("Session {} tried admin command {} without permission", user_id, spec.name);
+            return format!("只有管理员可以使用 {}", spec.name);
+        }
+        let required = spec.args.iter().filter(|arg| matches!(arg, Arg::Required(_))).count();
+        if call.args.len() < required {
+            return format!("用法: {}", spec.usage());
+        }
+        command.run(call).await
+    }
+}
+
+fn help(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        let registry = &call.app_state.commands;
+        // /help <命令> 显示单个命令的用法
+        if let Some(name) = call.args.first() {
+            let name = if name.starts_with('/') { name.to_string() } else { format!("/{}", name) };
+            return match registry.get(&name) {
+                Some(command) => {
+                    let spec = command.spec();
+                    let permission = if spec.permission == Permission::Admin { "\n需要管理员权限" } else { "" };
+                    format!("用法: {}\n{}{}", spec.usage(), spec.help, permission)
+                }
+                None => format!("未知命令: {}", name),
+            };
+        }
+        
+        let lines: Vec<String> = registry.specs()
+            .map(|spec| {
+                let permission = if spec.permission == Permission::Admin { "（管理员）" } else { "" };
+                format!("{} - {}{}", spec.usage(), spec.help, permission)
+            })
+            .collect();
+        format!("可用命令:\n{}", lines.join("\n"))
+    })
+}
+
+fn rooms(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        // 合并其他节点上的房间与人数
+        let app_state = call.app_state;
+        let mut counts = app_state.cluster.room_counts();
+        for (name, user_ids) in app_state.rooms.lock().unwrap().iter() {
+            *counts.entry(name.clone()).or_default() += user_ids.len();
+        }
+        let room_list: Vec<String> = counts.iter()
+            .map(|(name, count)| format!("{} ({} 人在线)", name, count))
+            .collect();
+        format!("可用房间: \n{}", room_list.join("\n"))
+    })
+}
+
+fn join(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        // 加入房间后服务器会发送新房间的用户列表与历史消息
+        join_room(call.user_id, call.args[0], call.app_state).await;
+        "".to_string()
+    })
+}
+
+fn users(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        let app_state = call.app_state;
+        let mut user_count = 0;
+        let mut user_list = Vec::new();
+        
+        let sessions = app_state.sessions.lock().unwrap();
+        if let Some(user_session) = sessions.get(call.user_id) {
+            let room = &user_session.room;
+            let is_admin = user_session.role == Role::Admin;
+            let rooms = app_state.rooms.lock().unwrap();
+            
+            if let Some(user_ids) = rooms.get(room) {
+                user_count = user_ids.len();
+                for uid in user_ids {
+                    if let Some(u_session) = sessions.get(uid) {
+                        // IP地址仅对管理员可见
+                        if is_admin {
+                            user_list.push(format!("{} ({})", u_session.username, u_session.addr));
+                        } else {
+                            user_list.push(u_session.username.clone());
+                        }
+                    }
+                }
+            }
+            
+            // 其他节点上同一房间的成员
+            for entry in app_state.cluster.room_users(room) {
+                user_count += 1;
+                match (is_admin, &entry.addr) {
+                    (true, Some(addr)) => user_list.push(format!("{} ({})", entry.username, addr)),
+                    _ => user_list.push(entry.username),
+                }
+            }
+        }
+        
+        format!("当前房间有 {} 名用户:\n{}", user_count, user_list.join("\n"))
+    })
+}
+
+fn private(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        // 与客户端直接发送的私聊消息走同一条路径
+        let Some(username) = call.username() else {
+            return "".to_string();
+        };
+        let message = ChatMessage {
+            msg_type: "private".to_string(),
+            username,
+            room: "".to_string(),
+            text: call.args[1..].join(" "),
+            timestamp: call.app_state.clock.timestamp(),
+            id: Uuid::new_v4().to_string(),
+            target: Some(call.args[0].to_string()),
+            data: None,
+        };
+        handle_chat_message(message, call.user_id, call.app_state).await;
+        "".to_string()
+    })
+}
+
+fn ping(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        // 直接发送ping消息，而不是返回文本
+        let app_state = call.app_state;
+        let ping_msg = ChatMessage {
+            msg_type: "ping".to_string(),
+            username: "服务器".to_string(),
+            room: "".to_string(),
+            text: app_state.clock.utc().timestamp_micros().to_string(),
+            timestamp: app_state.clock.timestamp(),
+            id: Uuid::new_v4().to_string(),
+            target: None,
+            data: None,
+        };
+        
+        send_message_to_user(&ping_msg, call.user_id, app_state).await;
+        
+        // 返回空字符串，因为ping消息已经直接发送
+        "".to_string()
+    })
+}
+
+fn stats(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        let app_state = call.app_state;
+        let sessions = app_state.sessions.lock().unwrap();
+        
+        // 单个会话的流量统计：/stats me 查看自己，管理员可以用用户名或会话ID查看其他用户
+        if let Some(target) = call.args.first() {
+            let Some(current) = sessions.get(call.user_id) else {
+                return "".to_string();
+            };
+            let target_session = if *target == "me" {
+                Some(current)
+            } else if current.role != Role::Admin {
+                return "只有管理员可以查看其他用户的流量统计".to_string();
+            } else {
+                sessions.values().find(|user_session| user_session.username == *target || user_session.id == *target)
+            };
+            return match target_session {
+                Some(user_session) => user_session.traffic.report(&user_session.username, user_session.transport, app_state.clock.elapsed(user_session.join_time)),
+                None => format!("用户 {} 不在线或不在本节点", target),
+            };
+        }
+        
+        let mut stats = format!(
+            "网络统计信息:\n\
+             总连接数: {}\n\
+             总房间数: {}",
+            sessions.len(),
+            app_state.rooms.lock().unwrap().len()
+        );
+        
+        // 按传输方式统计连接数
+        let mut by_transport: Vec<(&str, usize)> = Vec::new();
+        for user_session in sessions.values() {
+            let name = user_session.transport.name();
+            match by_transport.iter_mut().find(|(transport, _)| *transport == name) {
+                Some((_, count)) => *count += 1,
+                None => by_transport.push((name, 1)),
+            }
+        }
+        by_transport.sort();
+        for (transport, count) in by_transport {
+            stats.push_str(&format!("\n{} 连接: {}", transport, count));
+        }
+        
+        // 各心跳模式的发送与超时次数
+        for line in app_state.heartbeat_stats.summary() {
+            stats.push_str(&format!("\n{}", line));
+        }
+        
+        // 集群中已连接的节点
+        let peers = app_state.cluster.peer_summary();
+        if !peers.is_empty() {
+            stats.push_str(&format!("\n集群节点: {} (本节点)", app_state.cluster.node_id()));
+            for (node, count) in peers {
+                stats.push_str(&format!("\n节点 {}: {} 个会话", node, count));
+            }
+        }
+        
+        // 数据报客户端的序号统计
+        for user_session in sessions.values() {
+            if let Some(tracker) = &user_session.sequence {
+                let seq = tracker.stats;
+                stats.push_str(&format!(
+                    "\n{} ({} {}): 收到 {}，丢失 {}，重复 {}，乱序 {}",
+                    user_session.username, user_session.transport.name(), user_session.addr,
+                    seq.received, seq.lost, seq.duplicates, seq.reordered
+                ));
+            }
+        }
+        stats
+    })
+}
+
+fn search(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        let is_admin = match call.app_state.sessions.lock().unwrap().get(call.user_id) {
+            Some(user_session) => user_session.role == Role::Admin,
+            None => return "".to_string(),
+        };
+        
+        let query = {
+            let rooms = call.app_state.rooms.lock().unwrap();
+            let history = call.app_state.history.lock().unwrap();
+            SearchQuery::parse_command(call.args, |name| rooms.contains_key(name) || history.has_room(name))
+        };
+        let query = match query {
+            Ok(query) if !query.is_empty() => query,
+            Ok(_) => return "用法: /search <关键词> [房间] [from:用户] [before:日期] [after:日期] [page:页码]".to_string(),
+            Err(e) => return e,
+        };
+        
+        let viewer = if is_admin { Viewer::Admin } else { Viewer::Session(call.user_id) };
+        let page = call.app_state.history.lock().unwrap().search(&query, viewer);
+        format_search_page(&page)
+    })
+}
+
+fn export(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        let current_room = match call.app_state.sessions.lock().unwrap().get(call.user_id) {
+            Some(user_session) => user_session.room.clone(),
+            None => return "".to_string(),
+        };
+        
+        let mut format = ExportFormat::Html;
+        let mut room = current_room.clone();
+        let mut after = None;
+        let mut before = None;
+        for arg in call.args {
+            if let Some(value) = arg.strip_prefix("after:") {
+                match search::parse_date(value) {
+                    Some(timestamp) => after = Some(timestamp),
+                    None => return format!("无法识别的日期: {}", value),
+                }
+            } else if let Some(value) = arg.strip_prefix("before:") {
+                match search::parse_date(value) {
+                    Some(timestamp) => before = Some(timestamp),
+                    None => return format!("无法识别的日期: {}", value),
+                }
+            } else if let Some(parsed) = ExportFormat::parse(arg) {
+                format = parsed;
+            } else {
+                room = arg.to_string();
+            }
+        }
+        
+        // 私聊只导出本会话参与的部分，管理员也不例外
+        let messages = call.app_state.history.lock().unwrap().transcript(&room, after, before, Viewer::Session(call.user_id));
+        if messages.is_empty() {
+            return format!("房间 {} 在指定时间范围内没有聊天记录", room);
+        }
+        
+        // 文件内容直接随消息发送，由客户端保存为下载文件
+        let export_msg = ChatMessage {
+            msg_type: "export".to_string(),
+            username: "服务器".to_string(),
+            room: current_room,
+            text: format!("已导出房间 {} 的 {} 条记录", room, messages.len()),
+            timestamp: call.app_state.clock.timestamp(),
+            id: Uuid::new_v4().to_string(),
+            target: None,
+            data: Some(serde_json::json!({
+                "filename": export::filename(&room, format),
+                "content_type": format.content_type(),
+                "content": export::render(format, &room, &messages, call.app_state.clock.timestamp()),
+            })),
+        };
+        
+        send_message_to_user(&export_msg, call.user_id, call.app_state).await;
+        "".to_string()
+    })
+}
+
+// 将搜索结果格式化为命令回复文本
+fn format_search_page(page: &SearchPage) -> String {
+    if page.total == 0 {
+        return "没有找到匹配的消息".to_string();
+    }
+    
+    let pages = page.total.div_ceil(page.per_page);
+    let lines: Vec<String> = page.results.iter()
+        .map(|message| {
+            let time = chrono::DateTime::from_timestamp(message.timestamp as i64, 0)
+                .map(|dt| dt.with_timezone(&chrono::Local).format("%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            let place = match &message.target {
+                Some(target) if message.msg_type == "private" => format!("私聊 {}→{}", message.username, target),
+                _ => message.room.clone(),
+            };
+            format!("[{}] [{}] {}: {}", time, place, message.username, message.text)
+        })
+        .collect();
+    
+    format!("找到 {} 条消息（第 {}/{} 页）:\n{}", page.total, page.page, pages, lines.join("\n"))
+}
+
+fn admin(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        let app_state = call.app_state;
+        let user_id = call.user_id;
+        let Some(admin_token) = &app_state.admin_token else {
+            return "服务器未配置管理员口令".to_string();
+        };
+        if call.args[0] != admin_token.as_str() {
+            log::warn!("Rejected admin login attempt from {}", user_id);
+            return "管理员口令错误".to_string();
+        }
+        
+        let room = {
+            let mut sessions = app_state.sessions.lock().unwrap();
+            match sessions.get_mut(user_id) {
+                Some(user_session) => {
+                    user_session.role = Role::Admin;
+                    user_session.room.clone()
+                }
+                None => return "".to_string(),
+            }
+        };
+        log::info!("Session {} granted admin role", user_id);
+        
+        // 通知房间成员角色变化，并向新管理员发送带地址的完整列表
+        send_user_list_diff(app_state, &room, UserListDiff::Update(user_id.to_string())).await;
+        send_user_list(app_state, &room, user_id).await;
+        "已获得管理员权限".to_string()
+    })
+}
@@ -0,0 +1,161 @@
+use crate::AppState;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+// 发现协议使用的组播组与端口，探测同时发往组播组和本网段广播地址
+const GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 42, 99);
+const PORT: u16 = 45454;
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+const SERVICE: &str = "net_app";
+
+// 发现报文。服务器定期组播 announce，收到 probe 时单播回复 announce
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Packet {
+    Announce(Announcement),
+    Probe { service: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Announcement {
+    pub service: String,
+    pub name: String,
+    pub url: String,             // 网页客户端地址
+    pub ws: String,              // WebSocket地址
+    pub tls_url: Option<String>, // 启用TLS时的HTTPS地址
+    pub rooms: usize,
+    pub users: usize,
+    pub version: String,
+}
+
+// 服务器名称：NET_APP_NAME，未设置时使用主机名
+fn server_name() -> String {
+    std::env::var("NET_APP_NAME").ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "计算机网络实验服务器".to_string())
+}
+
+fn announcement(app_state: &AppState, name: &str, host: &str, http_port: u16, tls_port: Option<u16>) -> Announcement {
+    Announcement {
+        service: SERVICE.to_string(),
+        name: name.to_string(),
+        url: format!("http://{}:{}/", host, http_port),
+        ws: format!("ws://{}:{}/ws", host, http_port),
+        tls_url: tls_port.map(|port| format!("https://{}:{}/", host, port)),
+        rooms: app_state.rooms.lock().unwrap().len(),
+        users: app_state.sessions.lock().unwrap().len(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    }
+}
+
+// 定期在组播组上宣告服务器，并回复客户端的发现探测
+pub async fn run(app_state: Arc<AppState>, http_port: u16, tls_port: Option<u16>) {
+    let socket = match UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, PORT)).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::error!("Failed to bind discovery socket on port {}: {:?}", PORT, e);
+            return;
+        }
+    };
+    if let Err(e) = socket.join_multicast_v4(GROUP, Ipv4Addr::UNSPECIFIED) {
+        log::warn!("Failed to join discovery multicast group {}: {:?}, only broadcast probes will be answered", GROUP, e);
+    }
+    
+    let name = server_name();
+    let host = crate::tls::lan_ip().unwrap_or_else(|| "127.0.0.1".to_string());
+    log::info!("Announcing server \"{}\" at {} on {}:{}", name, host, GROUP, PORT);
+    
+    let mut announce_interval = actix_web::rt::time::interval(ANNOUNCE_INTERVAL);
+    let mut buf = [0u8; 2048];
+    
+    loop {
+        tokio::select! {
+            _ = announce_interval.tick() => {
+                let packet = Packet::Announce(announcement(&app_state, &name, &host, http_port, tls_port));
+                let payload = serde_json::to_vec(&packet).unwrap();
+                if let Err(e) = socket.send_to(&payload, SocketAddrV4::new(GROUP, PORT)).await {
+                    log::debug!("Failed to send discovery announcement: {:?}", e);
+                }
+            }
+            
+            received = socket.recv_from(&mut buf) => {
+                let (len, peer) = match received {
+                    Ok(received) => received,
+                    Err(e) => {
+                        log::debug!("Discovery receive error: {:?}", e);
+                        continue;
+                    }
+                };
+                // 只回复探测，其他服务器的宣告直接忽略
+                let Ok(Packet::Probe { service }) = serde_json::from_slice::<Packet>(&buf[..len]) else {
+                    continue;
+                };
+                if service != SERVICE {
+                    continue;
+                }
+                
+                log::info!("Answering discovery probe from {}", peer);
+                let packet = Packet::Announce(announcement(&app_state, &name, &host, http_port, tls_port));
+                if let Err(e) = socket.send_to(&serde_json::to_vec(&packet).unwrap(), peer).await {
+                    log::debug!("Failed to answer discovery probe from {}: {:?}", peer, e);
+                }
+            }
+        }
+    }
+}
+
+// 向组播组和广播地址发送探测，收集在超时前回复的服务器
+pub async fn discover(timeout: Duration) -> std::io::Result<Vec<(SocketAddr, Announcement)>> {
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+    
+    let probe = serde_json::to_vec(&Packet::Probe { service: SERVICE.to_string() }).unwrap();
+    socket.send_to(&probe, SocketAddrV4::new(GROUP, PORT)).await?;
+    if let Err(e) = socket.send_to(&probe, SocketAddrV4::new(Ipv4Addr::BROADCAST, PORT)).await {
+        log::debug!("Broadcast discovery probe failed: {:?}", e);
+    }
+    
+    let mut servers = Vec::new();
+    let mut seen = HashSet::new();
+    let mut buf = [0u8; 2048];
+    let deadline = tokio::time::Instant::now() + timeout;
+    // 同一服务器可能同时收到组播和广播探测，按地址去重
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (len, peer) = received?;
+        if let Ok(Packet::Announce(announcement)) = serde_json::from_slice::<Packet>(&buf[..len]) {
+            if announcement.service == SERVICE && seen.insert(announcement.url.clone()) {
+                servers.push((peer, announcement));
+            }
+        }
+    }
+    Ok(servers)
+}
+
+// 命令行发现工具：net_app discover [超时秒数]
+pub async fn discover_cli(args: &[String]) -> std::io::Result<()> {
+    let seconds = args.first().and_then(|value| value.parse().ok()).unwrap_or(2);
+    println!("正在局域网中查找服务器（{} 秒）...", seconds);
+    
+    let servers = discover(Duration::from_secs(seconds)).await?;
+    if servers.is_empty() {
+        println!("没有找到服务器。请确认服务器已启动，且防火墙允许UDP端口 {}", PORT);
+        return Ok(());
+    }
+    for (peer, server) in servers {
+        println!("{} ({})", server.name, peer.ip());
+        println!("  网页: {}", server.url);
+        println!("  WebSocket: {}", server.ws);
+        if let Some(tls_url) = &server.tls_url {
+            println!("  HTTPS: {}", tls_url);
+        }
+        println!("  房间 {} 个，在线用户 {} 人，版本 {}", server.rooms, server.users, server.version);
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use crate::ChatMessage;
+
+// 聊天记录导出格式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Html,
+    Markdown,
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Option<ExportFormat> {
+        match value.to_lowercase().as_str() {
+            "json" => Some(ExportFormat::Json),
+            "csv" => Some(ExportFormat::Csv),
+            "html" | "htm" => Some(ExportFormat::Html),
+            "md" | "markdown" => Some(ExportFormat::Markdown),
+            _ => None,
+        }
+    }
+    
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Html => "html",
+            ExportFormat::Markdown => "md",
+        }
+    }
+    
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json; charset=utf-8",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Html => "text/html; charset=utf-8",
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+}
+
+// 导出文件名，如 大厅-20250101-1200.html
+pub fn filename(room: &str, format: ExportFormat) -> String {
+    format!("{}-{}.{}", room, chrono::Local::now().format("%Y%m%d-%H%M"), format.extension())
+}
+
+fn format_time(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|dt| dt.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+// 消息的发送者描述，私聊消息显示为 发送方→接收方
+fn sender(message: &ChatMessage) -> String {
+    match &message.target {
+        Some(target) if message.msg_type == "private" => format!("{}→{}", message.username, target),
+        _ => message.username.clone(),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// 转义Markdown中有特殊含义的字符，换行保留为硬换行
+fn markdown_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped.replace('\n', "  \n")
+}
+
+// 按指定格式渲染房间的聊天记录
+pub fn render(format: ExportFormat, room: &str, messages: &[ChatMessage], exported_at: u64) -> String {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_string_pretty(&serde_json::json!({
+                "room": room,
+                "exported_at": exported_at,
+                "messages": messages,
+            })).unwrap()
+        }
+        ExportFormat::Csv => {
+            let mut out = String::from("time,timestamp,type,username,target,text\r\n");
+            for message in messages {
+                let fields = [
+                    format_time(message.timestamp),
+                    message.timestamp.to_string(),
+                    message.msg_type.clone(),
+                    message.username.clone(),
+                    message.target.clone().unwrap_or_default(),
+                    message.text.clone(),
+                ];
+                let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                out.push_str(&line.join(","));
+                out.push_str("\r\n");
+            }
+            out
+        }
+        ExportFormat::Html => {
+            let mut out = format!(
+                "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n<title>{} 聊天记录</title>\n\
+                 <style>\n\
+                 body {{ font-family: sans-serif; max-width: 800px; margin: 2em auto; color: #333; }}\n\
+                 .message {{ margin: 0.4em 0; }}\n\
+                 .time {{ color: #999; font-size: 0.85em; margin-right: 0.5em; }}\n\
+                 .user {{ font-weight: bold; margin-right: 0.5em; }}\n\
+                 .system {{ color: #888; font-style: italic; }}\n\
+                 .private {{ color: #7a3e9d; }}\n\
+                 .text {{ white-space: pre-wrap; }}\n\
+                 </style>\n</head>\n<body>\n<h1>{} 聊天记录</h1>\n",
+                html_escape(room), html_escape(room)
+            );
+            for message in messages {
+                out.push_str(&format!(
+                    "<div class=\"message {}\"><span class=\"time\">{}</span><span class=\"user\">{}</span><span class=\"text\">{}</span></div>\n",
+                    html_escape(&message.msg_type),
+                    format_time(message.timestamp),
+                    html_escape(&sender(message)),
+                    html_escape(&message.text)
+                ));
+            }
+            out.push_str("</body>\n</html>\n");
+            out
+        }
+        ExportFormat::Markdown => {
+            let mut out = format!("# {} 聊天记录\n\n", markdown_escape(room));
+            for message in messages {
+                let line = match message.msg_type.as_str() {
+                    "system" => format!("- `{}` _{}_", format_time(message.timestamp), markdown_escape(&message.text)),
+                    "private" => format!("- `{}` **{}**（私聊）: {}", format_time(message.timestamp), markdown_escape(&sender(message)), markdown_escape(&message.text)),
+                    _ => format!("- `{}` **{}**: {}", format_time(message.timestamp), markdown_escape(&message.username), markdown_escape(&message.text)),
+                };
+                out.push_str(&line);
+                out.push('\n');
+            }
+            out
+        }
+    }
+}
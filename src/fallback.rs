@@ -0,0 +1,150 @@
+use crate::{handle_chat_message, handle_disconnect, heartbeat, open_session, traffic};
+use crate::{AppState, ChatMessage, SessionSink, Transport, UserSession};
+use actix_web::{web, HttpRequest, HttpResponse};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// SSE连接空闲时发送注释行的间隔，避免代理因超时断开
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+// 长轮询请求在没有消息时的最长等待时间
+const POLL_TIMEOUT: Duration = Duration::from_secs(25);
+
+type FrameQueue = Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<String>>>;
+
+// 通过HTTP回退传输建立的会话。客户端凭令牌收发消息，令牌不同于会通过用户列表公开的会话ID
+pub struct HttpSession {
+    session_id: String,
+    queue: Option<FrameQueue>, // 长轮询会话待取走的消息，SSE会话的消息直接写入响应流
+}
+
+// 创建回退传输会话并加入大厅，返回令牌和消息接收端
+async fn open(req: &HttpRequest, app_state: &Arc<AppState>, transport: Transport) -> (String, mpsc::UnboundedReceiver<String>) {
+    let (client_addr, server_host) = {
+        let connection_info = req.connection_info();
+        (
+            connection_info.peer_addr().unwrap_or("unknown").to_string(),
+            connection_info.host().to_string(),
+        )
+    };
+    
+    let id = Uuid::new_v4().to_string();
+    let token = Uuid::new_v4().simple().to_string();
+    log::info!("New {} connection from {}, session_id: {}", transport.name(), client_addr, &id);
+    
+    let (sender, receiver) = mpsc::unbounded_channel::<String>();
+    let user_session = UserSession::new(id.clone(), client_addr, SessionSink::Channel(sender), transport, app_state.clock.as_ref());
+    app_state.http_sessions.lock().unwrap().insert(token.clone(), HttpSession { session_id: id.clone(), queue: None });
+    open_session(app_state, user_session, &server_host).await;
+    
+    // 心跳任务：与WebSocket相同，定时发送ping，超时没有收到心跳则移除会话
+    let heartbeat_state = app_state.clone();
+    let heartbeat_token = token.clone();
+    actix_web::rt::spawn(async move {
+        loop {
+            heartbeat::wait(&heartbeat_state, &id).await;
+            if !heartbeat(&id, &heartbeat_state).await {
+                break;
+            }
+        }
+        heartbeat_state.http_sessions.lock().unwrap().remove(&heartbeat_token);
+        handle_disconnect(&id, &heartbeat_state).await;
+    });
+    
+    (token, receiver)
+}
+
+// 根据令牌查找仍然存在的会话
+fn lookup(token: &str, app_state: &AppState) -> Option<(String, Option<FrameQueue>)> {
+    let http_sessions = app_state.http_sessions.lock().unwrap();
+    let http_session = http_sessions.get(token)?;
+    app_state.sessions.lock().unwrap().contains_key(&http_session.session_id)
+        .then(|| (http_session.session_id.clone(), http_session.queue.clone()))
+}
+
+// 建立SSE连接：第一个事件为 session（携带令牌），之后每个事件的data为一条JSON消息
+pub async fn sse_route(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    let (token, receiver) = open(&req, &app_state, Transport::Sse).await;
+    
+    let first = format!("event: session\ndata: {}\n\n", serde_json::json!({ "token": token }));
+    let stream = futures_util::stream::unfold((Some(first), receiver), |(first, mut receiver)| async move {
+        if let Some(first) = first {
+            return Some((Ok::<_, actix_web::Error>(web::Bytes::from(first)), (None, receiver)));
+        }
+        let chunk = match actix_web::rt::time::timeout(SSE_KEEPALIVE, receiver.recv()).await {
+            Ok(Some(json)) => format!("data: {}\n\n", json),
+            Ok(None) => return None, // 会话已移除
+            Err(_) => ": keepalive\n\n".to_string(),
+        };
+        Some((Ok(web::Bytes::from(chunk)), (None, receiver)))
+    });
+    
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(stream)
+}
+
+// 建立长轮询会话，返回令牌
+pub async fn poll_open_route(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    let (token, receiver) = open(&req, &app_state, Transport::LongPoll).await;
+    if let Some(http_session) = app_state.http_sessions.lock().unwrap().get_mut(&token) {
+        http_session.queue = Some(Arc::new(tokio::sync::Mutex::new(receiver)));
+    }
+    HttpResponse::Ok().json(serde_json::json!({ "token": token }))
+}
+
+// 长轮询：有待取消息时立即返回全部消息，否则最多等待 POLL_TIMEOUT 后返回空数组
+pub async fn poll_route(token: web::Path<String>, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    let Some((session_id, Some(queue))) = lookup(&token, &app_state) else {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": "会话不存在或已过期" }));
+    };
+    
+    // 每次轮询都说明客户端仍然在线
+    if let Some(user_session) = app_state.sessions.lock().unwrap().get_mut(&session_id) {
+        user_session.last_heartbeat = app_state.clock.now();
+    }
+    
+    let mut receiver = queue.lock().await;
+    let mut frames = Vec::new();
+    match actix_web::rt::time::timeout(POLL_TIMEOUT, receiver.recv()).await {
+        Ok(Some(json)) => frames.push(json),
+        Ok(None) => return HttpResponse::Gone().json(serde_json::json!({ "error": "会话已关闭" })),
+        Err(_) => {}
+    }
+    while let Ok(json) = receiver.try_recv() {
+        frames.push(json);
+    }
+    
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(format!("[{}]", frames.join(",")))
+}
+
+// SSE与长轮询共用的上行接口，请求体为一条JSON消息；msg_type 为 leave 时断开会话
+pub async fn send_route(
+    token: web::Path<String>,
+    message: web::Json<ChatMessage>,
+    app_state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    let Some((session_id, _)) = lookup(&token, &app_state) else {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": "会话不存在或已过期" }));
+    };
+    
+    let message = message.into_inner();
+    // 请求体已被解析，按重新序列化后的长度计入流量
+    traffic::record_inbound(&app_state, &session_id, &message.msg_type, serde_json::to_string(&message).map(|json| json.len()).unwrap_or_default());
+    if message.msg_type == "leave" {
+        app_state.http_sessions.lock().unwrap().remove(token.as_str());
+        handle_disconnect(&session_id, &app_state).await;
+        return HttpResponse::NoContent().finish();
+    }
+    
+    if handle_chat_message(message, &session_id, &app_state).await {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(serde_json::json!({ "error": "会话不存在或已过期" }))
+    }
+}
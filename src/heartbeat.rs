@@ -0,0 +1,310 @@
+// 心跳策略：固定间隔、自适应与关闭三种模式，心跳可以使用应用层的 "ping" 消息，也可以使用WebSocket协议层的Ping帧。
+// 服务器的默认策略由 AppState::with_heartbeat 设置，客户端可以通过 heartbeat 消息或 /heartbeat 命令为自己的会话协商另一种策略。
+// 自适应模式下，上一次心跳之后有主动操作的会话使用基础间隔，没有操作的会话间隔逐次翻倍直到上限；
+// 上一次心跳没有收到响应时间隔缩短到下限，尽快确认连接状态。间隔不小于最近RTT的4倍，超时取配置的超时与3倍当前间隔中的较大者。
+// 固定模式的超时同样不小于3倍间隔，协商的间隔较长时不会在两次心跳之间误判超时
+use crate::commands::{Arg, CommandSpec, Permission, Registry};
+use crate::{AppState, Timeouts, Transport};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// 心跳模式
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HeartbeatMode {
+    Fixed,
+    Adaptive,
+    #[serde(alias = "off")]
+    Disabled, // 不发送心跳也不因超时断开，只在连接关闭时移除会话
+}
+
+impl HeartbeatMode {
+    const ALL: [HeartbeatMode; 3] = [HeartbeatMode::Fixed, HeartbeatMode::Adaptive, HeartbeatMode::Disabled];
+    
+    pub fn parse(text: &str) -> Option<HeartbeatMode> {
+        match text {
+            "fixed" => Some(HeartbeatMode::Fixed),
+            "adaptive" => Some(HeartbeatMode::Adaptive),
+            "off" | "disabled" => Some(HeartbeatMode::Disabled),
+            _ => None,
+        }
+    }
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            HeartbeatMode::Fixed => "fixed",
+            HeartbeatMode::Adaptive => "adaptive",
+            HeartbeatMode::Disabled => "disabled",
+        }
+    }
+}
+
+// 心跳使用的帧
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PingKind {
+    App,      // msg_type 为 "ping" 的JSON消息，客户端回复 "pong"
+    Protocol, // WebSocket协议层的Ping帧，浏览器自动回复Pong，仅用于WebSocket会话
+}
+
+impl PingKind {
+    pub fn parse(text: &str) -> Option<PingKind> {
+        match text {
+            "app" => Some(PingKind::App),
+            "protocol" => Some(PingKind::Protocol),
+            _ => None,
+        }
+    }
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            PingKind::App => "app",
+            PingKind::Protocol => "protocol",
+        }
+    }
+}
+
+// 服务器的默认心跳策略。基础间隔与超时来自 Timeouts
+#[derive(Clone, Copy, Debug)]
+pub struct HeartbeatPolicy {
+    pub mode: HeartbeatMode,
+    pub ping: PingKind,
+    pub min_interval: Duration, // 自适应间隔与协商间隔的下限
+    pub max_interval: Duration, // 上限
+    pub allow_disabled: bool,   // 是否允许客户端协商关闭心跳，关闭后会话永远不会超时
+}
+
+impl Default for HeartbeatPolicy {
+    fn default() -> Self {
+        HeartbeatPolicy {
+            mode: HeartbeatMode::Fixed,
+            ping: PingKind::App,
+            min_interval: Duration::from_secs(5),
+            max_interval: Duration::from_secs(300),
+            allow_disabled: false,
+        }
+    }
+}
+
+impl HeartbeatPolicy {
+    // 读取环境变量 NET_APP_HEARTBEAT（fixed、adaptive、off）、NET_APP_HEARTBEAT_PING（app、protocol）
+    // 与 NET_APP_HEARTBEAT_ALLOW_OFF（1 或 true 时允许客户端关闭心跳），无法识别的值使用默认值
+    pub fn from_env() -> HeartbeatPolicy {
+        let mut policy = HeartbeatPolicy::default();
+        if let Some(value) = std::env::var("NET_APP_HEARTBEAT").ok().filter(|value| !value.is_empty()) {
+            match HeartbeatMode::parse(&value) {
+                Some(mode) => policy.mode = mode,
+                None => log::warn!("Unknown NET_APP_HEARTBEAT value {:?}, using {}", value, policy.mode.name()),
+            }
+        }
+        if let Some(value) = std::env::var("NET_APP_HEARTBEAT_PING").ok().filter(|value| !value.is_empty()) {
+            match PingKind::parse(&value) {
+                Some(ping) => policy.ping = ping,
+                None => log::warn!("Unknown NET_APP_HEARTBEAT_PING value {:?}, using {}", value, policy.ping.name()),
+            }
+        }
+        policy.allow_disabled = std::env::var("NET_APP_HEARTBEAT_ALLOW_OFF").is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+        policy
+    }
+}
+
+// 会话的心跳状态，由 open_session 按服务器策略初始化
+pub(crate) struct SessionHeartbeat {
+    pub(crate) mode: HeartbeatMode,
+    pub(crate) ping: PingKind,
+    base: Duration,     // 基础间隔，协商后可能与服务器的不同
+    interval: Duration, // 当前间隔
+    next: Instant,      // 下一次检查的时间
+    pub(crate) unanswered: u32, // 连续没有收到响应的心跳数
+}
+
+impl SessionHeartbeat {
+    // 第一次检查在建立会话时立即进行。非WebSocket会话只能使用应用层ping
+    pub(crate) fn new(policy: &HeartbeatPolicy, timeouts: &Timeouts, transport: Transport, now: Instant) -> Self {
+        SessionHeartbeat {
+            mode: policy.mode,
+            ping: if transport == Transport::WebSocket { policy.ping } else { PingKind::App },
+            base: timeouts.heartbeat_interval,
+            interval: timeouts.heartbeat_interval,
+            next: now,
+            unanswered: 0,
+        }
+    }
+    
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+    
+    // 超过该时间没有收到任何帧则断开，关闭心跳时不会超时
+    pub(crate) fn timeout(&self, timeouts: &Timeouts) -> Option<Duration> {
+        match self.mode {
+            HeartbeatMode::Fixed => Some(timeouts.heartbeat_timeout.max(self.base * 3)),
+            HeartbeatMode::Adaptive => Some(timeouts.heartbeat_timeout.max(self.interval * 3)),
+            HeartbeatMode::Disabled => None,
+        }
+    }
+    
+    // 每次检查时调用，计算下一次检查的时间。answered 为上一次心跳是否已收到响应，
+    // active 为上一次心跳之后是否有主动操作
+    pub(crate) fn schedule(&mut self, policy: &HeartbeatPolicy, now: Instant, answered: bool, active: bool, rtt: Option<Duration>) {
+        self.unanswered = if answered { 0 } else { self.unanswered + 1 };
+        self.interval = match self.mode {
+            HeartbeatMode::Fixed | HeartbeatMode::Disabled => self.base,
+            HeartbeatMode::Adaptive => {
+                let min = policy.min_interval.min(self.base);
+                let max = policy.max_interval.max(self.base);
+                let interval = if !answered {
+                    min
+                } else if active {
+                    self.base
+                } else {
+                    self.interval * 2
+                };
+                interval.max(rtt.unwrap_or_default() * 4).clamp(min, max)
+            }
+        };
+        self.next = now + self.interval;
+    }
+    
+    // 当前策略的说明与结构化数据，用于回复协商请求
+    pub(crate) fn describe(&self, timeouts: &Timeouts) -> (String, serde_json::Value) {
+        let timeout = self.timeout(timeouts);
+        let text = match (self.mode, timeout) {
+            (HeartbeatMode::Disabled, _) | (_, None) => "心跳已关闭，连接只在关闭时断开".to_string(),
+            (mode, Some(timeout)) => format!(
+                "心跳策略: {}，{}，当前间隔 {:.1} 秒，超时 {:.1} 秒",
+                mode.name(),
+                if self.ping == PingKind::Protocol { "协议层Ping帧" } else { "应用层ping消息" },
+                self.interval.as_secs_f64(),
+                timeout.as_secs_f64(),
+            ),
+        };
+        let data = serde_json::json!({
+            "mode": self.mode,
+            "ping": self.ping,
+            "interval_ms": self.interval.as_millis() as u64,
+            "base_interval_ms": self.base.as_millis() as u64,
+            "timeout_ms": timeout.map(|timeout| timeout.as_millis() as u64),
+        });
+        (text, data)
+    }
+}
+
+// 注册 /heartbeat 命令：查看或协商本会话的心跳策略
+pub(crate) fn register(registry: &mut Registry) {
+    registry.add_fn(
+        CommandSpec {
+            name: "/heartbeat",
+            args: &[Arg::Optional("fixed|adaptive|off"), Arg::Optional("app|protocol"), Arg::Optional("间隔秒")],
+            help: "查看或协商本会话的心跳策略",
+            permission: Permission::User,
+        },
+        |call| Box::pin(async move {
+            let result = Request::parse_command(call.args).and_then(|request| negotiate(call.app_state, call.user_id, request));
+            match result {
+                Ok((text, _)) => text,
+                Err(e) => e,
+            }
+        }),
+    );
+}
+
+// 客户端的协商请求，未指定的项保持不变
+#[derive(Deserialize, Default)]
+pub(crate) struct Request {
+    mode: Option<HeartbeatMode>,
+    ping: Option<PingKind>,
+    interval_ms: Option<u64>,
+}
+
+impl Request {
+    // 解析 /heartbeat 命令的参数：[fixed|adaptive|off] [app|protocol] [间隔秒]
+    pub(crate) fn parse_command(args: &[&str]) -> Result<Request, String> {
+        let mut request = Request::default();
+        for arg in args {
+            if let Some(mode) = HeartbeatMode::parse(arg) {
+                request.mode = Some(mode);
+            } else if let Some(ping) = PingKind::parse(arg) {
+                request.ping = Some(ping);
+            } else if let Ok(secs) = arg.parse::<f64>() {
+                request.interval_ms = Some((secs * 1000.0) as u64);
+            } else {
+                return Err(format!("无法识别的参数: {}。用法: /heartbeat [fixed|adaptive|off] [app|protocol] [间隔秒]", arg));
+            }
+        }
+        Ok(request)
+    }
+}
+
+// 为会话应用协商请求并重新开始计时，返回生效的策略；间隔限制在服务器策略的上下限之间，空请求只返回当前策略
+pub(crate) fn negotiate(app_state: &AppState, user_id: &str, request: Request) -> Result<(String, serde_json::Value), String> {
+    let policy = &app_state.heartbeat;
+    let mut sessions = app_state.sessions.lock().unwrap();
+    let user_session = sessions.get_mut(user_id).ok_or_else(String::new)?;
+    if request.ping == Some(PingKind::Protocol) && user_session.transport != Transport::WebSocket {
+        return Err(format!("协议层Ping帧仅适用于WebSocket连接，当前为 {}", user_session.transport.name()));
+    }
+    
+    // 服务器默认策略本身就是关闭时，客户端也可以关闭
+    if request.mode == Some(HeartbeatMode::Disabled) && !policy.allow_disabled && policy.mode != HeartbeatMode::Disabled {
+        return Err("服务器不允许关闭心跳".to_string());
+    }
+    
+    let state = &mut user_session.heartbeat;
+    if request.mode.is_none() && request.ping.is_none() && request.interval_ms.is_none() {
+        return Ok(state.describe(&app_state.timeouts));
+    }
+    if let Some(mode) = request.mode {
+        state.mode = mode;
+    }
+    if let Some(ping) = request.ping {
+        state.ping = ping;
+    }
+    if let Some(interval_ms) = request.interval_ms {
+        state.base = Duration::from_millis(interval_ms).clamp(policy.min_interval, policy.max_interval);
+    }
+    state.interval = state.base;
+    state.next = app_state.clock.now() + state.interval;
+    
+    log::info!("Session {} negotiated {} heartbeat with {} pings every {:?}", user_id, state.mode.name(), state.ping.name(), state.interval);
+    Ok(state.describe(&app_state.timeouts))
+}
+
+// 等待会话的下一次心跳检查。时间保存在会话中，在 select! 中被取消后重新等待不会改变检查的时刻；会话已移除时立即返回
+pub(crate) async fn wait(app_state: &AppState, user_id: &str) {
+    let next = app_state.sessions.lock().unwrap().get(user_id).map(|user_session| user_session.heartbeat.next);
+    if let Some(next) = next {
+        app_state.clock.sleep_until(next).await;
+    }
+}
+
+// 各模式发送的心跳数与超时断开数，用于在 /stats 中对比不同的保活策略
+#[derive(Default)]
+pub(crate) struct HeartbeatStats {
+    pings: [AtomicUsize; 3],
+    timeouts: [AtomicUsize; 3],
+}
+
+impl HeartbeatStats {
+    fn index(mode: HeartbeatMode) -> usize {
+        HeartbeatMode::ALL.iter().position(|candidate| *candidate == mode).unwrap_or_default()
+    }
+    
+    pub(crate) fn record_ping(&self, mode: HeartbeatMode) {
+        self.pings[HeartbeatStats::index(mode)].fetch_add(1, Ordering::Relaxed);
+    }
+    
+    pub(crate) fn record_timeout(&self, mode: HeartbeatMode) {
+        self.timeouts[HeartbeatStats::index(mode)].fetch_add(1, Ordering::Relaxed);
+    }
+    
+    // 每种发送过心跳的模式一行
+    pub(crate) fn summary(&self) -> Vec<String> {
+        HeartbeatMode::ALL.iter().enumerate()
+            .map(|(index, mode)| (mode, self.pings[index].load(Ordering::Relaxed), self.timeouts[index].load(Ordering::Relaxed)))
+            .filter(|(_, pings, timeouts)| pings + timeouts > 0)
+            .map(|(mode, pings, timeouts)| format!("心跳 {}: 发送 {} 次，超时断开 {} 次", mode.name(), pings, timeouts))
+            .collect()
+    }
+}
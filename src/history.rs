@@ -0,0 +1,381 @@
+use crate::search::{SearchIndex, SearchPage, SearchQuery, Viewer};
+use crate::ChatMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+// 每个房间保留的历史消息条数，与前端的显示上限一致
+const ROOM_HISTORY_LIMIT: usize = 200;
+
+// 每个私聊会话保留的历史消息条数
+const PRIVATE_HISTORY_LIMIT: usize = 200;
+
+// 表情键的最大长度（字节），避免客户端塞入任意长文本
+const MAX_EMOJI_LEN: usize = 32;
+
+// 单条消息上某个表情的聚合结果
+#[derive(Serialize, Clone, Debug)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: usize,
+    pub users: Vec<String>,
+}
+
+// 添加表情回应失败的原因
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReactionError {
+    InvalidEmoji,
+    MessageNotFound,
+    Duplicate,
+}
+
+// 持久化日志中的一条记录，每行一个JSON对象
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum LogRecord {
+    Room { message: ChatMessage },
+    Private { message: ChatMessage },
+    Event { message: ChatMessage }, // 加入、离开等系统事件，只用于导出聊天记录
+    Reaction { room: String, message_id: String, emoji: String, username: String },
+    Read { username: String, room: String, message_id: String }, // 房间已读位置前进，重启后未读计数保持不变
+}
+
+// 已读标记更新的结果
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadMark {
+    Room(String), // 房间消息，参数为房间名
+    Private { sender: String, message_id: String }, // 私聊消息，需要通知发送方
+}
+
+// 某个用户在各房间和私聊会话中的未读消息数
+#[derive(Serialize, Default, Debug)]
+pub struct UnreadCounts {
+    pub rooms: BTreeMap<String, usize>,
+    pub private: BTreeMap<String, usize>, // 对方用户名 -> 未读数
+}
+
+// 历史记录中的一条消息及其表情回应
+struct StoredMessage {
+    seq: u64, // 全局递增序号，用于比较已读位置
+    message: ChatMessage,
+    reactions: BTreeMap<String, BTreeSet<String>>, // emoji -> 回应过的用户名
+}
+
+impl StoredMessage {
+    fn reaction_counts(&self) -> Vec<ReactionCount> {
+        self.reactions.iter()
+            .map(|(emoji, users)| ReactionCount {
+                emoji: emoji.clone(),
+                count: users.len(),
+                users: users.iter().cloned().collect(),
+            })
+            .collect()
+    }
+}
+
+// 私聊会话的键，两个用户名按字典序排列
+fn conversation_key(a: &str, b: &str) -> (String, String) {
+    if a <= b {
+        (a.to_string(), b.to_string())
+    } else {
+        (b.to_string(), a.to_string())
+    }
+}
+
+// 各房间的消息历史
+#[derive(Default)]
+pub struct History {
+    rooms: HashMap<String, VecDeque<StoredMessage>>,
+    conversations: HashMap<(String, String), VecDeque<StoredMessage>>, // 私聊会话 -> 消息
+    room_markers: HashMap<String, HashMap<String, u64>>,    // 用户名 -> 房间 -> 已读序号
+    private_markers: HashMap<String, HashMap<String, u64>>, // 会话ID -> 对方用户名 -> 已读序号，不写入日志
+    next_seq: u64,
+    transcript: Vec<ChatMessage>, // 全部已持久化的消息和系统事件，不受内存中历史条数上限的影响
+    private_sessions: HashMap<String, [String; 2]>, // 私聊消息ID -> 发送方和接收方的会话ID，不写入日志
+    index: SearchIndex,           // transcript中聊天消息的全文索引
+    log: Option<File>,            // 追加写入的历史日志，未配置时只保存在内存中
+}
+
+impl History {
+    // 从日志文件恢复历史并继续追加写入
+    pub fn open(path: &Path) -> io::Result<History> {
+        let mut history = History::default();
+        
+        if path.exists() {
+            let reader = BufReader::new(File::open(path)?);
+            let mut skipped = 0;
+            for line in reader.lines() {
+                match serde_json::from_str::<LogRecord>(&line?) {
+                    Ok(record) => history.apply(record),
+                    Err(_) => skipped += 1,
+                }
+            }
+            if skipped > 0 {
+                log::warn!("Skipped {} malformed history records in {}", skipped, path.display());
+            }
+        } else if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        
+        history.log = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        log::info!("Loaded {} history records from {}", history.transcript.len(), path.display());
+        Ok(history)
+    }
+    
+    fn apply(&mut self, record: LogRecord) {
+        match record {
+            LogRecord::Room { message } => self.apply_room(&message),
+            LogRecord::Private { message } => self.apply_private(&message),
+            LogRecord::Event { message } => self.transcript.push(message),
+            LogRecord::Reaction { room, message_id, emoji, username } => {
+                let _ = self.apply_reaction(&room, &message_id, &emoji, &username);
+            }
+            LogRecord::Read { username, room, message_id } => {
+                let _ = self.apply_room_read(&username, &room, &message_id);
+            }
+        }
+    }
+    
+    fn append(&mut self, record: &LogRecord) {
+        let Some(file) = self.log.as_mut() else {
+            return;
+        };
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line + "\n",
+            Err(e) => {
+                log::error!("Failed to serialize history record: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            log::error!("Failed to append to history log: {:?}", e);
+        }
+    }
+    
+    fn stored(&mut self, message: &ChatMessage) -> StoredMessage {
+        self.next_seq += 1;
+        StoredMessage {
+            seq: self.next_seq,
+            message: message.clone(),
+            reactions: BTreeMap::new(),
+        }
+    }
+    
+    // 记录一条房间消息并写入日志
+    pub fn record(&mut self, message: &ChatMessage) {
+        self.apply_room(message);
+        self.append(&LogRecord::Room { message: message.clone() });
+    }
+    
+    // 记录一条私聊消息并写入日志，用于已读回执、未读计数和搜索。
+    // sessions 为发送方和接收方的会话ID，搜索和导出时据此判断私聊对谁可见
+    pub fn record_private(&mut self, message: &ChatMessage, sessions: [&str; 2]) {
+        if message.target.is_none() {
+            return;
+        }
+        self.private_sessions.insert(message.id.clone(), sessions.map(str::to_string));
+        self.apply_private(message);
+        self.append(&LogRecord::Private { message: message.clone() });
+    }
+    
+    // 记录一条系统事件（加入、离开等）并写入日志，事件不参与历史回放和搜索
+    pub fn record_event(&mut self, message: &ChatMessage) {
+        self.transcript.push(message.clone());
+        self.append(&LogRecord::Event { message: message.clone() });
+    }
+    
+    fn index_message(&mut self, message: &ChatMessage) {
+        self.index.add(self.transcript.len(), &message.text);
+        self.transcript.push(message.clone());
+    }
+    
+    // 房间消息进入内存历史，超出上限时丢弃最早的消息
+    fn apply_room(&mut self, message: &ChatMessage) {
+        self.index_message(message);
+        let stored = self.stored(message);
+        let messages = self.rooms.entry(message.room.clone()).or_default();
+        messages.push_back(stored);
+        if messages.len() > ROOM_HISTORY_LIMIT {
+            messages.pop_front();
+        }
+    }
+    
+    fn apply_private(&mut self, message: &ChatMessage) {
+        let Some(target) = &message.target else {
+            return;
+        };
+        self.index_message(message);
+        let stored = self.stored(message);
+        let messages = self.conversations.entry(conversation_key(&message.username, target)).or_default();
+        messages.push_back(stored);
+        if messages.len() > PRIVATE_HISTORY_LIMIT {
+            messages.pop_front();
+        }
+    }
+    
+    // 将用户的已读位置推进到指定消息。优先在给定房间中查找，找不到时再查找该会话收到的私聊消息。
+    // 已读位置只会前进，不会因为旧消息的回执而后退。房间已读位置按用户名记录并在前进时写入日志；
+    // 用户名可能被之后的连接重复使用，私聊已读位置按会话ID记录，只保存在内存中
+    pub fn mark_read(&mut self, username: &str, session: &str, room: &str, message_id: &str) -> Option<ReadMark> {
+        if let Some(advanced) = self.apply_room_read(username, room, message_id) {
+            if advanced {
+                self.append(&LogRecord::Read {
+                    username: username.to_string(),
+                    room: room.to_string(),
+                    message_id: message_id.to_string(),
+                });
+            }
+            return Some(ReadMark::Room(room.to_string()));
+        }
+        
+        // 只有接收方会话的回执才有意义
+        let stored = self.conversations.iter()
+            .filter(|((a, b), _)| a == username || b == username)
+            .flat_map(|(_, messages)| messages.iter())
+            .find(|stored| stored.message.id == message_id && self.received_by(session, &stored.message))?;
+        
+        let sender = stored.message.username.clone();
+        let marker = self.private_markers.entry(session.to_string()).or_default()
+            .entry(sender.clone()).or_default();
+        *marker = (*marker).max(stored.seq);
+        Some(ReadMark::Private { sender, message_id: message_id.to_string() })
+    }
+    
+    // 推进房间内的已读位置，返回位置是否前进；消息不在该房间时返回None
+    fn apply_room_read(&mut self, username: &str, room: &str, message_id: &str) -> Option<bool> {
+        let stored = self.rooms.get(room)?.iter().find(|stored| stored.message.id == message_id)?;
+        let marker = self.room_markers.entry(username.to_string()).or_default()
+            .entry(room.to_string()).or_default();
+        let advanced = stored.seq > *marker;
+        *marker = (*marker).max(stored.seq);
+        Some(advanced)
+    }
+    
+    // 私聊消息是否由该会话接收。与can_see一样按会话ID判断，但发送方看得到自己的消息却不能确认已读
+    fn received_by(&self, session: &str, message: &ChatMessage) -> bool {
+        self.private_sessions.get(&message.id).is_some_and(|[_, target]| target == session)
+    }
+    
+    // 统计用户的未读消息数。房间只统计用户当前所在的房间和读过的房间，自己发送的消息不计入；
+    // 私聊只统计该会话收到的消息
+    pub fn unread_counts(&self, username: &str, session: &str, current_room: &str) -> UnreadCounts {
+        let mut counts = UnreadCounts::default();
+        let room_markers = self.room_markers.get(username);
+        
+        for (room, messages) in &self.rooms {
+            let marker = room_markers.and_then(|markers| markers.get(room)).copied();
+            if marker.is_none() && room != current_room {
+                continue;
+            }
+            let unread = messages.iter()
+                .filter(|stored| stored.seq > marker.unwrap_or(0) && stored.message.username != username)
+                .count();
+            counts.rooms.insert(room.clone(), unread);
+        }
+        
+        let private_markers = self.private_markers.get(session);
+        for ((a, b), messages) in &self.conversations {
+            let peer = if a == username { b } else if b == username { a } else { continue };
+            let marker = private_markers.and_then(|markers| markers.get(peer)).copied().unwrap_or(0);
+            let unread = messages.iter()
+                .filter(|stored| stored.seq > marker && stored.message.username == *peer && self.received_by(session, &stored.message))
+                .count();
+            if unread > 0 {
+                counts.private.insert(peer.clone(), unread);
+            }
+        }
+        
+        counts
+    }
+    
+    // 为房间内的消息添加表情回应，返回该消息最新的聚合结果
+    pub fn add_reaction(
+        &mut self,
+        room: &str,
+        message_id: &str,
+        emoji: &str,
+        username: &str,
+    ) -> Result<Vec<ReactionCount>, ReactionError> {
+        let reactions = self.apply_reaction(room, message_id, emoji, username)?;
+        self.append(&LogRecord::Reaction {
+            room: room.to_string(),
+            message_id: message_id.to_string(),
+            emoji: emoji.to_string(),
+            username: username.to_string(),
+        });
+        Ok(reactions)
+    }
+    
+    fn apply_reaction(
+        &mut self,
+        room: &str,
+        message_id: &str,
+        emoji: &str,
+        username: &str,
+    ) -> Result<Vec<ReactionCount>, ReactionError> {
+        if emoji.is_empty() || emoji.len() > MAX_EMOJI_LEN || emoji.chars().any(char::is_whitespace) {
+            return Err(ReactionError::InvalidEmoji);
+        }
+        
+        let stored = self.rooms.get_mut(room)
+            .and_then(|messages| messages.iter_mut().find(|stored| stored.message.id == message_id))
+            .ok_or(ReactionError::MessageNotFound)?;
+        
+        if !stored.reactions.entry(emoji.to_string()).or_default().insert(username.to_string()) {
+            return Err(ReactionError::Duplicate);
+        }
+        
+        Ok(stored.reaction_counts())
+    }
+    
+    // 生成房间的历史回放，每条消息的data字段携带其表情回应
+    pub fn replay(&self, room: &str) -> Vec<ChatMessage> {
+        self.rooms.get(room)
+            .map(|messages| {
+                messages.iter()
+                    .map(|stored| {
+                        let mut message = stored.message.clone();
+                        if !stored.reactions.is_empty() {
+                            message.data = Some(serde_json::json!({ "reactions": stored.reaction_counts() }));
+                        }
+                        message
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    pub fn has_room(&self, room: &str) -> bool {
+        self.rooms.contains_key(room)
+    }
+    
+    // 在全部已持久化的消息中搜索
+    pub fn search(&self, query: &SearchQuery, viewer: Viewer) -> SearchPage {
+        self.index.search(&self.transcript, query, |message| self.can_see(viewer, message))
+    }
+    
+    // 私聊消息只对收发双方的会话（和管理员）可见。用户名可能被之后的连接重复使用，
+    // 所以按会话ID判断；从日志恢复的私聊没有会话记录，只有管理员能看到
+    fn can_see(&self, viewer: Viewer, message: &ChatMessage) -> bool {
+        if message.msg_type != "private" {
+            return true;
+        }
+        match viewer {
+            Viewer::Admin => true,
+            Viewer::Session(id) => self.private_sessions.get(&message.id).is_some_and(|sessions| sessions.iter().any(|session| session == id)),
+            Viewer::Anonymous => false,
+        }
+    }
+    
+    // 取出房间在时间范围内的完整记录（含系统事件），私聊消息只包含查询者参与的
+    pub fn transcript(&self, room: &str, after: Option<u64>, before: Option<u64>, viewer: Viewer) -> Vec<ChatMessage> {
+        self.transcript.iter()
+            .filter(|message| message.room == room && self.can_see(viewer, message))
+            .filter(|message| after.is_none_or(|after| message.timestamp >= after))
+            .filter(|message| before.is_none_or(|before| message.timestamp < before))
+            .cloned()
+            .collect()
+    }
+}
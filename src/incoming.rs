@@ -0,0 +1,213 @@
+// 入站Webhook：管理员用 /hook add 为房间创建令牌，外部脚本（CI、实验评分程序等）向 POST /api/hooks/<令牌>
+// 发送 {"text": "..."}，消息以令牌的名称作为发送者、以 webhook 消息类型广播到房间并记入历史，客户端以机器人样式显示。
+// 每个令牌按自己的每分钟条数限速（令牌桶，允许短时间内连续发送到上限），可以用 /hook revoke 撤销
+use crate::commands::{Arg, Call, CommandSpec, Permission, Registry};
+use crate::{broadcast_message_to_room, find_user_by_name, webhook, AppState, ChatMessage};
+use actix_web::{web, HttpResponse};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const DEFAULT_PER_MINUTE: u32 = 10;
+const MAX_PER_MINUTE: u32 = 600;
+// 消息文本的最大字符数
+const MAX_TEXT_CHARS: usize = 4000;
+// 名称的最大字符数
+const MAX_NAME_CHARS: usize = 20;
+
+// 一个入站Webhook令牌
+struct Hook {
+    token: String,
+    room: String,
+    name: String, // 消息的发送者名称
+    per_minute: u32,
+    allowance: f64,    // 令牌桶中剩余的条数
+    refilled: Instant, // 上次补充令牌桶的时间
+    created_by: String,
+    posted: u64,  // 已发送的消息数
+    limited: u64, // 因超过速率被拒绝的次数
+}
+
+impl Hook {
+    // 按经过的时间补充后取出一条，超过速率时返回还需等待的时间
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let rate = self.per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.allowance = (self.allowance + elapsed * rate).min(self.per_minute as f64);
+        self.refilled = now;
+        if self.allowance >= 1.0 {
+            self.allowance -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.allowance) / rate))
+        }
+    }
+}
+
+// 拒绝投递的原因
+enum Rejection {
+    Unknown,
+    Limited(Duration),
+}
+
+// 全部入站Webhook令牌，单独加锁
+#[derive(Default)]
+pub(crate) struct IncomingHooks {
+    hooks: Vec<Hook>,
+}
+
+impl IncomingHooks {
+    fn admit(&mut self, token: &str, now: Instant) -> Result<(String, String), Rejection> {
+        let hook = self.hooks.iter_mut().find(|hook| hook.token == token).ok_or(Rejection::Unknown)?;
+        match hook.take(now) {
+            Ok(()) => {
+                hook.posted += 1;
+                Ok((hook.room.clone(), hook.name.clone()))
+            }
+            Err(retry_after) => {
+                hook.limited += 1;
+                Err(Rejection::Limited(retry_after))
+            }
+        }
+    }
+}
+
+// 注册 /hook 命令
+pub(crate) fn register(registry: &mut Registry) {
+    registry.add_fn(
+        CommandSpec {
+            name: "/hook",
+            args: &[Arg::Required("list|add|revoke"), Arg::Optional("房间或名称"), Arg::Optional("名称"), Arg::Optional("每分钟条数")],
+            help: "管理向房间发消息的入站Webhook令牌",
+            permission: Permission::Admin,
+        },
+        hook_command,
+    );
+}
+
+fn hook_command(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        let app_state = call.app_state;
+        match call.args {
+            ["list", ..] => {
+                let incoming = app_state.incoming.lock().unwrap();
+                if incoming.hooks.is_empty() {
+                    return "当前没有入站Webhook".to_string();
+                }
+                let lines: Vec<String> = incoming.hooks.iter()
+                    .map(|hook| format!(
+                        "{} → 房间 {}，令牌 {}…，每分钟 {} 条，已发送 {} 条，限流 {} 次（由 {} 创建）",
+                        hook.name, hook.room, &hook.token[..8], hook.per_minute, hook.posted, hook.limited, hook.created_by
+                    ))
+                    .collect();
+                format!("入站Webhook:\n{}", lines.join("\n"))
+            }
+            ["add", room, name, rest @ ..] => {
+                let per_minute = match rest.first() {
+                    Some(value) => match value.parse::<u32>() {
+                        Ok(n) if (1..=MAX_PER_MINUTE).contains(&n) => n,
+                        _ => return format!("每分钟条数应为 1 到 {} 之间的整数", MAX_PER_MINUTE),
+                    },
+                    None => DEFAULT_PER_MINUTE,
+                };
+                if name.chars().count() > MAX_NAME_CHARS {
+                    return format!("名称不能超过 {} 个字符", MAX_NAME_CHARS);
+                }
+                if is_reserved_name(name) || find_user_by_name(name, app_state).is_some() {
+                    return format!("名称 {} 与服务器或用户的名称冲突，请换一个", name);
+                }
+                let token = Uuid::new_v4().simple().to_string();
+                {
+                    let mut incoming = app_state.incoming.lock().unwrap();
+                    if incoming.hooks.iter().any(|hook| hook.name == *name) {
+                        return format!("已有名为 {} 的入站Webhook", name);
+                    }
+                    incoming.hooks.push(Hook {
+                        token: token.clone(),
+                        room: room.to_string(),
+                        name: name.to_string(),
+                        per_minute,
+                        allowance: per_minute as f64,
+                        refilled: app_state.clock.now(),
+                        created_by: call.username().unwrap_or_default(),
+                        posted: 0,
+                        limited: 0,
+                    });
+                }
+                log::info!("Incoming webhook {} created for room {}", name, room);
+                format!(
+                    "已为房间 {} 创建入站Webhook {}（每分钟 {} 条）\n地址: POST /api/hooks/{}\n请求体: {{\"text\": \"消息内容\"}}\n令牌只显示这一次",
+                    room, name, per_minute, token
+                )
+            }
+            // 按名称或令牌撤销
+            ["revoke", key, ..] => {
+                let mut incoming = app_state.incoming.lock().unwrap();
+                match incoming.hooks.iter().position(|hook| hook.name == *key || hook.token == *key) {
+                    Some(index) => {
+                        let hook = incoming.hooks.remove(index);
+                        log::info!("Incoming webhook {} for room {} revoked", hook.name, hook.room);
+                        format!("已撤销入站Webhook {}（房间 {}）", hook.name, hook.room)
+                    }
+                    None => format!("没有名为 {} 的入站Webhook", key),
+                }
+            }
+            _ => "用法: /hook list | /hook add <房间> <名称> [每分钟条数] | /hook revoke <名称>".to_string(),
+        }
+    })
+}
+
+// 投递请求体
+// 服务器与默认分配的用户名（用户 + 数字）保留，避免webhook消息冒充服务器或之后上线的用户
+fn is_reserved_name(name: &str) -> bool {
+    let default_name = name.strip_prefix("用户").is_some_and(|suffix| !suffix.is_empty() && suffix.chars().all(|c| c.is_ascii_digit()));
+    name == "服务器" || name == "未命名用户" || default_name
+}
+
+#[derive(Deserialize)]
+struct Post {
+    text: String,
+}
+
+// 以令牌对应的名称向房间发送一条 webhook 消息
+pub(crate) async fn post_route(token: web::Path<String>, body: web::Bytes, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    let text = match serde_json::from_slice::<Post>(&body) {
+        Ok(post) => post.text.trim().to_string(),
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("请求体应为 {{\"text\": \"...\"}}: {}", e) })),
+    };
+    if text.is_empty() || text.chars().count() > MAX_TEXT_CHARS {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("消息不能为空，也不能超过 {} 个字符", MAX_TEXT_CHARS) }));
+    }
+    
+    let admitted = app_state.incoming.lock().unwrap().admit(&token, app_state.clock.now());
+    let (room, name) = match admitted {
+        Ok(target) => target,
+        Err(Rejection::Unknown) => return HttpResponse::NotFound().json(serde_json::json!({ "error": "令牌无效或已撤销" })),
+        Err(Rejection::Limited(retry_after)) => {
+            let seconds = retry_after.as_secs_f64().ceil() as u64;
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", seconds.to_string()))
+                .json(serde_json::json!({ "error": format!("超过速率限制，请 {} 秒后重试", seconds) }));
+        }
+    };
+    
+    let message = ChatMessage {
+        msg_type: "webhook".to_string(),
+        username: name.clone(),
+        room: room.clone(),
+        text,
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: None,
+    };
+    app_state.history.lock().unwrap().record(&message);
+    broadcast_message_to_room(&message, &room, &app_state).await;
+    if app_state.webhooks.active() {
+        app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::Message, &app_state, &room).with_message(&message));
+    }
+    log::info!("Incoming webhook {} posted to room {}", name, room);
+    HttpResponse::Ok().json(serde_json::json!({ "id": message.id, "room": room }))
+}
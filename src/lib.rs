@@ -0,0 +1,1745 @@
+// 聊天服务器核心：会话与房间管理、消息处理以及各种传输方式。
+// 可执行文件负责读取配置并启动监听，集成测试直接在进程内启动服务
+pub mod bot;
+pub mod capture;
+pub mod clock;
+pub mod cluster;
+pub mod commands;
+pub mod discovery;
+mod export;
+mod fallback;
+pub mod heartbeat;
+pub mod history;
+mod incoming;
+mod monitor;
+pub mod pcap;
+pub mod protocol;
+mod search;
+mod speedtest;
+mod subnet;
+pub mod tcp;
+pub mod tls;
+mod traffic;
+pub mod udp;
+mod webhook;
+
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web::http::header::{Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue};
+use actix_ws::Message;
+use capture::Recorder;
+use clock::{Clock, SystemClock, Ticker};
+use cluster::Cluster;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+use export::ExportFormat;
+use heartbeat::{HeartbeatMode, HeartbeatPolicy, HeartbeatStats, PingKind, SessionHeartbeat};
+use history::{History, ReactionError, ReadMark};
+use monitor::{Event, EventKind, Monitor};
+use protocol::ChatMessage;
+use search::{SearchQuery, Viewer};
+
+// 用户角色
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Role {
+    User,
+    Admin,
+}
+
+// 用户在线状态
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Presence {
+    Online,
+    Idle,
+}
+
+// 会话使用的传输方式
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Transport {
+    WebSocket,
+    Tcp,
+    Udp,
+    Sse,
+    LongPoll,
+    Bot, // 服务器进程内的机器人
+}
+
+impl Transport {
+    fn name(&self) -> &'static str {
+        match self {
+            Transport::WebSocket => "WebSocket",
+            Transport::Tcp => "TCP",
+            Transport::Udp => "UDP",
+            Transport::Sse => "SSE",
+            Transport::LongPoll => "Long-poll",
+            Transport::Bot => "Bot",
+        }
+    }
+}
+
+// 会话的发送端。非WebSocket传输通过通道接收与WebSocket相同的JSON帧，由各自的网关任务转换格式后写出
+#[derive(Clone)]
+enum SessionSink {
+    WebSocket(actix_ws::Session),
+    Channel(mpsc::UnboundedSender<String>),
+}
+
+impl SessionSink {
+    async fn text(&mut self, json: String) -> Result<(), actix_ws::Closed> {
+        match self {
+            SessionSink::WebSocket(session) => session.text(json).await,
+            SessionSink::Channel(sender) => sender.send(json).map_err(|_| actix_ws::Closed),
+        }
+    }
+}
+
+// 超过该时间没有主动操作（聊天、命令等）视为空闲
+const IDLE_AFTER: Duration = Duration::from_secs(300);
+
+// 心跳与陈旧会话清理的时间参数，测试中可以缩短
+#[derive(Clone, Copy, Debug)]
+pub struct Timeouts {
+    pub heartbeat_interval: Duration, // 服务器发送心跳ping的间隔
+    pub heartbeat_timeout: Duration,  // 超过该时间没有收到心跳则断开
+    pub stale_after: Duration,        // 同一IP建立新连接时，超过该时间没有心跳的旧连接会被清理
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            heartbeat_interval: Duration::from_secs(30),
+            heartbeat_timeout: Duration::from_secs(90),
+            stale_after: Duration::from_secs(60),
+        }
+    }
+}
+
+// 用户会话信息
+struct UserSession {
+    id: String,
+    username: String,
+    room: String,
+    addr: String,  // 客户端IP地址
+    session: SessionSink,
+    transport: Transport,
+    last_heartbeat: Instant,
+    join_time: Instant, // 添加加入时间字段，用于会话管理
+    joined_at: u64,     // 加入时的Unix时间戳（秒），只在建立会话时取一次，之后保持不变
+    role: Role,
+    presence: Presence,
+    last_activity: Instant,     // 最近一次主动操作的时间
+    ping_sent: Option<Instant>, // 最近一次心跳ping的发送时间，收到pong后用于计算RTT
+    rtt_ms: Option<u64>,
+    sequence: Option<udp::SequenceTracker>, // 数据报传输的序号统计
+    heartbeat: SessionHeartbeat,            // 心跳模式与下一次检查的时间
+    speedtest: Option<speedtest::SpeedTest>, // 正在进行的吞吐量测试
+    traffic: traffic::Traffic,               // 收发的帧数、字节数与错误计数
+}
+
+impl UserSession {
+    // 创建新会话，使用随机数字后缀的默认用户名避免冲突(用户名和房间稍后会通过消息更新)
+    fn new(id: String, addr: String, session: SessionSink, transport: Transport, clock: &dyn Clock) -> Self {
+        let now = clock.now();
+        let random_suffix = rand::random::<u16>() % 1000;
+        UserSession {
+            id,
+            username: format!("用户{}", random_suffix),
+            room: "大厅".to_string(),
+            addr,
+            session,
+            transport,
+            last_heartbeat: now,
+            join_time: now,
+            joined_at: clock.timestamp(),
+            role: Role::User,
+            presence: Presence::Online,
+            last_activity: now,
+            ping_sent: None,
+            rtt_ms: None,
+            sequence: None,
+            heartbeat: SessionHeartbeat::new(&HeartbeatPolicy::default(), &Timeouts::default(), transport, now),
+            speedtest: None,
+            traffic: traffic::Traffic::default(),
+        }
+    }
+    
+    // 根据最近的主动操作更新在线状态，返回状态是否发生变化
+    fn refresh_presence(&mut self, now: Instant) -> bool {
+        let presence = if now.saturating_duration_since(self.last_activity) > IDLE_AFTER {
+            Presence::Idle
+        } else {
+            Presence::Online
+        };
+        let changed = presence != self.presence;
+        self.presence = presence;
+        changed
+    }
+}
+
+// 应用状态
+pub struct AppState {
+    sessions: Mutex<HashMap<String, UserSession>>,
+    rooms: Mutex<HashMap<String, HashSet<String>>>, // room_name -> set of user_ids
+    admin_token: Option<String>, // 管理员口令，来自环境变量 NET_APP_ADMIN_TOKEN
+    history: Mutex<History>,     // 各房间的聊天历史与表情回应
+    http_sessions: Mutex<HashMap<String, fallback::HttpSession>>, // SSE/长轮询令牌 -> 会话，需先于sessions加锁
+    timeouts: Timeouts,
+    heartbeat: HeartbeatPolicy,       // 新会话默认的心跳策略
+    heartbeat_stats: HeartbeatStats,  // 各心跳模式的发送与超时次数
+    clock: Arc<dyn Clock>, // 会话计时与消息时间戳都通过该时钟获取
+    cluster: Cluster,      // 其他节点的用户目录与连接
+    capture: Option<Recorder>, // 启用录制时记录WebSocket会话收发的每一帧
+    monitor: Monitor,          // 协议监视流的订阅者
+    topology: Mutex<subnet::Topology>, // 模拟子网与路由器，单独加锁
+    departures: Mutex<traffic::Departures>, // 最近结束的会话，用于识别重连，最后加锁
+    commands: commands::Registry,           // 斜杠命令
+    webhooks: webhook::Webhooks,            // 外发Webhook的订阅，与monitor一样最后加锁
+    incoming: Mutex<incoming::IncomingHooks>, // 入站Webhook令牌，单独加锁
+}
+
+impl AppState {
+    pub fn new(admin_token: Option<String>, history: History) -> Self {
+        AppState {
+            sessions: Mutex::new(HashMap::new()),
+            rooms: Mutex::new({
+                let mut rooms = HashMap::new();
+                rooms.insert("大厅".to_string(), HashSet::new());
+                rooms
+            }),
+            admin_token,
+            history: Mutex::new(history),
+            http_sessions: Mutex::new(HashMap::new()),
+            timeouts: Timeouts::default(),
+            heartbeat: HeartbeatPolicy::default(),
+            heartbeat_stats: HeartbeatStats::default(),
+            clock: Arc::new(SystemClock),
+            cluster: Cluster::new(format!("node-{}", &Uuid::new_v4().simple().to_string()[..8])),
+            capture: None,
+            monitor: Monitor::default(),
+            topology: Mutex::new(subnet::Topology::default()),
+            departures: Mutex::new(traffic::Departures::default()),
+            commands: commands::Registry::builtin(),
+            webhooks: webhook::Webhooks::default(),
+            incoming: Mutex::new(incoming::IncomingHooks::default()),
+        }
+    }
+    
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+    
+    pub fn with_heartbeat(mut self, policy: HeartbeatPolicy) -> Self {
+        self.heartbeat = policy;
+        self
+    }
+    
+    // 注册自定义斜杠命令，与内置命令同名时替换内置命令
+    pub fn with_command(mut self, command: impl commands::Command + 'static) -> Self {
+        self.commands.add(Box::new(command));
+        self
+    }
+    
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+    
+    // 集群中的节点ID，各节点必须不同，默认随机生成
+    pub fn with_node_id(mut self, node_id: String) -> Self {
+        self.cluster = Cluster::new(node_id);
+        self
+    }
+    
+    // 集群节点间共享的口令，需在 with_node_id 之后调用
+    pub fn with_cluster_secret(mut self, secret: String) -> Self {
+        self.cluster.set_secret(secret);
+        self
+    }
+    
+    // 把WebSocket会话的流量录制到指定文件，需在 with_clock 之后调用
+    pub fn with_capture(mut self, path: &std::path::Path) -> std::io::Result<Self> {
+        self.capture = Some(Recorder::create(path, self.clock.as_ref())?);
+        Ok(self)
+    }
+}
+
+// 注册聊天服务的全部接口（WebSocket、回退传输与HTTP API），静态文件由调用方另行挂载
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/ws").route(web::get().to(ws_route)))
+        // WebSocket被代理阻断时的回退传输
+        .service(web::resource("/sse").route(web::get().to(fallback::sse_route)))
+        .service(web::resource("/poll").route(web::post().to(fallback::poll_open_route)))
+        .service(web::resource("/poll/{token}").route(web::get().to(fallback::poll_route)))
+        .service(web::resource("/send/{token}").route(web::post().to(fallback::send_route)))
+        .service(web::resource("/api/search").route(web::get().to(search_route)))
+        .service(web::resource("/api/monitor").route(web::get().to(monitor::monitor_route)))
+        .service(web::resource("/api/monitor/ticket").route(web::post().to(monitor::ticket_route)))
+        .service(web::resource("/api/speedtest/download").route(web::get().to(speedtest::download_route)))
+        .service(web::resource("/api/speedtest/upload").route(web::post().to(speedtest::upload_route)))
+        .service(web::resource("/api/traffic").route(web::get().to(traffic::traffic_route)))
+        .service(web::resource("/api/traffic/{user}").route(web::get().to(traffic::session_traffic_route)))
+        .service(web::resource("/api/webhooks")
+            .route(web::get().to(webhook::webhooks_route))
+            .route(web::post().to(webhook::create_webhook_route)))
+        .service(web::resource("/api/webhooks/{id}").route(web::delete().to(webhook::delete_webhook_route)))
+        .service(web::resource("/api/hooks/{token}").route(web::post().to(incoming::post_route)))
+        .service(web::resource("/api/rooms/{room}/export").route(web::get().to(export_route)));
+}
+
+// 用户列表中的单个成员，也用于在集群节点之间同步会话
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct UserListEntry {
+    id: String,
+    username: String,
+    role: Role,
+    presence: Presence,
+    transport: Transport,
+    join_time: u64, // 加入时间（Unix时间戳，秒）
+    rtt_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr: Option<String>, // 仅发送给管理员
+}
+
+impl UserListEntry {
+    fn new(user_session: &UserSession, with_addr: bool) -> Self {
+        UserListEntry {
+            id: user_session.id.clone(),
+            username: user_session.username.clone(),
+            role: user_session.role,
+            presence: user_session.presence,
+            transport: user_session.transport,
+            join_time: user_session.joined_at,
+            rtt_ms: user_session.rtt_ms,
+            addr: with_addr.then(|| user_session.addr.clone()),
+        }
+    }
+}
+
+// 用户列表增量更新，参数为发生变化的用户ID
+enum UserListDiff {
+    Join(String),
+    Leave(String),
+    Update(String),
+}
+
+// 记录会话收发的一帧：计入会话的流量统计，写入流量录制并推送到协议监视流（后两者只针对WebSocket会话）
+fn trace_frame(app_state: &AppState, user_id: &str, inbound: bool, opcode: capture::Opcode, payload: &[u8]) {
+    if let Some(capture) = &app_state.capture {
+        if inbound {
+            capture.inbound(app_state.clock.as_ref(), user_id, opcode, payload);
+        } else {
+            capture.outbound(app_state.clock.as_ref(), user_id, opcode, payload);
+        }
+    }
+    // 文本帧附带其中的消息类型
+    let msg_type = (opcode == capture::Opcode::Text).then(|| traffic::msg_type(payload)).flatten();
+    traffic::record_frame(app_state, user_id, inbound, opcode, msg_type.as_deref(), payload.len());
+    monitor_session(app_state, user_id, EventKind::Frame, |user_session| {
+        if user_session.transport != Transport::WebSocket {
+            return None;
+        }
+        let (direction, arrow) = if inbound { ("in", "客户端 → 服务器") } else { ("out", "服务器 → 客户端") };
+        let label = match opcode {
+            capture::Opcode::Text => "文本帧",
+            capture::Opcode::Ping => "ping帧",
+            capture::Opcode::Pong => "pong帧",
+            capture::Opcode::Close => "关闭帧",
+            capture::Opcode::Binary => "二进制帧",
+        };
+        let summary = match &msg_type {
+            Some(msg_type) => format!("{} {} {} 字节 ({})", arrow, label, payload.len(), msg_type),
+            None => format!("{} {} {} 字节", arrow, label, payload.len()),
+        };
+        Some((summary, serde_json::json!({ "direction": direction, "opcode": opcode.name(), "bytes": payload.len(), "msg_type": msg_type })))
+    });
+}
+
+// 记录发给会话的文本帧
+fn trace_outbound(app_state: &AppState, user_id: &str, message_json: &str) {
+    trace_frame(app_state, user_id, false, capture::Opcode::Text, message_json.as_bytes());
+}
+
+// 向协议监视流发送与会话相关的事件，build 返回说明与数据，返回None时不发送。
+// 没有订阅者时不做任何事；会获取sessions锁，调用时不能持有该锁
+fn monitor_session(app_state: &AppState, user_id: &str, kind: EventKind, build: impl FnOnce(&UserSession) -> Option<(String, serde_json::Value)>) {
+    if !app_state.monitor.active() {
+        return;
+    }
+    let event = {
+        let sessions = app_state.sessions.lock().unwrap();
+        let Some(user_session) = sessions.get(user_id) else {
+            return;
+        };
+        let Some((summary, data)) = build(user_session) else {
+            return;
+        };
+        Event::for_session(kind, app_state.clock.as_ref(), user_session, summary, data)
+    };
+    app_state.monitor.emit(event);
+}
+
+// 通过用户名查找用户ID，本节点没有时查找集群中的其他节点
+fn find_user_by_name(username: &str, app_state: &Arc<AppState>) -> Option<String> {
+    let sessions = app_state.sessions.lock().unwrap();
+    
+    for (id, session) in sessions.iter() {
+        if session.username == username {
+            return Some(id.clone());
+        }
+    }
+    
+    app_state.cluster.find_user_by_name(username)
+}
+
+// 会话所在的房间，包括其他节点上的会话
+fn user_room(user_id: &str, app_state: &Arc<AppState>) -> Option<String> {
+    let room = app_state.sessions.lock().unwrap().get(user_id).map(|user_session| user_session.room.clone());
+    room.or_else(|| app_state.cluster.user_room(user_id))
+}
+
+// 登记新会话并加入大厅：清理同IP的陈旧连接，发送欢迎信息、用户列表、历史消息与未读计数
+async fn open_session(app_state: &Arc<AppState>, mut user_session: UserSession, server_host: &str) {
+    user_session.heartbeat = SessionHeartbeat::new(&app_state.heartbeat, &app_state.timeouts, user_session.transport, app_state.clock.now());
+    let id = user_session.id.clone();
+    let client_addr = user_session.addr.clone();
+    let default_username = user_session.username.clone();
+    
+    // 存储连接前先检查并清理可能存在的同IP陈旧连接
+    let mut removed_stale = Vec::new(); // (session_id, room, username)
+    {
+        let mut sessions = app_state.sessions.lock().unwrap();
+        let mut stale_sessions = Vec::new();
+        
+        // 检查是否有来自相同IP的陈旧连接
+        for (session_id, existing_session) in sessions.iter() {
+            // 如果是相同IP地址并且长时间没有心跳，认为是陈旧连接
+            if existing_session.addr == client_addr && 
+               app_state.clock.elapsed(existing_session.last_heartbeat) > app_state.timeouts.stale_after {
+                stale_sessions.push(session_id.clone());
+            }
+        }
+        
+        // 移除陈旧连接
+        let mut rooms = app_state.rooms.lock().unwrap();
+        for stale_id in stale_sessions {
+            log::info!("Removing stale connection: {} from same IP {}", stale_id, client_addr);
+            if let Some(stale_session) = sessions.remove(&stale_id) {
+                if app_state.monitor.active() {
+                    let silent = app_state.clock.elapsed(stale_session.last_heartbeat);
+                    app_state.monitor.emit(Event::for_session(
+                        EventKind::Stale,
+                        app_state.clock.as_ref(),
+                        &stale_session,
+                        format!("同一IP {} 建立新连接，清理 {:.1} 秒没有心跳的旧连接（上限 {} 秒）", client_addr, silent.as_secs_f64(), app_state.timeouts.stale_after.as_secs()),
+                        serde_json::json!({ "addr": client_addr, "silent_ms": silent.as_millis() as u64, "stale_after_ms": app_state.timeouts.stale_after.as_millis() as u64 }),
+                    ));
+                }
+                // 从房间中移除
+                if let Some(room_users) = rooms.get_mut(&stale_session.room) {
+                    room_users.remove(&stale_id);
+                }
+                app_state.departures.lock().unwrap().depart(&client_addr, app_state.clock.now(), stale_session.traffic.reconnects);
+                removed_stale.push((stale_id, stale_session.room, stale_session.username));
+            }
+        }
+        
+        // 同一地址刚结束的会话视为本次连接之前的会话
+        user_session.traffic.reconnects = app_state.departures.lock().unwrap().arrive(&client_addr, app_state.clock.now());
+        if user_session.traffic.reconnects > 0 {
+            log::info!("Connection {} from {} is reconnect #{}", id, client_addr, user_session.traffic.reconnects);
+        }
+        
+        // 添加新连接
+        sessions.insert(id.clone(), user_session);
+        
+        // 将用户添加到默认房间
+        rooms.entry("大厅".to_string())
+             .or_default()
+             .insert(id.clone());
+    }
+    
+    monitor_session(app_state, &id, EventKind::Open, |user_session| Some((
+        format!("{} 连接建立，来自 {}", user_session.transport.name(), user_session.addr),
+        serde_json::json!({ "transport": user_session.transport, "addr": user_session.addr }),
+    )));
+    
+    if app_state.webhooks.active() {
+        app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::Join, app_state, "大厅").by(&id, &default_username));
+    }
+    
+    // 通知陈旧连接所在房间的其他用户
+    for (stale_id, stale_room, stale_username) in removed_stale {
+        if app_state.webhooks.active() {
+            app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::Leave, app_state, &stale_room).by(&stale_id, &stale_username));
+        }
+        send_user_list_diff(app_state, &stale_room, UserListDiff::Leave(stale_id)).await;
+    }
+    
+    // 发送连接成功消息与服务器信息
+    let server_info = ChatMessage {
+        msg_type: "system".to_string(),
+        username: "服务器".to_string(),
+        room: "大厅".to_string(),
+        text: format!("连接成功！服务器信息: 本地地址 {}，您的IP地址: {}", 
+                     server_host, client_addr),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: None,
+    };
+    
+    // 记录信息到日志，帮助调试
+    log::info!("Sending welcome message to new connection {}", id);
+    
+    send_message_to_user(&server_info, &id, app_state).await;
+    
+    // 初始化时发送默认用户名
+    let init_msg = ChatMessage {
+        msg_type: "chat".to_string(),
+        username: default_username.clone(),
+        room: "大厅".to_string(),
+        text: "".to_string(),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: None,
+    };
+    
+    send_message_to_user(&init_msg, &id, app_state).await;
+    
+    // 连接事件只记入聊天记录，房间成员通过用户列表更新得知
+    let connect_event = ChatMessage {
+        msg_type: "system".to_string(),
+        username: "服务器".to_string(),
+        room: "大厅".to_string(),
+        text: format!("{} 加入了聊天室", default_username),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: None,
+    };
+    app_state.history.lock().unwrap().record_event(&connect_event);
+    
+    // 向新用户发送完整的在线用户列表，并通知房间内其他用户
+    send_user_list(app_state, "大厅", &id).await;
+    send_user_list_diff(app_state, "大厅", UserListDiff::Join(id.clone())).await;
+    send_history(app_state, "大厅", &id).await;
+    send_unread_counts(app_state, &id).await;
+}
+
+// 处理WebSocket连接
+async fn ws_route(
+    req: HttpRequest,
+    stream: web::Payload,
+    app_state: web::Data<Arc<AppState>>,
+) -> Result<HttpResponse, Error> {
+    let (response, session, mut msg_stream) = actix_ws::handle(&req, stream)?;
+    
+    // 获取客户端IP地址与服务器地址
+    let (client_addr, server_host) = {
+        let connection_info = req.connection_info();
+        (
+            connection_info.peer_addr().unwrap_or("unknown").to_string(),
+            connection_info.host().to_string(),
+        )
+    };
+    
+    // 为新连接创建唯一标识符
+    let id = Uuid::new_v4().to_string();
+    log::info!("New WebSocket connection from {}, session_id: {}", client_addr, &id);
+    if let Some(capture) = &app_state.capture {
+        // 录制TCP连接两端的实际地址，导出pcapng时还原连接
+        // 监听在通配地址时以客户端访问的主机地址为准
+        let mut local = req.app_config().local_addr();
+        if local.ip().is_unspecified() {
+            if let Ok(host) = server_host.parse::<std::net::SocketAddr>() {
+                local = host;
+            }
+        }
+        capture.open(app_state.clock.as_ref(), &id, &client_addr, req.peer_addr(), Some(local));
+    }
+    
+    let user_session = UserSession::new(id.clone(), client_addr, SessionSink::WebSocket(session.clone()), Transport::WebSocket, app_state.clock.as_ref());
+    open_session(&app_state, user_session, &server_host).await;
+    
+    // 在新线程处理消息
+    let app_state_clone = app_state.clone();
+    let id_clone = id.clone();
+    
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                // 处理接收到的WebSocket消息
+                msg = msg_stream.recv() => {
+                    match msg {
+                        Some(Ok(ws_msg)) => {
+                            if !handle_message(ws_msg, &id_clone, &app_state_clone).await {
+                                log::info!("Connection {} message handler returned false, breaking loop", id_clone);
+                                break;
+                            }
+                        }
+                        Some(Err(e)) => {
+                            log::error!("WebSocket error for {}: {:?}", id_clone, e);
+                            break;
+                        }
+                        None => {
+                            log::info!("WebSocket stream ended for {}", id_clone);
+                            break;
+                        }
+                    }
+                }
+                
+                // 按会话的心跳策略定时检查连接状态
+                _ = heartbeat::wait(&app_state_clone, &id_clone) => {
+                    if !heartbeat(&id_clone, &app_state_clone).await {
+                        break;
+                    }
+                }
+            }
+        }
+        
+        // 连接关闭，处理用户离开
+        log::info!("WebSocket handler loop exited for {}, cleaning up", id_clone);
+        handle_disconnect(&id_clone, &app_state_clone).await;
+        
+        // 服务器主动断开（如心跳超时）时发送关闭帧，否则底层连接要等到keep-alive超时才会关闭
+        let _ = session.close(None).await;
+        if let Some(capture) = &app_state_clone.capture {
+            capture.outbound(app_state_clone.clock.as_ref(), &id_clone, capture::Opcode::Close, &[]);
+            capture.close(app_state_clone.clock.as_ref(), &id_clone);
+        }
+    });
+    
+    Ok(response)
+}
+
+// 心跳检查：超时未收到心跳时返回false，否则更新在线状态、安排下一次检查并按会话的心跳策略发送ping，各种传输方式共用
+async fn heartbeat(user_id: &str, app_state: &Arc<AppState>) -> bool {
+    let (mut session, room, presence_changed, mode, ping) = {
+        let mut sessions = app_state.sessions.lock().unwrap();
+        let Some(user_session) = sessions.get_mut(user_id) else {
+            log::warn!("Session {} not found during ping", user_id);
+            return false;
+        };
+        
+        // 超时没有心跳，断开连接并记录原因
+        let now = app_state.clock.now();
+        let silent = app_state.clock.elapsed(user_session.last_heartbeat);
+        let state = &user_session.heartbeat;
+        if let Some(timeout) = state.timeout(&app_state.timeouts).filter(|timeout| silent > *timeout) {
+            let unanswered = state.unanswered + u32::from(user_session.ping_sent.is_some());
+            log::info!(
+                "Client {} timed out: no frames for {:.1}s, over the {:.1}s limit of the {} heartbeat, {} unanswered {} pings",
+                user_id, silent.as_secs_f64(), timeout.as_secs_f64(), state.mode.name(), unanswered, state.ping.name()
+            );
+            app_state.heartbeat_stats.record_timeout(state.mode);
+            if app_state.monitor.active() {
+                let reason = format!(
+                    "{:.1} 秒没有收到心跳，超过 {} 模式 {:.1} 秒的上限，连续 {} 次心跳未响应",
+                    silent.as_secs_f64(), state.mode.name(), timeout.as_secs_f64(), unanswered
+                );
+                app_state.monitor.emit(Event::for_session(
+                    EventKind::Timeout,
+                    app_state.clock.as_ref(),
+                    user_session,
+                    format!("{}，断开连接", reason),
+                    serde_json::json!({
+                        "silent_ms": silent.as_millis() as u64,
+                        "timeout_ms": timeout.as_millis() as u64,
+                        "mode": state.mode,
+                        "ping": state.ping,
+                        "unanswered": unanswered,
+                        "reason": reason,
+                    }),
+                ));
+            }
+            return false;
+        }
+        
+        // 根据最近的主动操作更新在线状态
+        let presence_changed = user_session.refresh_presence(now);
+        let answered = user_session.ping_sent.is_none();
+        let active = app_state.clock.elapsed(user_session.last_activity) < user_session.heartbeat.interval();
+        let rtt = user_session.rtt_ms.map(Duration::from_millis);
+        user_session.heartbeat.schedule(&app_state.heartbeat, now, answered, active, rtt);
+        if user_session.heartbeat.mode != HeartbeatMode::Disabled {
+            user_session.ping_sent = Some(now);
+        }
+        
+        (user_session.session.clone(), user_session.room.clone(), presence_changed, user_session.heartbeat.mode, user_session.heartbeat.ping)
+    };
+    
+    if mode != HeartbeatMode::Disabled {
+        app_state.heartbeat_stats.record_ping(mode);
+        monitor_session(app_state, user_id, EventKind::Heartbeat, |user_session| {
+            let since = app_state.clock.elapsed(user_session.last_heartbeat);
+            let state = &user_session.heartbeat;
+            let timeout = state.timeout(&app_state.timeouts).unwrap_or_default();
+            Some((
+                format!(
+                    "发送心跳{}（{} 模式），距上次收到心跳 {:.1} 秒（间隔 {:.1} 秒，超时 {:.1} 秒）",
+                    if ping == PingKind::Protocol { "Ping帧" } else { "ping" },
+                    mode.name(), since.as_secs_f64(), state.interval().as_secs_f64(), timeout.as_secs_f64()
+                ),
+                serde_json::json!({
+                    "since_heartbeat_ms": since.as_millis() as u64,
+                    "interval_ms": state.interval().as_millis() as u64,
+                    "timeout_ms": timeout.as_millis() as u64,
+                    "mode": mode,
+                    "ping": ping,
+                    "unanswered": state.unanswered,
+                }),
+            ))
+        });
+        
+        let result = match (&mut session, ping) {
+            // 协议层Ping帧的负载为发送时间（微秒），回复的Pong原样带回
+            (SessionSink::WebSocket(ws_session), PingKind::Protocol) => {
+                let payload = app_state.clock.utc().timestamp_micros().to_string();
+                trace_frame(app_state, user_id, false, capture::Opcode::Ping, payload.as_bytes());
+                ws_session.ping(payload.as_bytes()).await
+            }
+            _ => {
+                let ping_msg = ChatMessage {
+                    msg_type: "ping".to_string(),
+                    username: "服务器".to_string(),
+                    room: "".to_string(),
+                    text: "".to_string(),
+                    timestamp: app_state.clock.timestamp(),
+                    id: Uuid::new_v4().to_string(),
+                    target: None,
+                    data: None,
+                };
+                let ping_json = serde_json::to_string(&ping_msg).unwrap();
+                trace_outbound(app_state, user_id, &ping_json);
+                session.text(ping_json).await
+            }
+        };
+        if let Err(e) = result {
+            log::error!("Error sending ping to {}: {:?}", user_id, e);
+            traffic::record_send_error(app_state, user_id);
+            return false;
+        }
+    }
+    
+    if presence_changed {
+        send_user_list_diff(app_state, &room, UserListDiff::Update(user_id.to_string())).await;
+    }
+    true
+}
+
+// 处理接收到的消息
+async fn handle_message(msg: Message, user_id: &str, app_state: &Arc<AppState>) -> bool {
+    match msg {
+        Message::Text(text) => {
+            log::debug!("Received message from {}: {}", user_id, text);
+            trace_frame(app_state, user_id, true, capture::Opcode::Text, text.as_bytes());
+            
+            // 尝试解析为JSON消息
+            match serde_json::from_str::<ChatMessage>(&text) {
+                Ok(chat_msg) => {
+                    if !handle_chat_message(chat_msg, user_id, app_state).await {
+                        return false;
+                    }
+                },
+                Err(e) => {
+                    log::error!("Failed to parse message: {:?}, error: {:?}", text, e);
+                    traffic::record_dropped(app_state, user_id);
+                    
+                    // 发送错误消息给用户
+                    let error_msg = ChatMessage {
+                        msg_type: "system".to_string(),
+                        username: "服务器".to_string(),
+                        room: "".to_string(),
+                        text: "消息格式错误，请检查客户端代码".to_string(),
+                        timestamp: app_state.clock.timestamp(),
+                        id: Uuid::new_v4().to_string(),
+                        target: None,
+                        data: None,
+                    };
+                    
+                    send_message_to_user(&error_msg, user_id, app_state).await;
+                }
+            }
+            
+            true
+        },
+        Message::Close(reason) => {
+            log::info!("Client {} disconnected: {:?}", user_id, reason);
+            // 关闭帧负载为2字节关闭码加原因
+            let payload = reason.map(|reason| {
+                let mut payload = u16::from(reason.code).to_be_bytes().to_vec();
+                payload.extend_from_slice(reason.description.unwrap_or_default().as_bytes());
+                payload
+            }).unwrap_or_default();
+            trace_frame(app_state, user_id, true, capture::Opcode::Close, &payload);
+            false
+        },
+        Message::Ping(bytes) => {
+            // 处理WebSocket协议层Ping
+            trace_frame(app_state, user_id, true, capture::Opcode::Ping, &bytes);
+            let ws_session = {
+                let mut sessions = app_state.sessions.lock().unwrap();
+                sessions.get_mut(user_id).and_then(|user_session| {
+                    user_session.last_heartbeat = app_state.clock.now();
+                    match &user_session.session {
+                        SessionSink::WebSocket(session) => Some(session.clone()),
+                        SessionSink::Channel(_) => None,
+                    }
+                })
+            };
+            if let Some(mut ws_session) = ws_session {
+                trace_frame(app_state, user_id, false, capture::Opcode::Pong, &bytes);
+                if let Err(e) = ws_session.pong(&bytes).await {
+                    log::error!("Error sending pong to {}: {:?}", user_id, e);
+                    traffic::record_send_error(app_state, user_id);
+                    return false;
+                }
+            }
+            true
+        },
+        Message::Pong(bytes) => {
+            // 处理WebSocket协议层Pong
+            trace_frame(app_state, user_id, true, capture::Opcode::Pong, &bytes);
+            record_pong(user_id, app_state).await;
+            true
+        },
+        Message::Binary(bytes) => {
+            // 二进制帧目前只用于吞吐量测试
+            trace_frame(app_state, user_id, true, capture::Opcode::Binary, &bytes);
+            speedtest::record_upload(app_state, user_id, bytes.len());
+            true
+        },
+        Message::Continuation(_) => {
+            // 暂不处理分片消息
+            traffic::record_dropped(app_state, user_id);
+            true
+        },
+        Message::Nop => true,
+    }
+}
+
+// 处理已解析的客户端消息，各种传输方式共用。返回false表示会话已不存在
+async fn handle_chat_message(mut chat_msg: ChatMessage, user_id: &str, app_state: &Arc<AppState>) -> bool {
+    // 声明变量但暂不初始化
+    let current_room;
+    let current_username;
+    let mut renamed = false;
+    let mut entry_changed = false;
+    
+    // 更新会话信息
+    {
+        let mut sessions = app_state.sessions.lock().unwrap();
+        if let Some(user_session) = sessions.get_mut(user_id) {
+            user_session.last_heartbeat = app_state.clock.now();
+            
+            // 心跳以外的消息视为用户主动操作
+            if chat_msg.msg_type != "ping" && chat_msg.msg_type != "pong" {
+                user_session.last_activity = app_state.clock.now();
+                if user_session.presence == Presence::Idle {
+                    user_session.presence = Presence::Online;
+                    entry_changed = true;
+                }
+            }
+            
+            // 如果是第一次设置用户名，处理加入房间
+            if user_session.username == "未命名用户" && chat_msg.username != "未命名用户" {
+                user_session.username = chat_msg.username.clone();
+                renamed = true;
+                entry_changed = true;
+            }
+            
+            current_room = user_session.room.clone();
+            current_username = user_session.username.clone();
+        } else {
+            return false; // 用户会话不存在
+        }
+    }
+    
+    if renamed {
+        let join_msg = ChatMessage {
+            msg_type: "system".to_string(),
+            username: "服务器".to_string(),
+            room: current_room.clone(),
+            text: format!("{} 加入了聊天室", current_username),
+            timestamp: app_state.clock.timestamp(),
+            id: Uuid::new_v4().to_string(),
+            target: None,
+            data: None,
+        };
+        
+        app_state.history.lock().unwrap().record_event(&join_msg);
+        broadcast_message_to_room(&join_msg, &current_room, app_state).await;
+        
+        // 已读标记按用户名保存，设置用户名后发送其未读计数
+        send_unread_counts(app_state, user_id).await;
+    }
+    
+    if entry_changed {
+        send_user_list_diff(app_state, &current_room, UserListDiff::Update(user_id.to_string())).await;
+    }
+    
+    // 根据消息类型处理
+    match chat_msg.msg_type.as_str() {
+        "chat" => {
+            // 修正发送者信息并广播
+            chat_msg.username = current_username;
+            chat_msg.room = current_room.clone();
+            chat_msg.timestamp = app_state.clock.timestamp();
+            
+            // 空消息（如客户端的初始化消息）不计入历史
+            if !chat_msg.text.is_empty() {
+                app_state.history.lock().unwrap().record(&chat_msg);
+            }
+            
+            broadcast_message_to_room(&chat_msg, &current_room, app_state).await;
+            if !chat_msg.text.is_empty() && app_state.webhooks.active() {
+                app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::Message, app_state, &current_room).by(user_id, &chat_msg.username).with_message(&chat_msg));
+            }
+        },
+        "reaction" => {
+            // 处理表情回应：id为被回应消息的ID，text为表情
+            let emoji = chat_msg.text.trim().to_string();
+            let result = app_state.history.lock().unwrap()
+                .add_reaction(&current_room, &chat_msg.id, &emoji, &current_username);
+            
+            match result {
+                Ok(reactions) => {
+                    // 向房间广播该消息最新的回应统计
+                    let reaction_msg = ChatMessage {
+                        msg_type: "reaction".to_string(),
+                        username: current_username,
+                        room: current_room.clone(),
+                        text: emoji,
+                        timestamp: app_state.clock.timestamp(),
+                        id: chat_msg.id.clone(),
+                        target: None,
+                        data: Some(serde_json::json!({
+                            "message_id": chat_msg.id,
+                            "reactions": reactions,
+                        })),
+                    };
+                    
+                    broadcast_message_to_room(&reaction_msg, &current_room, app_state).await;
+                }
+                Err(e) => {
+                    let text = match e {
+                        ReactionError::InvalidEmoji => "无效的表情".to_string(),
+                        ReactionError::MessageNotFound => "消息不存在或已过期".to_string(),
+                        ReactionError::Duplicate => format!("您已经对该消息回应过 {}", emoji),
+                    };
+                    let error_msg = ChatMessage {
+                        msg_type: "system".to_string(),
+                        username: "服务器".to_string(),
+                        room: current_room.clone(),
+                        text,
+                        timestamp: app_state.clock.timestamp(),
+                        id: Uuid::new_v4().to_string(),
+                        target: None,
+                        data: None,
+                    };
+                    
+                    send_message_to_user(&error_msg, user_id, app_state).await;
+                }
+            }
+        },
+        "private" => {
+            // 处理私聊消息
+            if let Some(target_username) = &chat_msg.target {
+                // 修正发送者信息
+                chat_msg.username = current_username.clone();
+                chat_msg.room = current_room.clone(); // 私聊归属于发送方所在房间，用于导出聊天记录
+                chat_msg.timestamp = app_state.clock.timestamp();
+                
+                // 查找目标用户
+                let target_user_id = find_user_by_name(target_username, app_state);
+                
+                // 双方房间位于不同的模拟子网时经路由转发
+                let routing = target_user_id.as_ref()
+                    .and_then(|target_id| user_room(target_id, app_state))
+                    .map(|target_room| app_state.topology.lock().unwrap().route(&current_room, &target_room));
+                
+                match (target_user_id, routing) {
+                    (Some(_), Some(subnet::Routing::Unreachable(source, target))) => {
+                        let error_msg = ChatMessage {
+                            msg_type: "system".to_string(),
+                            username: "服务器".to_string(),
+                            room: current_room.clone(),
+                            text: format!("目标不可达：从子网 {} 到 {} 没有路由", source, target),
+                            timestamp: app_state.clock.timestamp(),
+                            id: Uuid::new_v4().to_string(),
+                            target: None,
+                            data: None,
+                        };
+                        
+                        send_message_to_user(&error_msg, user_id, app_state).await;
+                    }
+                    (Some(target_id), Some(subnet::Routing::Routed(source, hops))) => {
+                        chat_msg.data = Some(subnet::route_data(&source, &hops));
+                        
+                        // 发送方立即收到回显，接收方在逐跳转发后收到
+                        send_message_to_user(&chat_msg, user_id, app_state).await;
+                        actix_web::rt::spawn(subnet::forward(app_state.clone(), chat_msg, user_id.to_string(), target_id, source, hops));
+                    }
+                    (Some(target_id), _) => {
+                        app_state.history.lock().unwrap().record_private(&chat_msg, [user_id, &target_id]);
+                        
+                        // 发送给接收方
+                        send_message_to_user(&chat_msg, &target_id, app_state).await;
+                        
+                        // 也发送给发送方（回显）
+                        send_message_to_user(&chat_msg, user_id, app_state).await;
+                        
+                        log::info!("Private message from {} to {}", current_username, target_username);
+                    }
+                    (None, _) => {
+                        // 用户不存在，发送错误消息
+                        let error_msg = ChatMessage {
+                            msg_type: "system".to_string(),
+                            username: "服务器".to_string(),
+                            room: current_room.clone(),
+                            text: format!("用户 {} 不在线或不存在", target_username),
+                            timestamp: app_state.clock.timestamp(),
+                            id: Uuid::new_v4().to_string(),
+                            target: None,
+                            data: None,
+                        };
+                        
+                        send_message_to_user(&error_msg, user_id, app_state).await;
+                    }
+                }
+            }
+        },
+        "read" => {
+            // 处理已读标记：id为最后看到的消息ID，room为空时使用当前房间
+            let room = if chat_msg.room.is_empty() { current_room.clone() } else { chat_msg.room.clone() };
+            let mark = app_state.history.lock().unwrap().mark_read(&current_username, user_id, &room, &chat_msg.id);
+            
+            match mark {
+                Some(ReadMark::Private { sender, message_id }) => {
+                    // 私聊消息的已读回执发送给原发送方
+                    if let Some(sender_id) = find_user_by_name(&sender, app_state) {
+                        let receipt = ChatMessage {
+                            msg_type: "read".to_string(),
+                            username: current_username.clone(),
+                            room: "".to_string(),
+                            text: "".to_string(),
+                            timestamp: app_state.clock.timestamp(),
+                            id: message_id.clone(),
+                            target: Some(sender),
+                            data: Some(serde_json::json!({
+                                "message_id": message_id,
+                                "reader": current_username,
+                            })),
+                        };
+                        
+                        send_message_to_user(&receipt, &sender_id, app_state).await;
+                    }
+                }
+                Some(ReadMark::Room(_)) => {}
+                None => {
+                    log::debug!("Read marker from {} references unknown message {}", user_id, chat_msg.id);
+                }
+            }
+            
+            send_unread_counts(app_state, user_id).await;
+        },
+        "ping" => {
+            // 处理客户端ping请求，直接回复pong消息
+            let pong_msg = ChatMessage {
+                msg_type: "pong".to_string(),
+                username: "服务器".to_string(),
+                room: "".to_string(),
+                text: chat_msg.text, // 返回相同的内容，客户端可用于计算延迟
+                timestamp: app_state.clock.timestamp(),
+                id: Uuid::new_v4().to_string(),
+                target: None,
+                data: None,
+            };
+            send_message_to_user(&pong_msg, user_id, app_state).await;
+        },
+        "pong" => {
+            // 处理客户端的pong响应
+            record_pong(user_id, app_state).await;
+        },
+        "heartbeat" => {
+            // 协商本会话的心跳策略，data为空时只查询当前策略
+            let request = chat_msg.data.clone()
+                .map(serde_json::from_value::<heartbeat::Request>)
+                .unwrap_or_else(|| Ok(heartbeat::Request::default()));
+            let reply = match request {
+                Ok(request) => heartbeat::negotiate(app_state, user_id, request),
+                Err(e) => Err(format!("心跳协商请求格式错误: {}", e)),
+            };
+            let reply_msg = match reply {
+                Ok((text, data)) => ChatMessage {
+                    msg_type: "heartbeat".to_string(),
+                    username: "服务器".to_string(),
+                    room: "".to_string(),
+                    text,
+                    timestamp: app_state.clock.timestamp(),
+                    id: Uuid::new_v4().to_string(),
+                    target: None,
+                    data: Some(data),
+                },
+                Err(text) => ChatMessage {
+                    msg_type: "system".to_string(),
+                    username: "服务器".to_string(),
+                    room: current_room.clone(),
+                    text,
+                    timestamp: app_state.clock.timestamp(),
+                    id: Uuid::new_v4().to_string(),
+                    target: None,
+                    data: None,
+                },
+            };
+            if !reply_msg.text.is_empty() {
+                send_message_to_user(&reply_msg, user_id, app_state).await;
+            }
+        },
+        "speedtest" => {
+            // 吞吐量测试的数据帧、确认与结束消息
+            speedtest::handle(app_state, user_id, &chat_msg).await;
+        },
+        "join" => {
+            // 处理用户加入/创建房间请求
+            if !chat_msg.room.is_empty() {
+                let new_room = chat_msg.room.clone();
+                join_room(user_id, &new_room, app_state).await;
+            }
+        },
+        "command" => {
+            // 处理命令消息
+            let response = handle_command(chat_msg.text.clone(), user_id, app_state).await;
+            
+            // 发送命令响应
+            if !response.is_empty() {
+                let cmd_response = ChatMessage {
+                    msg_type: "system".to_string(),
+                    username: "服务器".to_string(),
+                    room: current_room,
+                    text: response,
+                    timestamp: app_state.clock.timestamp(),
+                    id: Uuid::new_v4().to_string(),
+                    target: None,
+                    data: None,
+                };
+                
+                send_message_to_user(&cmd_response, user_id, app_state).await;
+            }
+        },
+        _ => {
+            log::warn!("Unknown message type: {}", chat_msg.msg_type);
+        }
+    }
+    
+    true
+}
+
+// 记录心跳响应，并根据上一次ping的发送时间计算RTT
+async fn record_pong(user_id: &str, app_state: &Arc<AppState>) {
+    let measured = {
+        let mut sessions = app_state.sessions.lock().unwrap();
+        sessions.get_mut(user_id).and_then(|user_session| {
+            user_session.last_heartbeat = app_state.clock.now();
+            user_session.ping_sent.take().map(|sent| {
+                let rtt_ms = app_state.clock.elapsed(sent).as_millis() as u64;
+                user_session.rtt_ms = Some(rtt_ms);
+                (user_session.room.clone(), rtt_ms)
+            })
+        })
+    };
+    
+    monitor_session(app_state, user_id, EventKind::Pong, |_| Some(match measured {
+        Some((_, rtt_ms)) => (format!("收到心跳响应，RTT {} 毫秒", rtt_ms), serde_json::json!({ "rtt_ms": rtt_ms })),
+        None => ("收到心跳响应".to_string(), serde_json::json!({ "rtt_ms": null })),
+    }));
+    if let Some((room, _)) = measured {
+        send_user_list_diff(app_state, &room, UserListDiff::Update(user_id.to_string())).await;
+    }
+}
+
+// 新增加入房间的独立函数，确保创建房间逻辑统一
+async fn join_room(user_id: &str, new_room: &str, app_state: &Arc<AppState>) {
+    let username;
+    let old_room;
+    
+    {
+        let mut sessions = app_state.sessions.lock().unwrap();
+        
+        if let Some(user_session) = sessions.get_mut(user_id) {
+            username = user_session.username.clone();
+            old_room = user_session.room.clone();
+            
+            // 不在该房间时才更新用户房间
+            if old_room != new_room {
+                user_session.room = new_room.to_string();
+            }
+        } else {
+            return;
+        }
+    }
+    
+    // 检查是否已经在该房间
+    if old_room == new_room {
+        let already_msg = ChatMessage {
+            msg_type: "system".to_string(),
+            username: "服务器".to_string(),
+            room: old_room.clone(),
+            text: format!("您已经在房间 {} 中", new_room),
+            timestamp: app_state.clock.timestamp(),
+            id: Uuid::new_v4().to_string(),
+            target: None,
+            data: None,
+        };
+        
+        send_message_to_user(&already_msg, user_id, app_state).await;
+        return;
+    }
+    
+    // 从旧房间移除用户
+    {
+        let mut rooms = app_state.rooms.lock().unwrap();
+        if let Some(room_users) = rooms.get_mut(&old_room) {
+            room_users.remove(user_id);
+        }
+    }
+    
+    // 发送离开消息到旧房间
+    let leave_msg = ChatMessage {
+        msg_type: "system".to_string(),
+        username: "服务器".to_string(),
+        room: old_room.clone(),
+        text: format!("{} 离开了房间", username),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: None,
+    };
+    
+    app_state.history.lock().unwrap().record_event(&leave_msg);
+    broadcast_message_to_room(&leave_msg, &old_room, app_state).await;
+    if app_state.webhooks.active() {
+        app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::Leave, app_state, &old_room).by(user_id, &username));
+    }
+    
+    // 将用户添加到新房间
+    let created = {
+        let mut rooms = app_state.rooms.lock().unwrap();
+        let created = !rooms.contains_key(new_room);
+        rooms.entry(new_room.to_string())
+             .or_default()
+             .insert(user_id.to_string());
+        created
+    };
+    if created && app_state.webhooks.active() {
+        app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::RoomCreated, app_state, new_room).by(user_id, &username));
+    }
+    
+    // 发送加入消息到新房间
+    let join_msg = ChatMessage {
+        msg_type: "system".to_string(),
+        username: "服务器".to_string(),
+        room: new_room.to_string(),
+        text: format!("{} 加入了房间", username),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: None,
+    };
+    
+    app_state.history.lock().unwrap().record_event(&join_msg);
+    broadcast_message_to_room(&join_msg, new_room, app_state).await;
+    if app_state.webhooks.active() {
+        app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::Join, app_state, new_room).by(user_id, &username));
+    }
+    
+    // 更新两个房间的用户列表
+    send_user_list_diff(app_state, &old_room, UserListDiff::Leave(user_id.to_string())).await;
+    send_user_list(app_state, new_room, user_id).await;
+    send_user_list_diff(app_state, new_room, UserListDiff::Join(user_id.to_string())).await;
+    send_history(app_state, new_room, user_id).await;
+    
+    log::info!("User {} moved from room {} to room {}", username, old_room, new_room);
+}
+
+// 处理用户断开连接
+async fn handle_disconnect(user_id: &str, app_state: &Arc<AppState>) {
+    let username;
+    let room;
+    let traffic;
+    let mut emptied = false;
+    
+    // 获取用户信息并从会话中移除
+    {
+        let mut sessions = app_state.sessions.lock().unwrap();
+        if let Some(user_session) = sessions.remove(user_id) {
+            log::info!("User {} was online for {}s", user_session.username, app_state.clock.elapsed(user_session.join_time).as_secs());
+            if app_state.monitor.active() {
+                let online = app_state.clock.elapsed(user_session.join_time);
+                app_state.monitor.emit(Event::for_session(
+                    EventKind::Close,
+                    app_state.clock.as_ref(),
+                    &user_session,
+                    format!("{} 连接关闭，在线 {:.1} 秒", user_session.transport.name(), online.as_secs_f64()),
+                    serde_json::json!({ "transport": user_session.transport, "online_ms": online.as_millis() as u64 }),
+                ));
+            }
+            app_state.departures.lock().unwrap().depart(&user_session.addr, app_state.clock.now(), user_session.traffic.reconnects);
+            username = user_session.username;
+            room = user_session.room;
+            traffic = user_session.traffic;
+            
+            // 从房间中移除用户
+            let mut rooms = app_state.rooms.lock().unwrap();
+            if let Some(room_users) = rooms.get_mut(&room) {
+                room_users.remove(user_id);
+                emptied = room_users.is_empty();
+                // 如果房间为空且非大厅，则移除房间
+                if room != "大厅" && room_users.is_empty() {
+                    rooms.remove(&room);
+                }
+            }
+        } else {
+            return;
+        }
+    }
+    
+    // 通知其他用户
+    if username != "未命名用户" {
+        let leave_msg = ChatMessage {
+            msg_type: "system".to_string(),
+            username: "服务器".to_string(),
+            room: room.clone(),
+            text: format!("{} 离开了聊天室", username),
+            timestamp: app_state.clock.timestamp(),
+            id: Uuid::new_v4().to_string(),
+            target: None,
+            data: None,
+        };
+        
+        app_state.history.lock().unwrap().record_event(&leave_msg);
+        broadcast_message_to_room(&leave_msg, &room, app_state).await;
+        
+        // 更新用户列表
+        send_user_list_diff(app_state, &room, UserListDiff::Leave(user_id.to_string())).await;
+    }
+    
+    if app_state.webhooks.active() {
+        app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::Leave, app_state, &room).by(user_id, &username));
+        if emptied {
+            app_state.webhooks.emit(webhook::Event::new(webhook::EventKind::RoomEmptied, app_state, &room));
+        }
+    }
+    
+    log::info!("Connection closed for {} ({})", user_id, username);
+    log::info!("Traffic for {} ({}): {}", user_id, username, traffic.summary());
+}
+
+// 向指定房间广播消息，同时转发给该房间有成员的其他节点
+async fn broadcast_message_to_room(message: &ChatMessage, room: &str, app_state: &Arc<AppState>) {
+    log::info!("Broadcasting to room {}: type={}, from={}, text={}", 
+               room, message.msg_type, message.username, 
+               if message.text.chars().count() > 30 { format!("{}...", message.text.chars().take(30).collect::<String>()) } else { message.text.clone() });
+    
+    let message_json = match serde_json::to_string(message) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Failed to serialize message: {:?}", e);
+            return;
+        }
+    };
+    
+    let bytes = message_json.len();
+    let forwarded = app_state.cluster.forward_room(room, message);
+    let delivered = deliver_to_room(app_state, room, message_json).await;
+    if delivered == 0 && forwarded == 0 {
+        log::warn!("No users in room {}, message not delivered", room);
+    }
+    
+    if app_state.monitor.active() {
+        let mut event = Event::new(
+            EventKind::Route,
+            app_state.clock.as_ref(),
+            format!("房间 {} 的 {} 消息（{} 字节）投递给本节点 {} 名用户，转发到 {} 个节点", room, message.msg_type, bytes, delivered, forwarded),
+            serde_json::json!({ "msg_type": message.msg_type, "from": message.username, "bytes": bytes, "local_recipients": delivered, "forwarded_nodes": forwarded }),
+        );
+        event.user = Some(message.username.clone());
+        event.room = Some(room.to_string());
+        app_state.monitor.emit(event);
+    }
+}
+
+// 把消息发送给本节点上位于指定房间的用户，返回接收者数量
+async fn deliver_to_room(app_state: &Arc<AppState>, room: &str, message_json: String) -> usize {
+    let user_ids = {
+        let rooms = app_state.rooms.lock().unwrap();
+        match rooms.get(room) {
+            Some(user_set) => {
+                let users = user_set.clone();
+                log::debug!("Room {} has {} local users: {:?}", room, users.len(), &users);
+                users
+            },
+            None => return 0,
+        }
+    };
+    
+    // 先复制出各用户的会话句柄，避免在发送（await）期间持有锁
+    let recipients: Vec<(String, String, SessionSink)> = {
+        let sessions = app_state.sessions.lock().unwrap();
+        user_ids.into_iter()
+            .filter_map(|user_id| match sessions.get(&user_id) {
+                Some(user_session) => Some((user_id, user_session.username.clone(), user_session.session.clone())),
+                None => {
+                    log::warn!("User {} not found in sessions", user_id);
+                    None
+                }
+            })
+            .collect()
+    };
+    
+    let count = recipients.len();
+    for (user_id, username, mut session) in recipients {
+        log::debug!("Sending to user {} in room {}", username, room);
+        trace_outbound(app_state, &user_id, &message_json);
+        if let Err(e) = session.text(message_json.clone()).await {
+            log::error!("Error sending message to {}: {:?}", user_id, e);
+            traffic::record_send_error(app_state, &user_id);
+        } else {
+            log::debug!("Message sent successfully to user {}", username);
+        }
+    }
+    count
+}
+
+// 发送消息给特定用户
+async fn send_message_to_user(message: &ChatMessage, user_id: &str, app_state: &Arc<AppState>) {
+    log::debug!("Sending to user {}: {:?}", user_id, message);
+    
+    let message_json = match serde_json::to_string(message) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Failed to serialize message: {:?}", e);
+            return;
+        }
+    };
+    
+    let session = app_state.sessions.lock().unwrap()
+        .get(user_id)
+        .map(|user_session| user_session.session.clone());
+    match session {
+        Some(mut session) => {
+            trace_outbound(app_state, user_id, &message_json);
+            if let Err(e) = session.text(message_json).await {
+                log::error!("Error sending message to {}: {:?}", user_id, e);
+                traffic::record_send_error(app_state, user_id);
+            }
+        }
+        // 不是本节点的会话时转发给它所在的节点
+        None => {
+            app_state.cluster.forward_user(user_id, message);
+        }
+    }
+}
+
+// 向指定用户发送房间的完整用户列表，地址信息仅对管理员可见
+async fn send_user_list(app_state: &Arc<AppState>, room: &str, user_id: &str) {
+    let user_list = {
+        let sessions = app_state.sessions.lock().unwrap();
+        let rooms = app_state.rooms.lock().unwrap();
+        
+        let with_addr = sessions.get(user_id).is_some_and(|s| s.role == Role::Admin);
+        let mut users = rooms.get(room)
+            .map(|user_ids| {
+                user_ids.iter()
+                    .filter_map(|uid| sessions.get(uid))
+                    .map(|user_session| UserListEntry::new(user_session, with_addr))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        
+        // 其他节点上同一房间的成员
+        for mut entry in app_state.cluster.room_users(room) {
+            if !with_addr {
+                entry.addr = None;
+            }
+            users.push(entry);
+        }
+        users
+    };
+    
+    let user_list_msg = ChatMessage {
+        msg_type: "userlist".to_string(),
+        username: "服务器".to_string(),
+        room: room.to_string(),
+        text: "".to_string(),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: Some(serde_json::json!({ "op": "snapshot", "users": user_list })),
+    };
+    
+    send_message_to_user(&user_list_msg, user_id, app_state).await;
+}
+
+// 本节点会话的用户列表变化：发送给房间内的用户，并同步给集群中的其他节点。加入的用户本身会单独收到完整列表
+async fn send_user_list_diff(app_state: &Arc<AppState>, room: &str, diff: UserListDiff) {
+    let (id, op, entry) = {
+        let sessions = app_state.sessions.lock().unwrap();
+        match &diff {
+            UserListDiff::Join(id) | UserListDiff::Update(id) => {
+                let Some(user_session) = sessions.get(id) else {
+                    return;
+                };
+                app_state.cluster.publish_session(user_session);
+                let op = if matches!(diff, UserListDiff::Join(_)) { "join" } else { "update" };
+                (id, op, Some(UserListEntry::new(user_session, true)))
+            }
+            UserListDiff::Leave(id) => {
+                // 换房间时会话仍然存在，随后的加入会同步新的房间
+                if !sessions.contains_key(id) {
+                    app_state.cluster.publish_removal(id);
+                }
+                (id, "leave", None)
+            }
+        }
+    };
+    
+    deliver_user_list_diff(app_state, room, id, op, entry).await;
+}
+
+// 向本节点房间内的用户发送用户列表增量。entry 为变化后的成员信息（离开时为None），地址只发给管理员
+async fn deliver_user_list_diff(app_state: &Arc<AppState>, room: &str, id: &str, op: &str, entry: Option<UserListEntry>) {
+    let (public_json, admin_json, recipients) = {
+        let sessions = app_state.sessions.lock().unwrap();
+        let rooms = app_state.rooms.lock().unwrap();
+        
+        let Some(user_ids) = rooms.get(room) else {
+            return;
+        };
+        
+        // 普通用户与管理员分别生成一份负载，只有后者带有地址
+        let (public_data, admin_data) = match entry {
+            Some(entry) => {
+                let public_entry = UserListEntry { addr: None, ..entry.clone() };
+                (
+                    serde_json::json!({ "op": op, "user": public_entry }),
+                    serde_json::json!({ "op": op, "user": entry }),
+                )
+            }
+            None => {
+                let data = serde_json::json!({ "op": op, "id": id });
+                (data.clone(), data)
+            }
+        };
+        let skip_id = (op == "join").then_some(id);
+        
+        let to_json = |data: serde_json::Value| {
+            serde_json::to_string(&ChatMessage {
+                msg_type: "userlist".to_string(),
+                username: "服务器".to_string(),
+                room: room.to_string(),
+                text: "".to_string(),
+                timestamp: app_state.clock.timestamp(),
+                id: Uuid::new_v4().to_string(),
+                target: None,
+                data: Some(data),
+            })
+        };
+        let (public_json, admin_json) = match (to_json(public_data), to_json(admin_data)) {
+            (Ok(public_json), Ok(admin_json)) => (public_json, admin_json),
+            (Err(e), _) | (_, Err(e)) => {
+                log::error!("Failed to serialize user list diff: {:?}", e);
+                return;
+            }
+        };
+        
+        let recipients: Vec<(String, bool, SessionSink)> = user_ids.iter()
+            .filter(|uid| Some(uid.as_str()) != skip_id)
+            .filter_map(|uid| sessions.get(uid))
+            .map(|user_session| (user_session.id.clone(), user_session.role == Role::Admin, user_session.session.clone()))
+            .collect();
+        (public_json, admin_json, recipients)
+    };
+    
+    for (uid, is_admin, mut session) in recipients {
+        let message_json = if is_admin { admin_json.clone() } else { public_json.clone() };
+        trace_outbound(app_state, &uid, &message_json);
+        if let Err(e) = session.text(message_json).await {
+            log::error!("Error sending user list diff to {}: {:?}", uid, e);
+            traffic::record_send_error(app_state, &uid);
+        }
+    }
+}
+
+// 向指定用户回放房间的历史消息（含表情回应）
+async fn send_history(app_state: &Arc<AppState>, room: &str, user_id: &str) {
+    let messages = app_state.history.lock().unwrap().replay(room);
+    
+    let history_msg = ChatMessage {
+        msg_type: "history".to_string(),
+        username: "服务器".to_string(),
+        room: room.to_string(),
+        text: "".to_string(),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: Some(serde_json::json!({ "messages": messages })),
+    };
+    
+    send_message_to_user(&history_msg, user_id, app_state).await;
+}
+
+// 向指定用户发送各房间与私聊会话的未读消息数
+async fn send_unread_counts(app_state: &Arc<AppState>, user_id: &str) {
+    let (username, room) = match app_state.sessions.lock().unwrap().get(user_id) {
+        Some(user_session) => (user_session.username.clone(), user_session.room.clone()),
+        None => return,
+    };
+    let counts = app_state.history.lock().unwrap().unread_counts(&username, user_id, &room);
+    
+    let unread_msg = ChatMessage {
+        msg_type: "unread".to_string(),
+        username: "服务器".to_string(),
+        room,
+        text: "".to_string(),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: Some(serde_json::json!(counts)),
+    };
+    
+    send_message_to_user(&unread_msg, user_id, app_state).await;
+}
+
+// 检查HTTP请求是否携带管理员口令（Authorization: Bearer <口令>）
+fn is_admin_request(req: &HttpRequest, app_state: &AppState) -> bool {
+    let Some(admin_token) = &app_state.admin_token else {
+        return false;
+    };
+    req.headers().get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| token == admin_token)
+}
+
+// 搜索接口的查询参数
+#[derive(Deserialize)]
+struct SearchParams {
+    q: Option<String>,
+    room: Option<String>,
+    from: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+}
+
+// 搜索历史消息的REST接口。匿名请求只能搜索房间消息，携带管理员口令时包含私聊消息
+async fn search_route(
+    req: HttpRequest,
+    params: web::Query<SearchParams>,
+    app_state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    let params = params.into_inner();
+    let parse = |value: Option<String>| match value {
+        Some(value) => search::parse_date(&value).map(Some).ok_or(value),
+        None => Ok(None),
+    };
+    let (before, after) = match (parse(params.before), parse(params.after)) {
+        (Ok(before), Ok(after)) => (before, after),
+        (Err(value), _) | (_, Err(value)) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("无法识别的日期: {}", value) }));
+        }
+    };
+    
+    let mut query = SearchQuery {
+        terms: params.q.unwrap_or_default().split_whitespace().map(str::to_string).collect(),
+        room: params.room.filter(|room| !room.is_empty()),
+        from: params.from.filter(|from| !from.is_empty()),
+        before,
+        after,
+        page: params.page.unwrap_or(1),
+        per_page: params.per_page.unwrap_or(20),
+    };
+    query.clamp_paging();
+    if query.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "至少需要提供 q、room 或 from 之一" }));
+    }
+    
+    let viewer = if is_admin_request(&req, &app_state) { Viewer::Admin } else { Viewer::Anonymous };
+    let page = app_state.history.lock().unwrap().search(&query, viewer);
+    HttpResponse::Ok().json(page)
+}
+
+// 导出接口的查询参数
+#[derive(Deserialize)]
+struct ExportParams {
+    format: Option<String>,
+    after: Option<String>,
+    before: Option<String>,
+}
+
+// 以文件形式导出房间聊天记录。REST请求不属于任何会话，所以不包含私聊消息（携带管理员口令时也一样）
+async fn export_route(
+    room: web::Path<String>,
+    params: web::Query<ExportParams>,
+    app_state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    let room = room.into_inner();
+    let params = params.into_inner();
+    
+    let format = match params.format.as_deref() {
+        None => ExportFormat::Html,
+        Some(value) => match ExportFormat::parse(value) {
+            Some(format) => format,
+            None => return HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("不支持的导出格式: {}", value) })),
+        },
+    };
+    let parse = |value: Option<String>| match value {
+        Some(value) => search::parse_date(&value).map(Some).ok_or(value),
+        None => Ok(None),
+    };
+    let (after, before) = match (parse(params.after), parse(params.before)) {
+        (Ok(after), Ok(before)) => (after, before),
+        (Err(value), _) | (_, Err(value)) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("无法识别的日期: {}", value) }));
+        }
+    };
+    
+    let messages = app_state.history.lock().unwrap().transcript(&room, after, before, Viewer::Anonymous);
+    if messages.is_empty() && !app_state.rooms.lock().unwrap().contains_key(&room) && !app_state.history.lock().unwrap().has_room(&room) {
+        return HttpResponse::NotFound().json(serde_json::json!({ "error": format!("房间 {} 不存在", room) }));
+    }
+    
+    // 中文文件名通过 filename* 传递，filename 中保留ASCII的回退名称
+    let filename = export::filename(&room, format);
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![
+            DispositionParam::Filename(format!("chat-export.{}", format.extension())),
+            DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_string()),
+                language_tag: None,
+                value: filename.into_bytes(),
+            }),
+        ],
+    };
+    
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(disposition)
+        .body(export::render(format, &room, &messages, app_state.clock.timestamp()))
+}
+
+// 处理命令，由注册表查找并执行
+async fn handle_command(command: String, user_id: &str, app_state: &Arc<AppState>) -> String {
+    app_state.commands.dispatch(&command, user_id, app_state).await
+}
@@ -0,0 +1,244 @@
+// 协议事件的实时流，供课堂演示：管理员订阅 /api/monitor（SSE）后，服务器把帧的收发、心跳、超时、
+// 陈旧连接清理与房间广播的路由决策逐条推送出来，每条事件附带一句说明，可按房间、用户或事件类型过滤
+use crate::clock::Clock;
+use crate::{is_admin_request, AppState, UserSession};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// 没有事件时发送注释行的间隔，避免代理因超时断开
+const KEEPALIVE: Duration = Duration::from_secs(15);
+// 每个订阅者最多积压的事件数，超过时视为订阅者已停滞并断开
+const SUBSCRIBER_BUFFER: usize = 1024;
+// 订阅凭证的有效期，凭证只能使用一次
+const TICKET_TTL: Duration = Duration::from_secs(30);
+
+// 事件类型
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum EventKind {
+    Open,      // 会话建立
+    Close,     // 会话结束
+    Frame,     // WebSocket帧的收发
+    Heartbeat, // 服务器发送心跳ping
+    Pong,      // 收到心跳响应
+    Timeout,   // 心跳超时断开
+    Stale,     // 同IP新连接清理陈旧连接
+    Route,     // 房间广播的投递与转发
+}
+
+impl EventKind {
+    const ALL: [EventKind; 8] = [
+        EventKind::Open,
+        EventKind::Close,
+        EventKind::Frame,
+        EventKind::Heartbeat,
+        EventKind::Pong,
+        EventKind::Timeout,
+        EventKind::Stale,
+        EventKind::Route,
+    ];
+    
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Open => "open",
+            EventKind::Close => "close",
+            EventKind::Frame => "frame",
+            EventKind::Heartbeat => "heartbeat",
+            EventKind::Pong => "pong",
+            EventKind::Timeout => "timeout",
+            EventKind::Stale => "stale",
+            EventKind::Route => "route",
+        }
+    }
+}
+
+// 推送给订阅者的一条事件
+#[derive(Serialize)]
+pub(crate) struct Event {
+    event: EventKind,
+    timestamp_ms: i64, // Unix时间（毫秒）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) session: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) room: Option<String>,
+    summary: String,         // 给人看的说明
+    data: serde_json::Value, // 各类事件的具体数值
+}
+
+impl Event {
+    pub(crate) fn new(event: EventKind, clock: &dyn Clock, summary: String, data: serde_json::Value) -> Event {
+        Event {
+            event,
+            timestamp_ms: clock.utc().timestamp_millis(),
+            session: None,
+            user: None,
+            room: None,
+            summary,
+            data,
+        }
+    }
+    
+    // 与某个会话相关的事件，带上会话ID、用户名与所在房间
+    pub(crate) fn for_session(event: EventKind, clock: &dyn Clock, user_session: &UserSession, summary: String, data: serde_json::Value) -> Event {
+        Event {
+            session: Some(user_session.id.clone()),
+            user: Some(user_session.username.clone()),
+            room: Some(user_session.room.clone()),
+            ..Event::new(event, clock, summary, data)
+        }
+    }
+}
+
+// 订阅时的过滤条件，未指定的条件不过滤
+#[derive(Deserialize, Serialize, Default)]
+pub(crate) struct Filter {
+    room: Option<String>,
+    user: Option<String>, // 用户名或会话ID
+    #[serde(rename = "event")]
+    events: Option<String>, // 逗号分隔的事件类型
+}
+
+impl Filter {
+    fn matches(&self, event: &Event) -> bool {
+        let room_ok = self.room.as_ref().is_none_or(|room| event.room.as_ref() == Some(room));
+        let user_ok = self.user.as_ref().is_none_or(|user| {
+            event.user.as_ref() == Some(user) || event.session.as_ref() == Some(user)
+        });
+        let event_ok = self.events.as_ref().is_none_or(|events| {
+            events.split(',').any(|name| name.trim() == event.event.name())
+        });
+        room_ok && user_ok && event_ok
+    }
+}
+
+struct Subscriber {
+    filter: Filter,
+    sender: mpsc::Sender<String>,
+}
+
+// 订阅者列表，在所有其他锁之后加锁。没有订阅者时各处跳过事件的构造
+#[derive(Default)]
+pub(crate) struct Monitor {
+    subscribers: Mutex<Vec<Subscriber>>,
+    count: AtomicUsize,
+    tickets: Mutex<HashMap<String, Instant>>, // 订阅凭证 -> 签发时间，单独加锁
+}
+
+impl Monitor {
+    pub(crate) fn active(&self) -> bool {
+        self.count.load(Ordering::Relaxed) > 0
+    }
+    
+    fn subscribe(&self, filter: Filter) -> mpsc::Receiver<String> {
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.push(Subscriber { filter, sender });
+        self.count.store(subscribers.len(), Ordering::Relaxed);
+        receiver
+    }
+    
+    // 发送给过滤条件匹配的订阅者，顺便移除已断开的订阅
+    pub(crate) fn emit(&self, event: Event) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut json = None;
+        subscribers.retain(|subscriber| {
+            if subscriber.sender.is_closed() {
+                return false;
+            }
+            if !subscriber.filter.matches(&event) {
+                return true;
+            }
+            let json = json.get_or_insert_with(|| serde_json::to_string(&event).unwrap_or_default());
+            match subscriber.sender.try_send(json.clone()) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    log::warn!("Dropping protocol monitor subscriber with {} undelivered events", SUBSCRIBER_BUFFER);
+                    false
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => false,
+            }
+        });
+        self.count.store(subscribers.len(), Ordering::Relaxed);
+    }
+    
+    // 签发一次性订阅凭证，顺便清理过期的凭证
+    fn issue_ticket(&self, clock: &dyn Clock) -> String {
+        let ticket = Uuid::new_v4().simple().to_string();
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, issued| clock.elapsed(*issued) < TICKET_TTL);
+        tickets.insert(ticket.clone(), clock.now());
+        ticket
+    }
+    
+    // 使用凭证，凭证不存在、已使用或已过期时返回false
+    fn redeem_ticket(&self, ticket: &str, clock: &dyn Clock) -> bool {
+        let issued = self.tickets.lock().unwrap().remove(ticket);
+        issued.is_some_and(|issued| clock.elapsed(issued) < TICKET_TTL)
+    }
+}
+
+// 订阅接口的查询参数。浏览器的 EventSource 无法设置请求头，可以先用口令换取一次性凭证，
+// 再通过 ticket 参数传递，避免口令出现在URL和访问日志中
+#[derive(Deserialize)]
+pub(crate) struct MonitorParams {
+    ticket: Option<String>,
+    #[serde(flatten)]
+    filter: Filter,
+}
+
+// 签发订阅凭证，需要管理员口令
+pub(crate) async fn ticket_route(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    if !is_admin_request(&req, &app_state) {
+        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "需要管理员口令" }));
+    }
+    let ticket = app_state.monitor.issue_ticket(app_state.clock.as_ref());
+    HttpResponse::Ok().json(serde_json::json!({ "ticket": ticket, "expires_in": TICKET_TTL.as_secs() }))
+}
+
+// 订阅协议事件：第一个事件为 subscribed（回显过滤条件），之后每个事件的data为一条事件JSON
+pub(crate) async fn monitor_route(
+    req: HttpRequest,
+    params: web::Query<MonitorParams>,
+    app_state: web::Data<Arc<AppState>>,
+) -> HttpResponse {
+    let params = params.into_inner();
+    let ticket_ok = || params.ticket.as_deref().is_some_and(|ticket| app_state.monitor.redeem_ticket(ticket, app_state.clock.as_ref()));
+    if !is_admin_request(&req, &app_state) && !ticket_ok() {
+        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "需要管理员口令" }));
+    }
+    
+    if let Some(events) = &params.filter.events {
+        if let Some(unknown) = events.split(',').map(str::trim).find(|name| EventKind::ALL.iter().all(|kind| kind.name() != *name)) {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("未知的事件类型: {}", unknown) }));
+        }
+    }
+    
+    log::info!("Protocol monitor subscribed from {}", req.connection_info().peer_addr().unwrap_or("unknown"));
+    let first = format!("event: subscribed\ndata: {}\n\n", serde_json::to_string(&params.filter).unwrap_or_default());
+    let receiver = app_state.monitor.subscribe(params.filter);
+    let stream = futures_util::stream::unfold((Some(first), receiver), |(first, mut receiver)| async move {
+        if let Some(first) = first {
+            return Some((Ok::<_, actix_web::Error>(web::Bytes::from(first)), (None, receiver)));
+        }
+        let chunk = match actix_web::rt::time::timeout(KEEPALIVE, receiver.recv()).await {
+            Ok(Some(json)) => format!("data: {}\n\n", json),
+            Ok(None) => return None,
+            Err(_) => ": keepalive\n\n".to_string(),
+        };
+        Some((Ok(web::Bytes::from(chunk)), (None, receiver)))
+    });
+    
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(stream)
+}
@@ -0,0 +1,303 @@
+// 把录制的WebSocket会话导出为pcapng，方便在Wireshark中查看服务器视角的流量。
+// 录制文件只有WebSocket帧，导出时按录制的套接字地址合成IP与TCP头：每个会话依次为三次握手、
+// HTTP升级请求与101响应、按RFC 6455编码的数据帧（客户端发出的帧带掩码），会话结束时四次挥手。
+// 使用TLS时导出的仍是解密后的明文
+use crate::capture::{Kind, Opcode, Record};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+// 链路类型：不带链路层头的IPv4/IPv6数据包
+const LINKTYPE_RAW: u16 = 101;
+// 单个TCP段的最大负载，较长的帧分成多个段
+const MSS: usize = 1460;
+// RFC 6455 示例中的握手密钥及对应的应答
+const WS_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const WS_ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+// 较早的录制文件没有套接字地址时使用的服务器地址
+const DEFAULT_SERVER: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8080);
+
+const SYN: u8 = 0x02;
+const FIN: u8 = 0x01;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+// 一个会话合成的TCP连接，序号为下一个要发送的字节
+struct Connection {
+    client: SocketAddr,
+    server: SocketAddr,
+    client_seq: u32,
+    server_seq: u32,
+    frames: u32, // 客户端已发送的帧数，用于生成掩码
+}
+
+struct PcapWriter<W: Write> {
+    out: W,
+    start_micros: i64,
+    packets: usize,
+}
+
+impl<W: Write> PcapWriter<W> {
+    fn new(mut out: W, start_micros: i64) -> io::Result<Self> {
+        // 节头块：字节序标记、版本1.0、节长度未知
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1a2b3c4du32.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut out, 0x0a0d0d0a, &shb)?;
+        
+        // 接口描述块：时间戳默认精度为微秒，抓包长度不限
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut out, 1, &idb)?;
+        
+        Ok(PcapWriter { out, start_micros, packets: 0 })
+    }
+    
+    // 增强数据包块
+    fn packet(&mut self, offset: Duration, data: &[u8]) -> io::Result<()> {
+        let timestamp = (self.start_micros + offset.as_micros() as i64) as u64;
+        let mut epb = Vec::with_capacity(20 + data.len() + 3);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(data);
+        epb.resize(epb.len().next_multiple_of(4), 0);
+        write_block(&mut self.out, 6, &epb)?;
+        self.packets += 1;
+        Ok(())
+    }
+    
+    // 发送一段数据，超过MSS时分成多个段，发送方序号随之推进
+    fn send(&mut self, offset: Duration, conn: &mut Connection, from_client: bool, payload: &[u8]) -> io::Result<()> {
+        for chunk in payload.chunks(MSS) {
+            self.segment(offset, conn, from_client, PSH | ACK, chunk)?;
+        }
+        Ok(())
+    }
+    
+    fn segment(&mut self, offset: Duration, conn: &mut Connection, from_client: bool, flags: u8, payload: &[u8]) -> io::Result<()> {
+        let (src, dst, seq, ack) = if from_client {
+            (conn.client, conn.server, conn.client_seq, conn.server_seq)
+        } else {
+            (conn.server, conn.client, conn.server_seq, conn.client_seq)
+        };
+        let ack = if flags & ACK != 0 { ack } else { 0 };
+        let packet = ip_packet(src, dst, &tcp_segment(src, dst, seq, ack, flags, payload));
+        
+        // SYN与FIN各占一个序号
+        let advance = payload.len() as u32 + u32::from(flags & (SYN | FIN) != 0);
+        if from_client {
+            conn.client_seq = conn.client_seq.wrapping_add(advance);
+        } else {
+            conn.server_seq = conn.server_seq.wrapping_add(advance);
+        }
+        self.packet(offset, &packet)
+    }
+}
+
+fn write_block<W: Write>(out: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total = (12 + body.len()) as u32;
+    out.write_all(&block_type.to_le_bytes())?;
+    out.write_all(&total.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&total.to_le_bytes())
+}
+
+// 16位反码求和
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        for pair in part.chunks(2) {
+            sum += u32::from(u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]));
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// 两端地址族不同时都按IPv4映射的IPv6地址处理
+fn ip_pair(src: IpAddr, dst: IpAddr) -> (IpAddr, IpAddr) {
+    match (src, dst) {
+        (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => (src, dst),
+        _ => (to_v6(src), to_v6(dst)),
+    }
+}
+
+fn to_v6(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => IpAddr::V6(v4.to_ipv6_mapped()),
+        v6 => v6,
+    }
+}
+
+fn ip_octets(ip: IpAddr) -> Vec<u8> {
+    match ip {
+        IpAddr::V4(v4) => v4.octets().to_vec(),
+        IpAddr::V6(v6) => v6.octets().to_vec(),
+    }
+}
+
+// TCP头固定20字节，校验和包含IP伪首部
+fn tcp_segment(src: SocketAddr, dst: SocketAddr, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(5 << 4);
+    segment.push(flags);
+    segment.extend_from_slice(&u16::MAX.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+    
+    let (src_ip, dst_ip) = ip_pair(src.ip(), dst.ip());
+    let (src_ip, dst_ip) = (ip_octets(src_ip), ip_octets(dst_ip));
+    let length = segment.len() as u32;
+    let pseudo = if src_ip.len() == 4 {
+        let mut pseudo = vec![0, 6];
+        pseudo.extend_from_slice(&(length as u16).to_be_bytes());
+        pseudo
+    } else {
+        let mut pseudo = length.to_be_bytes().to_vec();
+        pseudo.extend_from_slice(&[0, 0, 0, 6]);
+        pseudo
+    };
+    let sum = checksum(&[&src_ip, &dst_ip, &pseudo, &segment]);
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+    segment
+}
+
+fn ip_packet(src: SocketAddr, dst: SocketAddr, segment: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(40 + segment.len());
+    match ip_pair(src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + segment.len()) as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]); // 不分片，TTL 64，协议TCP
+            packet.extend_from_slice(&src_ip.octets());
+            packet.extend_from_slice(&dst_ip.octets());
+            let sum = checksum(&[&packet]);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        }
+        (src_ip, dst_ip) => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[6, 64]); // 下一首部TCP，跳数限制64
+            packet.extend_from_slice(&ip_octets(src_ip));
+            packet.extend_from_slice(&ip_octets(dst_ip));
+        }
+    }
+    packet.extend_from_slice(segment);
+    packet
+}
+
+// 按RFC 6455编码一个完整的帧，mask 为 None 时不加掩码
+fn ws_frame(opcode: Opcode, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let code = match opcode {
+        Opcode::Text => 0x1,
+        Opcode::Binary => 0x2,
+        Opcode::Close => 0x8,
+        Opcode::Ping => 0x9,
+        Opcode::Pong => 0xa,
+    };
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut frame = vec![0x80 | code];
+    match payload.len() {
+        len if len < 126 => frame.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(mask_bit | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(key) => {
+            frame.extend_from_slice(&key);
+            frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ key[i % 4]));
+        }
+        None => frame.extend_from_slice(payload),
+    }
+    frame
+}
+
+// 把录制写成pcapng，session 指定时只导出该会话。返回写入的数据包数
+pub fn write_pcapng<W: Write>(out: W, start_micros: i64, records: &[Record], session: Option<u32>) -> io::Result<usize> {
+    let mut writer = PcapWriter::new(out, start_micros)?;
+    let mut connections: HashMap<u32, Connection> = HashMap::new();
+    
+    for record in records.iter().filter(|record| session.is_none_or(|session| record.session == session)) {
+        let offset = record.offset;
+        match record.kind {
+            Kind::Open => {
+                let info = record.session_info();
+                let peer = info.as_ref().and_then(|info| info.peer);
+                let local = info.as_ref().and_then(|info| info.local);
+                // 初始序号由会话序号推出，同一录制每次导出的结果相同
+                let mut conn = Connection {
+                    client: peer.unwrap_or(SocketAddr::new(DEFAULT_SERVER.ip(), 40000 + (record.session % 20000) as u16)),
+                    server: local.unwrap_or(DEFAULT_SERVER),
+                    client_seq: 0x1000_0000u32.wrapping_add(record.session.wrapping_mul(0x9e37_79b9)),
+                    server_seq: 0x2000_0000u32.wrapping_add(record.session.wrapping_mul(0x85eb_ca6b)),
+                    frames: 0,
+                };
+                
+                // 三次握手
+                writer.segment(offset, &mut conn, true, SYN, &[])?;
+                writer.segment(offset, &mut conn, false, SYN | ACK, &[])?;
+                writer.segment(offset, &mut conn, true, ACK, &[])?;
+                
+                // HTTP升级，Wireshark据此把后续数据识别为WebSocket
+                let request = format!(
+                    "GET /ws HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+                    conn.server, WS_KEY
+                );
+                writer.send(offset, &mut conn, true, request.as_bytes())?;
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                    WS_ACCEPT
+                );
+                writer.send(offset, &mut conn, false, response.as_bytes())?;
+                connections.insert(record.session, conn);
+            }
+            Kind::Inbound => {
+                let Some(conn) = connections.get_mut(&record.session) else {
+                    continue;
+                };
+                // 客户端发出的帧必须带掩码
+                let mask = conn.frames.wrapping_mul(0x2545_f491).wrapping_add(0x6d2b_79f5).to_be_bytes();
+                conn.frames += 1;
+                writer.send(offset, conn, true, &ws_frame(record.opcode, &record.payload, Some(mask)))?;
+            }
+            Kind::Outbound => {
+                let Some(conn) = connections.get_mut(&record.session) else {
+                    continue;
+                };
+                writer.send(offset, conn, false, &ws_frame(record.opcode, &record.payload, None))?;
+            }
+            Kind::Close => {
+                // 服务器发出关闭帧后断开连接
+                let Some(mut conn) = connections.remove(&record.session) else {
+                    continue;
+                };
+                writer.segment(offset, &mut conn, false, FIN | ACK, &[])?;
+                writer.segment(offset, &mut conn, true, FIN | ACK, &[])?;
+                writer.segment(offset, &mut conn, false, ACK, &[])?;
+            }
+        }
+    }
+    
+    writer.out.flush()?;
+    Ok(writer.packets)
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+// 定义消息类型
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChatMessage {
+    pub msg_type: String, // "chat", "system", "command", "ping", "pong", "join", "leave", "userlist", "private", "reaction", "history", "read", "unread", "export"
+    pub username: String,
+    pub room: String,
+    pub text: String,
+    pub timestamp: u64,
+    pub id: String, // 消息唯一ID，用于确认机制
+    pub target: Option<String>, // 私聊目标用户名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>, // 结构化负载，如用户列表
+}
+
+impl ChatMessage {
+    // 客户端构造消息：时间戳取当前时间并生成新的消息ID，服务器会修正用户名与房间
+    pub fn new(msg_type: &str, username: &str, room: &str, text: &str) -> Self {
+        ChatMessage {
+            msg_type: msg_type.to_string(),
+            username: username.to_string(),
+            room: room.to_string(),
+            text: text.to_string(),
+            timestamp: chrono::Utc::now().timestamp() as u64,
+            id: uuid::Uuid::new_v4().to_string(),
+            target: None,
+            data: None,
+        }
+    }
+}
+
+// 命令名，即用法中的第一个词。命令列表由服务器的命令注册表生成，客户端从 /help 的回复中获取
+pub fn command_name(usage: &str) -> &str {
+    usage.split_whitespace().next().unwrap_or(usage)
+}
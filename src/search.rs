@@ -0,0 +1,220 @@
+use crate::ChatMessage;
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use serde::Serialize;
+use std::collections::HashMap;
+
+// 每页结果数的上限
+pub const MAX_PER_PAGE: usize = 100;
+// 页码的上限
+pub const MAX_PAGE: usize = 10_000;
+
+// 判断字符是否属于中日韩文字，这类文字之间没有空格分隔
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' | // 日文假名
+        '\u{3400}'..='\u{4dbf}' | // 扩展A
+        '\u{4e00}'..='\u{9fff}' | // 基本汉字
+        '\u{ac00}'..='\u{d7af}' | // 韩文
+        '\u{f900}'..='\u{faff}')  // 兼容汉字
+}
+
+// 将文本切分为连续的中日韩文字串和字母数字串，其余字符作为分隔符
+fn runs(text: &str) -> Vec<(bool, Vec<char>)> {
+    let mut runs: Vec<(bool, Vec<char>)> = Vec::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        let cjk = is_cjk(c);
+        if !cjk && !c.is_alphanumeric() {
+            runs.push((false, Vec::new()));
+            continue;
+        }
+        match runs.last_mut() {
+            Some((last_cjk, chars)) if *last_cjk == cjk && (cjk || !chars.is_empty()) => chars.push(c),
+            _ => runs.push((cjk, vec![c])),
+        }
+    }
+    runs.retain(|(_, chars)| !chars.is_empty());
+    runs
+}
+
+// 索引分词：英文按单词，中文同时生成单字和相邻两字（bigram），
+// 这样不依赖词典也能检索到任意长度不小于一的中文片段
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for (cjk, chars) in runs(text) {
+        if cjk {
+            tokens.extend(chars.iter().map(|c| c.to_string()));
+            tokens.extend(chars.windows(2).map(|pair| pair.iter().collect::<String>()));
+        } else {
+            tokens.push(chars.into_iter().collect());
+        }
+    }
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+// 查询分词：中文片段长度大于一时只使用bigram，英文单词按前缀匹配
+fn query_tokens(term: &str) -> Vec<(String, bool)> {
+    let mut tokens = Vec::new();
+    for (cjk, chars) in runs(term) {
+        if !cjk {
+            tokens.push((chars.into_iter().collect(), true));
+        } else if chars.len() == 1 {
+            tokens.push((chars[0].to_string(), false));
+        } else {
+            tokens.extend(chars.windows(2).map(|pair| (pair.iter().collect::<String>(), false)));
+        }
+    }
+    tokens
+}
+
+// 解析日期过滤条件：支持 YYYY-MM-DD、YYYY-MM-DDTHH:MM 和Unix时间戳（秒），日期按服务器本地时区解释
+pub fn parse_date(value: &str) -> Option<u64> {
+    if let Ok(timestamp) = value.parse::<u64>() {
+        return Some(timestamp);
+    }
+    let datetime = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M"))
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))?;
+    Local.from_local_datetime(&datetime).earliest().map(|dt| dt.timestamp().max(0) as u64)
+}
+
+// 查询者身份，决定可以看到哪些私聊消息
+#[derive(Clone, Copy)]
+pub enum Viewer<'a> {
+    Session(&'a str), // 只能看到该会话收发的私聊，参数为会话ID
+    Admin,            // 可以看到全部消息
+    Anonymous,        // 只能看到房间消息
+}
+
+// 搜索条件
+#[derive(Default, Debug)]
+pub struct SearchQuery {
+    pub terms: Vec<String>,
+    pub room: Option<String>,
+    pub from: Option<String>,
+    pub before: Option<u64>, // 只返回早于该时间的消息
+    pub after: Option<u64>,  // 只返回不早于该时间的消息
+    pub page: usize,         // 从1开始
+    pub per_page: usize,
+}
+
+impl SearchQuery {
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty() && self.room.is_none() && self.from.is_none()
+    }
+    
+    // 把页码和每页条数限制在合理范围内，避免计算偏移时溢出
+    pub fn clamp_paging(&mut self) {
+        self.page = self.page.clamp(1, MAX_PAGE);
+        self.per_page = self.per_page.clamp(1, MAX_PER_PAGE);
+    }
+    
+    // 解析 /search 命令的参数：<关键词...> [房间] [from:用户] [before:日期] [after:日期] [page:页码]。
+    // 房间也可以写成 room:房间；不带前缀时，只有最后一个词是已知房间名且前面还有关键词才视为房间。
+    pub fn parse_command(args: &[&str], is_room: impl Fn(&str) -> bool) -> Result<SearchQuery, String> {
+        let mut query = SearchQuery { page: 1, per_page: 10, ..SearchQuery::default() };
+        
+        for arg in args {
+            if let Some(value) = arg.strip_prefix("from:") {
+                query.from = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("room:") {
+                query.room = Some(value.to_string());
+            } else if let Some(value) = arg.strip_prefix("before:") {
+                query.before = Some(parse_date(value).ok_or_else(|| format!("无法识别的日期: {}", value))?);
+            } else if let Some(value) = arg.strip_prefix("after:") {
+                query.after = Some(parse_date(value).ok_or_else(|| format!("无法识别的日期: {}", value))?);
+            } else if let Some(value) = arg.strip_prefix("page:") {
+                query.page = value.parse().map_err(|_| format!("无效的页码: {}", value))?;
+            } else {
+                query.terms.push(arg.to_string());
+            }
+        }
+        
+        if query.room.is_none() && query.terms.len() > 1 && query.terms.last().is_some_and(|last| is_room(last)) {
+            query.room = query.terms.pop();
+        }
+        query.clamp_paging();
+        
+        Ok(query)
+    }
+}
+
+// 一页搜索结果，按时间从新到旧排列
+#[derive(Serialize, Debug)]
+pub struct SearchPage {
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub results: Vec<ChatMessage>,
+}
+
+// 基于倒排表的全文索引，文档序号对应调用方保存的消息列表中的位置
+#[derive(Default)]
+pub struct SearchIndex {
+    postings: HashMap<String, Vec<usize>>, // 词项 -> 文档序号（递增）
+    docs: Vec<usize>,                      // 已索引的全部文档序号
+}
+
+impl SearchIndex {
+    pub fn add(&mut self, doc: usize, text: &str) {
+        for token in tokenize(text) {
+            self.postings.entry(token).or_default().push(doc);
+        }
+        self.docs.push(doc);
+    }
+    
+    // 取得包含全部查询词项的候选文档
+    fn candidates(&self, terms: &[String]) -> Vec<usize> {
+        let mut result: Option<Vec<usize>> = None;
+        for (token, prefix) in terms.iter().flat_map(|term| query_tokens(term)) {
+            let mut docs: Vec<usize> = if prefix {
+                self.postings.iter()
+                    .filter(|(key, _)| key.starts_with(&token))
+                    .flat_map(|(_, docs)| docs.iter().copied())
+                    .collect()
+            } else {
+                self.postings.get(&token).cloned().unwrap_or_default()
+            };
+            docs.sort_unstable();
+            docs.dedup();
+            
+            result = Some(match result {
+                None => docs,
+                Some(current) => current.into_iter().filter(|doc| docs.binary_search(doc).is_ok()).collect(),
+            });
+        }
+        result.unwrap_or_else(|| self.docs.clone())
+    }
+    
+    pub fn search(&self, docs: &[ChatMessage], query: &SearchQuery, visible: impl Fn(&ChatMessage) -> bool) -> SearchPage {
+        let lowered: Vec<String> = query.terms.iter().map(|term| term.to_lowercase()).collect();
+        
+        let mut matches: Vec<&ChatMessage> = self.candidates(&query.terms).into_iter()
+            .map(|doc| &docs[doc])
+            .filter(|message| {
+                let text = message.text.to_lowercase();
+                // bigram只能保证候选包含各个片段，再确认原文中确实出现了查询词
+                lowered.iter().all(|term| {
+                    query_tokens(term).iter().all(|(_, prefix)| *prefix) || text.contains(term.as_str())
+                })
+            })
+            .filter(|message| visible(message))
+            .filter(|message| query.room.as_ref().is_none_or(|room| message.msg_type != "private" && &message.room == room))
+            .filter(|message| query.from.as_ref().is_none_or(|from| &message.username == from))
+            .filter(|message| query.before.is_none_or(|before| message.timestamp < before))
+            .filter(|message| query.after.is_none_or(|after| message.timestamp >= after))
+            .collect();
+        matches.reverse();
+        
+        let per_page = query.per_page.clamp(1, MAX_PER_PAGE);
+        let page = query.page.clamp(1, MAX_PAGE);
+        SearchPage {
+            total: matches.len(),
+            page,
+            per_page,
+            results: matches.into_iter().skip(page.saturating_sub(1).saturating_mul(per_page)).take(per_page).cloned().collect(),
+        }
+    }
+}
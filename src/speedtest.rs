@@ -0,0 +1,430 @@
+// 吞吐量测试（/speedtest）：在WebSocket连接上测量上传或下载速率，可选文本帧或二进制帧。
+// 速率以接收方为准：上传时服务器记录每一帧的到达时间，下载时客户端逐帧回复确认，服务器记录确认的到达时间，
+// 并限制未确认的帧数，避免数据堆积在发送缓冲区而高估速率。
+// HTTP接口 /api/speedtest/download 与 /api/speedtest/upload 用于在同一服务器上对比HTTP与WebSocket的吞吐量
+use crate::capture::Opcode;
+use crate::commands::{Arg, CommandSpec, Permission, Registry};
+use crate::{send_message_to_user, trace_frame, trace_outbound, traffic, AppState, ChatMessage, SessionSink, Transport};
+use actix_web::{web, HttpResponse};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+const DEFAULT_SIZE: usize = 16 * 1024;
+const MAX_SIZE: usize = 1024 * 1024;
+const DEFAULT_SECS: u64 = 5;
+const MAX_SECS: u64 = 30;
+// 下载测试中未确认帧数的上限
+const WINDOW: usize = 8;
+// 测试时长结束后等待剩余帧或确认的时间
+const GRACE: Duration = Duration::from_secs(2);
+// HTTP测速的默认与最大字节数，下载按块发送
+const HTTP_DEFAULT_BYTES: usize = 10 * 1024 * 1024;
+const HTTP_MAX_BYTES: usize = 100 * 1024 * 1024;
+const HTTP_CHUNK: usize = 64 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Upload,
+    Download,
+}
+
+impl Direction {
+    fn name(self) -> &'static str {
+        match self {
+            Direction::Upload => "upload",
+            Direction::Download => "download",
+        }
+    }
+    
+    fn label(self) -> &'static str {
+        match self {
+            Direction::Upload => "上传",
+            Direction::Download => "下载",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Params {
+    direction: Direction,
+    size: usize, // 每帧的负载字节数，文本帧不含JSON外壳
+    duration: Duration,
+    binary: bool,
+}
+
+impl Params {
+    // 解析 /speedtest 的参数：[upload|download] [每帧字节数] [秒数] [binary|text]，数字依次为字节数与秒数
+    fn parse(args: &[&str]) -> Result<Params, String> {
+        let mut params = Params {
+            direction: Direction::Download,
+            size: DEFAULT_SIZE,
+            duration: Duration::from_secs(DEFAULT_SECS),
+            binary: false,
+        };
+        let mut numbers = Vec::new();
+        for arg in args {
+            match *arg {
+                "upload" | "up" => params.direction = Direction::Upload,
+                "download" | "down" => params.direction = Direction::Download,
+                "binary" => params.binary = true,
+                "text" => params.binary = false,
+                "http" => return Err("HTTP测速请使用 GET /api/speedtest/download?size=字节数 与 POST /api/speedtest/upload".to_string()),
+                value => match value.parse::<u64>() {
+                    Ok(number) => numbers.push(number),
+                    Err(_) => return Err(format!("无法识别的参数: {}", value)),
+                },
+            }
+        }
+        
+        match numbers[..] {
+            [] => {}
+            [size] => params.size = size as usize,
+            [size, secs] => {
+                params.size = size as usize;
+                params.duration = Duration::from_secs(secs);
+            }
+            _ => return Err("用法: /speedtest [upload|download] [每帧字节数] [秒数] [binary|text]".to_string()),
+        }
+        if params.size == 0 || params.size > MAX_SIZE {
+            return Err(format!("每帧字节数应在 1 到 {} 之间", MAX_SIZE));
+        }
+        if params.duration.is_zero() || params.duration > Duration::from_secs(MAX_SECS) {
+            return Err(format!("测试时长应在 1 到 {} 秒之间", MAX_SECS));
+        }
+        Ok(params)
+    }
+    
+    fn frame_label(&self) -> &'static str {
+        if self.binary { "二进制帧" } else { "文本帧" }
+    }
+}
+
+// 会话上正在进行的测试
+pub(crate) enum SpeedTest {
+    Upload {
+        id: String,
+        params: Params,
+        start: Instant,
+        arrivals: Vec<(Duration, usize)>, // (相对开始的到达时间, 字节数)
+    },
+    Download {
+        id: String,
+        acks: mpsc::UnboundedSender<()>,
+    },
+}
+
+// 根据每一帧的到达时间汇总测试结果，返回说明文本与结构化数据。
+// 速率按字节数与最后一帧的到达时间计算；抖动为相邻到达间隔之差的平均绝对值
+fn report(direction: Direction, transport: &str, frame_size: Option<usize>, arrivals: &[(Duration, usize)]) -> (String, serde_json::Value) {
+    let bytes: usize = arrivals.iter().map(|(_, bytes)| bytes).sum();
+    let elapsed = arrivals.last().map(|(offset, _)| *offset).unwrap_or_default();
+    let mbps = |bytes: usize, secs: f64| if secs > 0.0 { bytes as f64 * 8.0 / secs / 1_000_000.0 } else { 0.0 };
+    
+    let intervals: Vec<f64> = arrivals.windows(2).map(|pair| (pair[1].0 - pair[0].0).as_secs_f64() * 1000.0).collect();
+    let jitter_ms = if intervals.len() < 2 {
+        0.0
+    } else {
+        intervals.windows(2).map(|pair| (pair[1] - pair[0]).abs()).sum::<f64>() / (intervals.len() - 1) as f64
+    };
+    
+    // 每秒一个样本，最后不足一秒的部分按实际时长计算
+    let seconds = elapsed.as_secs_f64().ceil().max(1.0) as usize;
+    let mut buckets = vec![0usize; seconds];
+    for (offset, bytes) in arrivals {
+        buckets[(offset.as_secs() as usize).min(seconds - 1)] += bytes;
+    }
+    let samples: Vec<serde_json::Value> = buckets.iter().enumerate().map(|(index, bytes)| {
+        let length = (elapsed.as_secs_f64() - index as f64).clamp(0.0, 1.0);
+        let length = if length > 0.0 { length } else { 1.0 };
+        serde_json::json!({ "second": index + 1, "bytes": bytes, "mbps": mbps(*bytes, length) })
+    }).collect();
+    
+    let average = mbps(bytes, elapsed.as_secs_f64());
+    let sample_text: Vec<String> = samples.iter()
+        .map(|sample| format!("{}s {:.2}", sample["second"], sample["mbps"].as_f64().unwrap_or_default()))
+        .collect();
+    let text = format!(
+        "{}测速完成（{}）：平均 {:.2} Mbps，{:.2} MB / {} 帧，用时 {:.2} 秒，抖动 {:.3} 毫秒\n每秒速率(Mbps): {}",
+        direction.label(), transport, average, bytes as f64 / 1_000_000.0, arrivals.len(), elapsed.as_secs_f64(), jitter_ms, sample_text.join(" | ")
+    );
+    let data = serde_json::json!({
+        "op": "report",
+        "direction": direction.name(),
+        "transport": transport,
+        "frame_size": frame_size,
+        "bytes": bytes,
+        "frames": arrivals.len(),
+        "elapsed_ms": elapsed.as_secs_f64() * 1000.0,
+        "mbps": average,
+        "jitter_ms": jitter_ms,
+        "samples": samples,
+    });
+    (text, data)
+}
+
+fn speedtest_message(app_state: &AppState, text: String, data: serde_json::Value) -> ChatMessage {
+    ChatMessage {
+        msg_type: "speedtest".to_string(),
+        username: "服务器".to_string(),
+        room: "".to_string(),
+        text,
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: Some(data),
+    }
+}
+
+async fn send_report(app_state: &Arc<AppState>, user_id: &str, params: Params, arrivals: &[(Duration, usize)]) {
+    let transport = format!("WebSocket {}，每帧 {} 字节", params.frame_label(), params.size);
+    let (text, mut data) = report(params.direction, &transport, Some(params.size), arrivals);
+    data["binary"] = serde_json::Value::Bool(params.binary);
+    data["transport"] = serde_json::Value::String("websocket".to_string());
+    log::info!("Speed test for {} finished: {} frames in {:?}", user_id, arrivals.len(), arrivals.last().map(|(offset, _)| *offset));
+    send_message_to_user(&speedtest_message(app_state, text, data), user_id, app_state).await;
+}
+
+pub(crate) fn register(registry: &mut Registry) {
+    registry.add_fn(
+        CommandSpec {
+            name: "/speedtest",
+            args: &[Arg::Optional("upload|download"), Arg::Optional("每帧字节数"), Arg::Optional("秒数"), Arg::Optional("binary")],
+            help: "测试上传或下载吞吐量",
+            permission: Permission::User,
+        },
+        |call| Box::pin(async move { start(call.app_state, call.user_id, call.args).await }),
+    );
+}
+
+// 处理 /speedtest 命令，返回要回复的文本（已开始测试时为空）
+async fn start(app_state: &Arc<AppState>, user_id: &str, args: &[&str]) -> String {
+    let params = match Params::parse(args) {
+        Ok(params) => params,
+        Err(e) => return e,
+    };
+    
+    let id = Uuid::new_v4().simple().to_string();
+    let (session, acks) = {
+        let mut sessions = app_state.sessions.lock().unwrap();
+        let Some(user_session) = sessions.get_mut(user_id) else {
+            return "".to_string();
+        };
+        let session = match (&user_session.session, user_session.transport) {
+            (SessionSink::WebSocket(session), Transport::WebSocket) => session.clone(),
+            _ => return "测速仅支持WebSocket连接，其他传输方式请使用HTTP测速接口".to_string(),
+        };
+        if user_session.speedtest.is_some() {
+            return "已有测速正在进行".to_string();
+        }
+        let (state, acks) = match params.direction {
+            Direction::Upload => (
+                SpeedTest::Upload { id: id.clone(), params, start: app_state.clock.now(), arrivals: Vec::new() },
+                None,
+            ),
+            Direction::Download => {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                (SpeedTest::Download { id: id.clone(), acks: sender }, Some(receiver))
+            }
+        };
+        user_session.speedtest = Some(state);
+        (session, acks)
+    };
+    
+    log::info!("Starting {} speed test for {}: {} bytes per frame, {:?}", params.direction.name(), user_id, params.size, params.duration);
+    let begin = speedtest_message(
+        app_state,
+        format!("开始{}测速：每帧 {} 字节，{} 秒，{}", params.direction.label(), params.size, params.duration.as_secs(), params.frame_label()),
+        serde_json::json!({
+            "op": "begin",
+            "test": id,
+            "direction": params.direction.name(),
+            "size": params.size,
+            "duration_ms": params.duration.as_millis() as u64,
+            "binary": params.binary,
+        }),
+    );
+    send_message_to_user(&begin, user_id, app_state).await;
+    
+    let app_state = app_state.clone();
+    let user_id = user_id.to_string();
+    match acks {
+        Some(acks) => {
+            actix_web::rt::spawn(run_download(app_state, user_id, id, params, session, acks));
+        }
+        // 客户端没有发送结束消息时，到时自动结束上传测试
+        None => {
+            actix_web::rt::spawn(async move {
+                let deadline = app_state.clock.now() + params.duration + GRACE;
+                app_state.clock.sleep_until(deadline).await;
+                finish_upload(&app_state, &user_id, &id).await;
+            });
+        }
+    }
+    "".to_string()
+}
+
+// 下载测试：持续发送直到时长结束，未确认的帧数不超过 WINDOW
+async fn run_download(
+    app_state: Arc<AppState>,
+    user_id: String,
+    id: String,
+    params: Params,
+    mut session: actix_ws::Session,
+    mut acks: mpsc::UnboundedReceiver<()>,
+) {
+    let clock = app_state.clock.clone();
+    let data_json = serde_json::to_string(&speedtest_message(&app_state, "x".repeat(params.size), serde_json::json!({ "op": "data", "test": id }))).unwrap();
+    let payload: Vec<u8> = (0..params.size).map(|i| i as u8).collect();
+    
+    let start = clock.now();
+    let end = start + params.duration;
+    let mut in_flight = 0;
+    let mut arrivals = Vec::new();
+    loop {
+        let sending = clock.now() < end;
+        if sending && in_flight < WINDOW {
+            let result = if params.binary {
+                trace_frame(&app_state, &user_id, false, Opcode::Binary, &payload);
+                session.binary(payload.clone()).await
+            } else {
+                trace_outbound(&app_state, &user_id, &data_json);
+                session.text(data_json.clone()).await
+            };
+            if result.is_err() {
+                traffic::record_send_error(&app_state, &user_id);
+                return; // 连接已关闭
+            }
+            in_flight += 1;
+            continue;
+        }
+        if !sending && in_flight == 0 {
+            break;
+        }
+        
+        let deadline = if sending { end } else { end + GRACE };
+        tokio::select! {
+            ack = acks.recv() => match ack {
+                Some(()) => {
+                    in_flight -= 1;
+                    arrivals.push((clock.elapsed(start), params.size));
+                }
+                None => return, // 会话已移除
+            },
+            _ = clock.sleep_until(deadline) => {
+                if !sending {
+                    log::warn!("Speed test for {} ended with {} unacknowledged frames", user_id, in_flight);
+                    break;
+                }
+            }
+        }
+    }
+    
+    if let Some(user_session) = app_state.sessions.lock().unwrap().get_mut(&user_id) {
+        if matches!(&user_session.speedtest, Some(SpeedTest::Download { id: current, .. }) if *current == id) {
+            user_session.speedtest = None;
+        }
+    }
+    send_report(&app_state, &user_id, params, &arrivals).await;
+}
+
+// 记录上传测试中到达的一帧
+pub(crate) fn record_upload(app_state: &AppState, user_id: &str, bytes: usize) {
+    let mut sessions = app_state.sessions.lock().unwrap();
+    if let Some(SpeedTest::Upload { params, start, arrivals, .. }) = sessions.get_mut(user_id).and_then(|user_session| user_session.speedtest.as_mut()) {
+        let offset = app_state.clock.elapsed(*start);
+        if offset <= params.duration + GRACE {
+            arrivals.push((offset, bytes));
+        }
+    }
+}
+
+// 结束上传测试并发送结果，测试已结束或不是这一次测试时不做任何事
+async fn finish_upload(app_state: &Arc<AppState>, user_id: &str, id: &str) {
+    let finished = {
+        let mut sessions = app_state.sessions.lock().unwrap();
+        let Some(user_session) = sessions.get_mut(user_id) else {
+            return;
+        };
+        match user_session.speedtest.take() {
+            Some(SpeedTest::Upload { id: current, params, arrivals, .. }) if current == id => Some((params, arrivals)),
+            other => {
+                user_session.speedtest = other;
+                None
+            }
+        }
+    };
+    if let Some((params, arrivals)) = finished {
+        send_report(app_state, user_id, params, &arrivals).await;
+    }
+}
+
+// 处理客户端发来的 speedtest 消息：上传的文本帧（data）、下载的确认（ack）与上传结束（end）
+pub(crate) async fn handle(app_state: &Arc<AppState>, user_id: &str, message: &ChatMessage) {
+    let Some(data) = &message.data else {
+        return;
+    };
+    let test = data["test"].as_str().unwrap_or_default();
+    match data["op"].as_str() {
+        Some("data") => record_upload(app_state, user_id, message.text.len()),
+        Some("ack") => {
+            let sessions = app_state.sessions.lock().unwrap();
+            if let Some(SpeedTest::Download { id, acks }) = sessions.get(user_id).and_then(|user_session| user_session.speedtest.as_ref()) {
+                if id == test {
+                    let _ = acks.send(());
+                }
+            }
+        }
+        Some("end") => finish_upload(app_state, user_id, test).await,
+        _ => log::warn!("Unknown speed test message from {}: {:?}", user_id, data),
+    }
+}
+
+// HTTP下载测速的查询参数
+#[derive(Deserialize)]
+pub(crate) struct DownloadParams {
+    size: Option<usize>,
+}
+
+// 返回指定字节数的数据，速率由客户端计算
+pub(crate) async fn download_route(params: web::Query<DownloadParams>) -> HttpResponse {
+    let size = params.size.unwrap_or(HTTP_DEFAULT_BYTES);
+    if size > HTTP_MAX_BYTES {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": format!("size 不能超过 {}", HTTP_MAX_BYTES) }));
+    }
+    let chunk = web::Bytes::from((0..HTTP_CHUNK).map(|i| i as u8).collect::<Vec<u8>>());
+    let stream = futures_util::stream::iter((0..size).step_by(HTTP_CHUNK).map(move |offset| {
+        Ok::<_, actix_web::Error>(chunk.slice(..HTTP_CHUNK.min(size - offset)))
+    }));
+    HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .insert_header(("Cache-Control", "no-store"))
+        .no_chunking(size as u64)
+        .streaming(stream)
+}
+
+// 接收请求体并按到达时间计算上传速率，返回与WebSocket测速相同格式的结果
+pub(crate) async fn upload_route(mut payload: web::Payload, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    let start = app_state.clock.now();
+    let mut arrivals = Vec::new();
+    let mut total = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() })),
+        };
+        total += chunk.len();
+        if total > HTTP_MAX_BYTES {
+            return HttpResponse::PayloadTooLarge().json(serde_json::json!({ "error": format!("请求体不能超过 {} 字节", HTTP_MAX_BYTES) }));
+        }
+        arrivals.push((app_state.clock.elapsed(start), chunk.len()));
+    }
+    
+    let (text, mut data) = report(Direction::Upload, "HTTP", None, &arrivals);
+    data["transport"] = serde_json::Value::String("http".to_string());
+    data["text"] = serde_json::Value::String(text);
+    HttpResponse::Ok().json(data)
+}
@@ -0,0 +1,389 @@
+// 模拟子网与路由器：房间可以划入带CIDR标签的子网，管理员用 /net 定义路由器以及子网、路由器之间的链路，
+// 每条链路有各自的延迟与丢包率。跨子网的私聊沿总延迟最小的路径逐跳转发，/traceroute 显示这条路径与每一跳的往返时间。
+// 拓扑只保存在本节点的内存中，不在集群节点之间同步
+use crate::commands::{Arg, Call, CommandSpec, Permission, Registry};
+use crate::monitor::{Event, EventKind};
+use crate::{find_user_by_name, send_message_to_user, traffic, user_room, AppState, ChatMessage};
+use futures_util::future::BoxFuture;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+const USAGE: &str = "用法: /net [subnet <名称> <CIDR> | router <名称> | link <节点> <节点> <延迟毫秒> [丢包率%] | unlink <节点> <节点> | room <房间> [子网] | remove <名称>]";
+// traceroute 每一跳发送的探测数
+const PROBES: usize = 3;
+
+// IPv4网段，网络地址按前缀长度对齐
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Cidr {
+    network: Ipv4Addr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn parse(text: &str) -> Option<Cidr> {
+        let (addr, prefix) = text.split_once('/')?;
+        let addr: Ipv4Addr = addr.parse().ok()?;
+        let prefix: u8 = prefix.parse().ok().filter(|prefix| *prefix <= 32)?;
+        Some(Cidr { network: Ipv4Addr::from(u32::from(addr) & Cidr::mask(prefix)), prefix })
+    }
+    
+    fn mask(prefix: u8) -> u32 {
+        u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+    }
+    
+    fn overlaps(&self, other: &Cidr) -> bool {
+        let mask = Cidr::mask(self.prefix.min(other.prefix));
+        u32::from(self.network) & mask == u32::from(other.network) & mask
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+// 拓扑中的节点，子网与路由器共用同一个名称空间
+enum Node {
+    Subnet(Cidr),
+    Router,
+}
+
+// 两个节点之间的双向链路
+struct Link {
+    a: String,
+    b: String,
+    latency: Duration,
+    loss: f64, // 单向丢包概率，0到1
+}
+
+impl Link {
+    fn connects(&self, a: &str, b: &str) -> bool {
+        (self.a == a && self.b == b) || (self.a == b && self.b == a)
+    }
+    
+    // 链路另一端的节点
+    fn other(&self, node: &str) -> Option<&str> {
+        if self.a == node {
+            Some(&self.b)
+        } else if self.b == node {
+            Some(&self.a)
+        } else {
+            None
+        }
+    }
+}
+
+// 路径上的一跳：到达的节点与经过的链路参数
+pub(crate) struct Hop {
+    node: String,
+    latency: Duration,
+    loss: f64,
+}
+
+// 两个房间之间的路由结果
+pub(crate) enum Routing {
+    Direct(&'static str),       // 不经过路由，附带原因
+    Routed(String, Vec<Hop>),   // 源子网与依次经过的各跳，最后一跳为目标子网
+    Unreachable(String, String), // 源子网与目标子网之间没有路径
+}
+
+// 模拟网络的拓扑。单独加锁，持有时不获取其他锁
+#[derive(Default)]
+pub(crate) struct Topology {
+    nodes: BTreeMap<String, Node>,
+    links: Vec<Link>,
+    rooms: BTreeMap<String, String>, // 房间 -> 子网
+}
+
+impl Topology {
+    fn subnet(&self, name: &str) -> Option<Cidr> {
+        match self.nodes.get(name) {
+            Some(Node::Subnet(cidr)) => Some(*cidr),
+            _ => None,
+        }
+    }
+    
+    // 子网的显示名称，带上CIDR
+    fn label(&self, name: &str) -> String {
+        match self.subnet(name) {
+            Some(cidr) => format!("{} ({})", name, cidr),
+            None => name.to_string(),
+        }
+    }
+    
+    // 执行 /net 的修改子命令，返回给管理员的回复
+    pub(crate) fn command(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            ["subnet", name, cidr] => {
+                let cidr = Cidr::parse(cidr).ok_or_else(|| format!("无法识别的CIDR: {}", cidr))?;
+                if let Some(Node::Router) = self.nodes.get(*name) {
+                    return Err(format!("{} 已经是路由器", name));
+                }
+                if let Some((other, _)) = self.nodes.iter().find(|(other, node)| {
+                    other.as_str() != *name && matches!(node, Node::Subnet(existing) if existing.overlaps(&cidr))
+                }) {
+                    return Err(format!("{} 与子网 {} 重叠", cidr, self.label(other)));
+                }
+                self.nodes.insert(name.to_string(), Node::Subnet(cidr));
+                Ok(format!("已定义子网 {} ({})", name, cidr))
+            }
+            ["router", name] => {
+                if let Some(Node::Subnet(_)) = self.nodes.get(*name) {
+                    return Err(format!("{} 已经是子网", name));
+                }
+                self.nodes.insert(name.to_string(), Node::Router);
+                Ok(format!("已定义路由器 {}", name))
+            }
+            ["link", a, b, latency, rest @ ..] if rest.len() <= 1 => {
+                for node in [a, b] {
+                    if !self.nodes.contains_key(*node) {
+                        return Err(format!("节点 {} 不存在", node));
+                    }
+                }
+                if a == b {
+                    return Err("链路的两端不能是同一个节点".to_string());
+                }
+                if self.subnet(a).is_some() && self.subnet(b).is_some() {
+                    return Err("子网之间需要通过路由器连接".to_string());
+                }
+                let latency: u64 = latency.parse().map_err(|_| format!("无法识别的延迟: {}", latency))?;
+                let loss = match rest.first() {
+                    Some(loss) => match loss.trim_end_matches('%').parse::<f64>() {
+                        Ok(percent) if (0.0..=100.0).contains(&percent) => percent / 100.0,
+                        _ => return Err(format!("丢包率应在 0 到 100 之间: {}", loss)),
+                    },
+                    None => 0.0,
+                };
+                self.links.retain(|link| !link.connects(a, b));
+                self.links.push(Link { a: a.to_string(), b: b.to_string(), latency: Duration::from_millis(latency), loss });
+                Ok(format!("已连接 {} — {}：延迟 {} 毫秒，丢包率 {}%", a, b, latency, loss * 100.0))
+            }
+            ["unlink", a, b] => {
+                let before = self.links.len();
+                self.links.retain(|link| !link.connects(a, b));
+                if self.links.len() == before {
+                    return Err(format!("{} 与 {} 之间没有链路", a, b));
+                }
+                Ok(format!("已断开 {} — {}", a, b))
+            }
+            ["room", room] => match self.rooms.remove(*room) {
+                Some(subnet) => Ok(format!("已把房间 {} 移出子网 {}", room, subnet)),
+                None => Err(format!("房间 {} 不属于任何子网", room)),
+            },
+            ["room", room, subnet] => {
+                let cidr = self.subnet(subnet).ok_or_else(|| format!("子网 {} 不存在", subnet))?;
+                self.rooms.insert(room.to_string(), subnet.to_string());
+                Ok(format!("已把房间 {} 划入子网 {} ({})", room, subnet, cidr))
+            }
+            ["remove", name] => {
+                if self.nodes.remove(*name).is_none() {
+                    return Err(format!("节点 {} 不存在", name));
+                }
+                self.links.retain(|link| link.other(name).is_none());
+                self.rooms.retain(|_, subnet| subnet != name);
+                Ok(format!("已删除 {} 及其链路", name))
+            }
+            _ => Err(USAGE.to_string()),
+        }
+    }
+    
+    // 列出子网、路由器与链路
+    pub(crate) fn describe(&self) -> String {
+        if self.nodes.is_empty() {
+            return "尚未定义子网，所有房间之间直接通信".to_string();
+        }
+        let mut lines = vec!["子网:".to_string()];
+        for (name, node) in &self.nodes {
+            if let Node::Subnet(cidr) = node {
+                let rooms: Vec<&str> = self.rooms.iter().filter(|(_, subnet)| *subnet == name).map(|(room, _)| room.as_str()).collect();
+                let rooms = if rooms.is_empty() { "无".to_string() } else { rooms.join(", ") };
+                lines.push(format!("  {} {} 房间: {}", name, cidr, rooms));
+            }
+        }
+        let routers: Vec<&str> = self.nodes.iter().filter(|(_, node)| matches!(node, Node::Router)).map(|(name, _)| name.as_str()).collect();
+        lines.push(format!("路由器: {}", if routers.is_empty() { "无".to_string() } else { routers.join(", ") }));
+        lines.push("链路:".to_string());
+        for link in &self.links {
+            lines.push(format!("  {} — {} 延迟 {} 毫秒 丢包率 {}%", link.a, link.b, link.latency.as_millis(), link.loss * 100.0));
+        }
+        lines.join("\n")
+    }
+    
+    // 计算两个房间之间总延迟最小的路径（Dijkstra）
+    pub(crate) fn route(&self, from_room: &str, to_room: &str) -> Routing {
+        let (Some(source), Some(target)) = (self.rooms.get(from_room), self.rooms.get(to_room)) else {
+            return Routing::Direct("所在房间未划入子网");
+        };
+        if source == target {
+            return Routing::Direct("位于同一子网");
+        }
+        
+        let mut distance: BTreeMap<&str, Duration> = BTreeMap::from([(source.as_str(), Duration::ZERO)]);
+        let mut previous: BTreeMap<&str, &Link> = BTreeMap::new();
+        let mut visited: BTreeSet<&str> = BTreeSet::new();
+        while let Some((node, cost)) = distance.iter()
+            .filter(|(node, _)| !visited.contains(*node))
+            .min_by_key(|(_, cost)| **cost)
+            .map(|(node, cost)| (*node, *cost))
+        {
+            if node == target {
+                break;
+            }
+            visited.insert(node);
+            for link in &self.links {
+                let Some(next) = link.other(node) else {
+                    continue;
+                };
+                let cost = cost + link.latency;
+                if distance.get(next).is_none_or(|known| cost < *known) {
+                    distance.insert(next, cost);
+                    previous.insert(next, link);
+                }
+            }
+        }
+        
+        // 从目标沿前驱回溯得到路径
+        let mut hops = Vec::new();
+        let mut node = target.as_str();
+        while node != source {
+            let Some(link) = previous.get(node) else {
+                return Routing::Unreachable(self.label(source), self.label(target));
+            };
+            hops.push(Hop { node: node.to_string(), latency: link.latency, loss: link.loss });
+            node = link.other(node).unwrap_or_default();
+        }
+        hops.reverse();
+        Routing::Routed(source.clone(), hops)
+    }
+    
+    // 模拟traceroute：每一跳发送 PROBES 个探测，往返时间为到该跳的单程延迟之和的两倍，
+    // 探测在往返经过的每条链路上都可能丢失，丢失的探测显示为 *
+    pub(crate) fn traceroute(&self, target: &str, source: &str, hops: &[Hop]) -> String {
+        let destination = hops.last().map(|hop| self.label(&hop.node)).unwrap_or_default();
+        let mut lines = vec![format!("到 {} 的路径（{} → {}），共 {} 跳:", target, self.label(source), destination, hops.len())];
+        let mut one_way = Duration::ZERO;
+        for (index, hop) in hops.iter().enumerate() {
+            one_way += hop.latency;
+            let probes: Vec<String> = (0..PROBES).map(|_| {
+                let lost = hops[..=index].iter().any(|hop| rand::random::<f64>() < hop.loss || rand::random::<f64>() < hop.loss);
+                if lost { "*".to_string() } else { format!("{} 毫秒", (one_way * 2).as_millis()) }
+            }).collect();
+            lines.push(format!("{:>2}  {}  {}", index + 1, self.label(&hop.node), probes.join("  ")));
+        }
+        lines.join("\n")
+    }
+}
+
+// 注册 /traceroute 与 /net 命令
+pub(crate) fn register(registry: &mut Registry) {
+    registry.add_fn(
+        CommandSpec { name: "/traceroute", args: &[Arg::Required("用户名")], help: "显示到该用户的模拟路由路径", permission: Permission::User },
+        traceroute_command,
+    );
+    registry.add_fn(
+        CommandSpec { name: "/net", args: &[Arg::Optional("subnet|router|link|unlink|room|remove ...")], help: "查看或修改模拟子网与路由器（修改需管理员）", permission: Permission::User },
+        net_command,
+    );
+}
+
+fn traceroute_command(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        let target = call.args[0];
+        let Some(current_room) = user_room(call.user_id, call.app_state) else {
+            return "".to_string();
+        };
+        let Some(target_room) = find_user_by_name(target, call.app_state).and_then(|target_id| user_room(&target_id, call.app_state)) else {
+            return format!("用户 {} 不在线或不存在", target);
+        };
+        
+        let topology = call.app_state.topology.lock().unwrap();
+        match topology.route(&current_room, &target_room) {
+            Routing::Direct(reason) => format!("{} {}，消息直接送达", target, reason),
+            Routing::Unreachable(source, destination) => format!("目标不可达：从子网 {} 到 {} 没有路由", source, destination),
+            Routing::Routed(source, hops) => topology.traceroute(target, &source, &hops),
+        }
+    })
+}
+
+// 查看模拟网络拓扑，修改拓扑需要管理员权限
+fn net_command(call: Call<'_>) -> BoxFuture<'_, String> {
+    Box::pin(async move {
+        if call.args.is_empty() {
+            return call.app_state.topology.lock().unwrap().describe();
+        }
+        if !call.is_admin() {
+            return "只有管理员可以修改网络拓扑".to_string();
+        }
+        let result = call.app_state.topology.lock().unwrap().command(call.args);
+        match result {
+            Ok(reply) => {
+                log::info!("Session {} changed the simulated topology: {}", call.user_id, call.args.join(" "));
+                reply
+            }
+            Err(e) => e,
+        }
+    })
+}
+
+// 跨子网的私聊：按各跳链路的延迟依次等待，每一跳按丢包率决定是否丢失，
+// 到达目标子网后记录历史并投递；丢失时通知发送方
+pub(crate) async fn forward(app_state: Arc<AppState>, message: ChatMessage, sender_id: String, target_id: String, source: String, hops: Vec<Hop>) {
+    let target = message.target.clone().unwrap_or_default();
+    let mut previous = source;
+    for (index, hop) in hops.iter().enumerate() {
+        let deadline = app_state.clock.now() + hop.latency;
+        app_state.clock.sleep_until(deadline).await;
+        let lost = rand::random::<f64>() < hop.loss;
+        
+        if app_state.monitor.active() {
+            let summary = if lost {
+                format!("{} 发给 {} 的私聊在链路 {} → {} 上丢失", message.username, target, previous, hop.node)
+            } else {
+                format!("{} 发给 {} 的私聊经第 {} 跳到达 {}", message.username, target, index + 1, hop.node)
+            };
+            let mut event = Event::new(
+                EventKind::Route,
+                app_state.clock.as_ref(),
+                summary,
+                serde_json::json!({ "msg_type": message.msg_type, "from": message.username, "hop": index + 1, "node": hop.node, "latency_ms": hop.latency.as_millis() as u64, "lost": lost }),
+            );
+            event.user = Some(message.username.clone());
+            event.room = Some(message.room.clone());
+            app_state.monitor.emit(event);
+        }
+        
+        if lost {
+            log::info!("Private message from {} to {} lost between {} and {}", message.username, target, previous, hop.node);
+            traffic::record_dropped(&app_state, &sender_id);
+            let notice = ChatMessage {
+                msg_type: "system".to_string(),
+                username: "服务器".to_string(),
+                room: message.room.clone(),
+                text: format!("发给 {} 的私聊在链路 {} → {} 上丢失", target, previous, hop.node),
+                timestamp: app_state.clock.timestamp(),
+                id: Uuid::new_v4().to_string(),
+                target: None,
+                data: None,
+            };
+            send_message_to_user(&notice, &sender_id, &app_state).await;
+            return;
+        }
+        previous = hop.node.clone();
+    }
+    
+    app_state.history.lock().unwrap().record_private(&message, [&sender_id, &target_id]);
+    send_message_to_user(&message, &target_id, &app_state).await;
+    log::info!("Private message from {} to {} routed over {} hops", message.username, target, hops.len());
+}
+
+// 路径的各跳名称与总延迟，附在转发的私聊消息上
+pub(crate) fn route_data(source: &str, hops: &[Hop]) -> serde_json::Value {
+    let path: Vec<&str> = std::iter::once(source).chain(hops.iter().map(|hop| hop.node.as_str())).collect();
+    let delay: Duration = hops.iter().map(|hop| hop.latency).sum();
+    serde_json::json!({ "route": path, "delay_ms": delay.as_millis() as u64 })
+}
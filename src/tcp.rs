@@ -0,0 +1,229 @@
+use crate::{handle_chat_message, handle_disconnect, open_session, send_message_to_user, send_user_list_diff, traffic};
+use crate::{AppState, ChatMessage, SessionSink, Ticker, Transport, UserListDiff, UserSession};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// 单行文本的最大长度（字节），超过后断开连接
+const MAX_LINE: usize = 4096;
+
+// 监听TCP端口，每个连接作为一个会话加入聊天室，可以用 nc/telnet 直接连接
+pub async fn run(addr: String, app_state: Arc<AppState>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Failed to bind TCP gateway on {}: {:?}", addr, e);
+            return;
+        }
+    };
+    log::info!("TCP gateway listening on {}", addr);
+    
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                actix_web::rt::spawn(handle_connection(stream, peer, app_state.clone()));
+            }
+            Err(e) => {
+                log::error!("Failed to accept TCP connection: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn handle_connection(stream: TcpStream, peer: SocketAddr, app_state: Arc<AppState>) {
+    let server_host = stream.local_addr().map(|addr| addr.to_string()).unwrap_or_default();
+    let (reader, mut writer) = stream.into_split();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    
+    let id = Uuid::new_v4().to_string();
+    log::info!("New TCP connection from {}, session_id: {}", peer, &id);
+    
+    // 发送任务：把发给该会话的JSON帧渲染为文本行写出，会话移除后通道关闭，任务随之结束
+    actix_web::rt::spawn(async move {
+        while let Some(json) = receiver.recv().await {
+            let Some(text) = render_frame(&json) else {
+                continue;
+            };
+            if let Err(e) = writer.write_all(text.as_bytes()).await {
+                log::debug!("TCP write failed: {:?}", e);
+                break;
+            }
+        }
+        let _ = writer.shutdown().await;
+    });
+    
+    let user_session = UserSession::new(id.clone(), peer.ip().to_string(), SessionSink::Channel(sender), Transport::Tcp, app_state.clock.as_ref());
+    open_session(&app_state, user_session, &server_host).await;
+    
+    let usage = ChatMessage {
+        msg_type: "system".to_string(),
+        username: "服务器".to_string(),
+        room: "大厅".to_string(),
+        text: "直接输入文字发送消息；/join <房间名> 切换房间，/msg <用户名> <消息> 发送私聊，/quit 断开连接，/help 查看其他命令".to_string(),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target: None,
+        data: None,
+    };
+    send_message_to_user(&usage, &id, &app_state).await;
+    
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    let mut presence_interval = Ticker::new(app_state.clock.clone(), app_state.timeouts.heartbeat_interval);
+    
+    loop {
+        let mut limited = (&mut reader).take((MAX_LINE - line.len()) as u64);
+        tokio::select! {
+            // 读取一行。被取消时已读到的部分保留在line中，下次继续读取
+            read = limited.read_until(b'\n', &mut line) => {
+                match read {
+                    Ok(0) => {
+                        log::info!("TCP connection {} closed by peer", id);
+                        break;
+                    }
+                    Ok(_) if line.last() == Some(&b'\n') => {
+                        let text = String::from_utf8_lossy(&line).into_owned();
+                        line.clear();
+                        if !handle_line(&text, &id, &app_state).await {
+                            break;
+                        }
+                    }
+                    Ok(_) if line.len() >= MAX_LINE => {
+                        log::warn!("TCP connection {} sent a line longer than {} bytes", id, MAX_LINE);
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::error!("TCP read error for {}: {:?}", id, e);
+                        break;
+                    }
+                }
+            }
+            
+            // TCP连接由内核检测断开，这里只刷新心跳与在线状态
+            _ = presence_interval.tick() => {
+                let changed_room = {
+                    let mut sessions = app_state.sessions.lock().unwrap();
+                    match sessions.get_mut(&id) {
+                        Some(user_session) => {
+                            let now = app_state.clock.now();
+                            user_session.last_heartbeat = now;
+                            user_session.refresh_presence(now).then(|| user_session.room.clone())
+                        }
+                        None => break,
+                    }
+                };
+                if let Some(room) = changed_room {
+                    send_user_list_diff(&app_state, &room, UserListDiff::Update(id.clone())).await;
+                }
+            }
+        }
+    }
+    
+    log::info!("TCP handler loop exited for {}, cleaning up", id);
+    handle_disconnect(&id, &app_state).await;
+}
+
+// 将一行输入转换为与WebSocket客户端相同的消息并处理，返回false表示断开连接
+async fn handle_line(line: &str, user_id: &str, app_state: &Arc<AppState>) -> bool {
+    let line = line.trim();
+    if line.is_empty() {
+        return true;
+    }
+    
+    let mut parts = line.splitn(3, char::is_whitespace);
+    let command = parts.next().unwrap_or_default();
+    let (msg_type, room, text, target) = match command {
+        "/quit" => return false,
+        "/join" => match parts.next() {
+            Some(room) => ("join", room, "", None),
+            None => ("command", "", "/help", None),
+        },
+        "/msg" => match (parts.next(), parts.next()) {
+            (Some(target), Some(text)) => ("private", "", text.trim(), Some(target.to_string())),
+            _ => ("command", "", "/help", None),
+        },
+        _ if command.starts_with('/') => ("command", "", line, None),
+        _ => ("chat", "", line, None),
+    };
+    
+    // 按收到的一行（含换行符）计入流量
+    traffic::record_inbound(app_state, user_id, msg_type, line.len() + 1);
+    
+    // 发送者由服务器根据会话填写
+    let chat_msg = ChatMessage {
+        msg_type: msg_type.to_string(),
+        username: "".to_string(),
+        room: room.to_string(),
+        text: text.to_string(),
+        timestamp: app_state.clock.timestamp(),
+        id: Uuid::new_v4().to_string(),
+        target,
+        data: None,
+    };
+    handle_chat_message(chat_msg, user_id, app_state).await
+}
+
+fn format_time(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .map(|dt| dt.with_timezone(&chrono::Local).format("%H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+// 系统消息可能包含多行，每行加上前缀
+fn system_lines(text: &str) -> String {
+    text.lines().map(|line| format!("* {}\r\n", line)).collect()
+}
+
+// 渲染单条消息，心跳、未读计数等只对图形客户端有意义的消息返回None
+fn render_message(message: &ChatMessage) -> Option<String> {
+    let data = message.data.as_ref();
+    match message.msg_type.as_str() {
+        "chat" if !message.text.is_empty() => Some(format!(
+            "[{}] [{}] {}: {}\r\n", format_time(message.timestamp), message.room, message.username, message.text
+        )),
+        "private" => Some(format!(
+            "[{}] [私聊] {}→{}: {}\r\n",
+            format_time(message.timestamp), message.username, message.target.as_deref().unwrap_or_default(), message.text
+        )),
+        "system" => Some(system_lines(&message.text)),
+        "export" => {
+            let content = data.and_then(|data| data["content"].as_str()).unwrap_or_default();
+            Some(format!("{}{}\r\n", system_lines(&message.text), content))
+        }
+        "reaction" => Some(format!("* {} 回应了 {}\r\n", message.username, message.text)),
+        "history" => {
+            let messages = data.and_then(|data| data["messages"].as_array())?;
+            if messages.is_empty() {
+                return None;
+            }
+            let mut out = format!("* 房间 {} 的最近 {} 条消息:\r\n", message.room, messages.len());
+            for value in messages {
+                if let Some(line) = serde_json::from_value(value.clone()).ok().and_then(|m| render_message(&m)) {
+                    out.push_str(&line);
+                }
+            }
+            Some(out)
+        }
+        "userlist" if data.is_some_and(|data| data["op"] == "snapshot") => {
+            let users: Vec<&str> = data?["users"].as_array()?.iter()
+                .filter_map(|user| user["username"].as_str())
+                .collect();
+            Some(format!("* 房间 {} 在线用户: {}\r\n", message.room, users.join(", ")))
+        }
+        _ => None,
+    }
+}
+
+fn render_frame(json: &str) -> Option<String> {
+    match serde_json::from_str::<ChatMessage>(json) {
+        Ok(message) => render_message(&message),
+        Err(e) => {
+            log::error!("Failed to parse outgoing frame for TCP session: {:?}", e);
+            None
+        }
+    }
+}
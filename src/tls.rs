@@ -0,0 +1,130 @@
+use actix_web::{HttpRequest, HttpResponse};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::Arc;
+
+// TLS监听配置，来自环境变量：
+//   NET_APP_TLS_ADDR      TLS监听地址，如 0.0.0.0:8443，未设置时不启用TLS
+//   NET_APP_TLS_CERT/KEY  PEM格式的证书链与私钥路径，未设置时生成自签名证书
+//   NET_APP_TLS_HOSTS     自签名证书额外包含的主机名或IP，逗号分隔
+//   NET_APP_TLS_REDIRECT  为 1 或 true 时，明文端口只把请求重定向到HTTPS
+pub struct TlsSettings {
+    pub addr: String,
+    pub port: u16,
+    pub redirect: bool,
+    pub config: rustls::ServerConfig,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl TlsSettings {
+    pub fn from_env(data_dir: &Path) -> io::Result<Option<TlsSettings>> {
+        let Some(addr) = std::env::var("NET_APP_TLS_ADDR").ok().filter(|addr| !addr.is_empty()) else {
+            return Ok(None);
+        };
+        let port = addr.parse::<SocketAddr>()
+            .map_err(|e| invalid(format!("无效的TLS监听地址 {}: {}", addr, e)))?
+            .port();
+        let redirect = std::env::var("NET_APP_TLS_REDIRECT").is_ok_and(|value| value == "1" || value.eq_ignore_ascii_case("true"));
+        
+        let (certs, key) = match (std::env::var("NET_APP_TLS_CERT"), std::env::var("NET_APP_TLS_KEY")) {
+            (Ok(cert_path), Ok(key_path)) => load_pem(Path::new(&cert_path), Path::new(&key_path))?,
+            (Err(_), Err(_)) => self_signed(&data_dir.join("tls"))?,
+            _ => return Err(invalid("NET_APP_TLS_CERT 与 NET_APP_TLS_KEY 需要同时设置".to_string())),
+        };
+        
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid(format!("TLS配置错误: {}", e)))?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(|e| invalid(format!("证书或私钥无效: {}", e)))?;
+        
+        Ok(Some(TlsSettings { addr, port, redirect, config }))
+    }
+}
+
+fn load_pem(cert_path: &Path, key_path: &Path) -> io::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = rustls_pemfile::certs(&mut io::BufReader::new(std::fs::File::open(cert_path)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(invalid(format!("{} 中没有证书", cert_path.display())));
+    }
+    let key = rustls_pemfile::private_key(&mut io::BufReader::new(std::fs::File::open(key_path)?))?
+        .ok_or_else(|| invalid(format!("{} 中没有私钥", key_path.display())))?;
+    Ok((certs, key))
+}
+
+// 本机的局域网地址：向外“连接”一个UDP套接字以选出出口网卡，不会真正发送数据
+pub fn lan_ip() -> Option<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip().to_string())
+}
+
+// 读取数据目录中已生成的自签名证书，不存在时生成一份，重启后证书保持不变，浏览器中的例外无需重复添加
+fn self_signed(dir: &Path) -> io::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    if cert_path.exists() && key_path.exists() {
+        log::info!("Using self-signed certificate from {}", cert_path.display());
+        return load_pem(&cert_path, &key_path);
+    }
+    
+    let mut hosts = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    hosts.extend(lan_ip());
+    if let Ok(extra) = std::env::var("NET_APP_TLS_HOSTS") {
+        hosts.extend(extra.split(',').map(str::trim).filter(|host| !host.is_empty()).map(str::to_string));
+    }
+    // 去掉重复的主机名，保留第一次出现的顺序
+    let mut seen = HashSet::new();
+    hosts.retain(|host| seen.insert(host.clone()));
+    
+    let certified = rcgen::generate_simple_self_signed(hosts.clone())
+        .map_err(|e| invalid(format!("生成自签名证书失败: {}", e)))?;
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(&cert_path, certified.cert.pem())?;
+    write_private(&key_path, &certified.key_pair.serialize_pem())?;
+    log::info!("Generated self-signed certificate for {} at {}", hosts.join(", "), cert_path.display());
+    
+    load_pem(&cert_path, &key_path)
+}
+
+// 写入私钥文件，在unix上只允许所有者读写
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // 文件已存在时 mode 不起作用，再设置一次权限
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
+// 把明文HTTP请求重定向到HTTPS端口的同一路径
+pub async fn redirect(req: HttpRequest, port: u16) -> HttpResponse {
+    let host = req.connection_info().host().to_string();
+    // 去掉Host中的端口（IPv6地址形如 [::1]:8080）
+    let hostname = match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+        _ => host,
+    };
+    let path = req.uri().path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    let location = if port == 443 {
+        format!("https://{}{}", hostname, path)
+    } else {
+        format!("https://{}:{}{}", hostname, port, path)
+    };
+    
+    // 使用临时重定向，关闭重定向后浏览器不会继续使用缓存的跳转
+    HttpResponse::TemporaryRedirect()
+        .insert_header((actix_web::http::header::LOCATION, location))
+        .finish()
+}
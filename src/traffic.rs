@@ -0,0 +1,191 @@
+// 会话的流量统计：收发的帧数与字节数（合计、按消息类型、按WebSocket帧类型），以及发送失败、丢弃与重连次数。
+// WebSocket会话按实际的帧统计；其他传输方式的下行按发给网关任务的JSON帧统计，上行按收到的原始数据统计，不区分帧类型
+use crate::capture::Opcode;
+use crate::{is_admin_request, AppState, Transport};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// 同一地址的会话结束后在该时间内又建立新会话，视为重连
+const RECONNECT_WITHIN: Duration = Duration::from_secs(60);
+
+// 一个方向或一类流量的帧数与字节数
+#[derive(Serialize, Default, Clone, Copy)]
+pub(crate) struct Counter {
+    pub(crate) frames_in: u64,
+    pub(crate) bytes_in: u64,
+    pub(crate) frames_out: u64,
+    pub(crate) bytes_out: u64,
+}
+
+impl Counter {
+    fn add(&mut self, inbound: bool, bytes: usize) {
+        if inbound {
+            self.frames_in += 1;
+            self.bytes_in += bytes as u64;
+        } else {
+            self.frames_out += 1;
+            self.bytes_out += bytes as u64;
+        }
+    }
+    
+    fn describe(&self) -> String {
+        format!("收 {} 帧 {} 字节，发 {} 帧 {} 字节", self.frames_in, self.bytes_in, self.frames_out, self.bytes_out)
+    }
+}
+
+// 单个会话的流量统计
+#[derive(Serialize, Default, Clone)]
+pub(crate) struct Traffic {
+    #[serde(flatten)]
+    pub(crate) total: Counter,
+    pub(crate) by_msg_type: BTreeMap<String, Counter>,
+    pub(crate) by_opcode: BTreeMap<&'static str, Counter>, // 仅WebSocket会话
+    pub(crate) send_errors: u64, // 写入连接失败的次数
+    pub(crate) dropped: u64,     // 无法解析或不支持的上行帧、重复的数据报与模拟路由中丢失的私聊
+    pub(crate) reconnects: u32,  // 同一地址在短时间内重新连接的次数，沿用到新会话
+}
+
+impl Traffic {
+    fn record(&mut self, inbound: bool, opcode: Option<Opcode>, msg_type: Option<&str>, bytes: usize) {
+        self.total.add(inbound, bytes);
+        if let Some(opcode) = opcode {
+            self.by_opcode.entry(opcode.name()).or_default().add(inbound, bytes);
+        }
+        if let Some(msg_type) = msg_type {
+            self.by_msg_type.entry(msg_type.to_string()).or_default().add(inbound, bytes);
+        }
+    }
+    
+    // /stats me 与 /stats <用户> 的回复
+    pub(crate) fn report(&self, username: &str, transport: Transport, online: Duration) -> String {
+        let mut text = format!(
+            "{} 的流量统计（{}，在线 {:.1} 秒）:\n合计: {}\n发送失败 {} 次，丢弃 {} 条，重连 {} 次",
+            username, transport.name(), online.as_secs_f64(), self.total.describe(), self.send_errors, self.dropped, self.reconnects
+        );
+        if !self.by_msg_type.is_empty() {
+            text.push_str("\n按消息类型:");
+            for (msg_type, counter) in &self.by_msg_type {
+                text.push_str(&format!("\n  {}: {}", msg_type, counter.describe()));
+            }
+        }
+        if !self.by_opcode.is_empty() {
+            text.push_str("\n按帧类型:");
+            for (opcode, counter) in &self.by_opcode {
+                text.push_str(&format!("\n  {}: {}", opcode, counter.describe()));
+            }
+        }
+        text
+    }
+    
+    // 会话结束时写入日志的一行摘要
+    pub(crate) fn summary(&self) -> String {
+        let types: Vec<String> = self.by_msg_type.iter()
+            .map(|(msg_type, counter)| format!("{}={}/{}", msg_type, counter.frames_in, counter.frames_out))
+            .collect();
+        format!(
+            "in {} frames/{} bytes, out {} frames/{} bytes, {} send errors, {} dropped, {} reconnects, by type (in/out) [{}]",
+            self.total.frames_in, self.total.bytes_in, self.total.frames_out, self.total.bytes_out,
+            self.send_errors, self.dropped, self.reconnects, types.join(" ")
+        )
+    }
+}
+
+// 最近结束的会话，按客户端地址记录结束时间与重连次数，用于识别重连
+#[derive(Default)]
+pub(crate) struct Departures {
+    recent: HashMap<String, (Instant, u32)>,
+}
+
+impl Departures {
+    // 会话结束（包括作为陈旧连接被清理）时调用
+    pub(crate) fn depart(&mut self, addr: &str, now: Instant, reconnects: u32) {
+        self.recent.retain(|_, (at, _)| now.saturating_duration_since(*at) <= RECONNECT_WITHIN);
+        self.recent.insert(addr.to_string(), (now, reconnects));
+    }
+    
+    // 新会话建立时调用，返回新会话的重连次数，不是重连时为0
+    pub(crate) fn arrive(&mut self, addr: &str, now: Instant) -> u32 {
+        match self.recent.remove(addr) {
+            Some((at, reconnects)) if now.saturating_duration_since(at) <= RECONNECT_WITHIN => reconnects + 1,
+            _ => 0,
+        }
+    }
+}
+
+// 只解析文本帧中的消息类型，其余字段跳过
+#[derive(Deserialize)]
+struct Kind {
+    msg_type: String,
+}
+
+// 文本帧中JSON消息的类型，无法解析时返回None
+pub(crate) fn msg_type(payload: &[u8]) -> Option<String> {
+    serde_json::from_slice::<Kind>(payload).ok().map(|kind| kind.msg_type)
+}
+
+// 记录会话收发的一帧，帧类型只对WebSocket会话统计。会获取sessions锁，调用时不能持有该锁
+pub(crate) fn record_frame(app_state: &AppState, user_id: &str, inbound: bool, opcode: Opcode, msg_type: Option<&str>, bytes: usize) {
+    if let Some(user_session) = app_state.sessions.lock().unwrap().get_mut(user_id) {
+        let opcode = (user_session.transport == Transport::WebSocket).then_some(opcode);
+        user_session.traffic.record(inbound, opcode, msg_type, bytes);
+    }
+}
+
+// 记录非WebSocket会话收到的一条消息
+pub(crate) fn record_inbound(app_state: &AppState, user_id: &str, msg_type: &str, bytes: usize) {
+    if let Some(user_session) = app_state.sessions.lock().unwrap().get_mut(user_id) {
+        user_session.traffic.record(true, None, Some(msg_type), bytes);
+    }
+}
+
+pub(crate) fn record_send_error(app_state: &AppState, user_id: &str) {
+    if let Some(user_session) = app_state.sessions.lock().unwrap().get_mut(user_id) {
+        user_session.traffic.send_errors += 1;
+    }
+}
+
+pub(crate) fn record_dropped(app_state: &AppState, user_id: &str) {
+    if let Some(user_session) = app_state.sessions.lock().unwrap().get_mut(user_id) {
+        user_session.traffic.dropped += 1;
+    }
+}
+
+// 本节点各会话的流量统计，user 为会话ID或用户名，为None时返回全部会话
+fn snapshot(app_state: &AppState, user: Option<&str>) -> Vec<serde_json::Value> {
+    let sessions = app_state.sessions.lock().unwrap();
+    let mut entries: Vec<serde_json::Value> = sessions.values()
+        .filter(|user_session| user.is_none_or(|user| user_session.id == user || user_session.username == user))
+        .map(|user_session| serde_json::json!({
+            "id": user_session.id,
+            "username": user_session.username,
+            "transport": user_session.transport,
+            "addr": user_session.addr,
+            "online_ms": app_state.clock.elapsed(user_session.join_time).as_millis() as u64,
+            "traffic": user_session.traffic,
+        }))
+        .collect();
+    entries.sort_by(|a, b| a["username"].as_str().cmp(&b["username"].as_str()));
+    entries
+}
+
+// 查询全部会话的流量统计，需要管理员口令
+pub(crate) async fn traffic_route(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    if !is_admin_request(&req, &app_state) {
+        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "需要管理员口令" }));
+    }
+    HttpResponse::Ok().json(snapshot(&app_state, None))
+}
+
+// 查询单个会话的流量统计，路径参数为会话ID或用户名，需要管理员口令
+pub(crate) async fn session_traffic_route(req: HttpRequest, user: web::Path<String>, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    if !is_admin_request(&req, &app_state) {
+        return HttpResponse::Forbidden().json(serde_json::json!({ "error": "需要管理员口令" }));
+    }
+    match snapshot(&app_state, Some(&user)).into_iter().next() {
+        Some(entry) => HttpResponse::Ok().json(entry),
+        None => HttpResponse::NotFound().json(serde_json::json!({ "error": format!("用户 {} 不在本节点", user) })),
+    }
+}
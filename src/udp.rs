@@ -0,0 +1,369 @@
+use crate::clock::Clock;
+use crate::{handle_chat_message, handle_disconnect, heartbeat, open_session, traffic, webhook};
+use crate::{AppState, ChatMessage, SessionSink, Transport, UserSession};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// 单个UDP数据报的最大负载（IPv4）
+const MAX_DATAGRAM: usize = 65507;
+// 序号跟踪窗口，早于窗口的数据报无法区分重复和迟到
+const SEQ_WINDOW: u64 = 1024;
+// 未登记地址的数据报至少要有这么长服务器才回复cookie，回复不会比请求大，伪造源地址无法放大流量
+const MIN_HELLO: usize = 256;
+// cookie 按时间分段签发，接受当前和上一段的cookie，因此有效期在一到两段之间
+const COOKIE_EPOCH_SECS: u64 = 30;
+
+// 二进制数据报格式（多字节整数均为大端序）:
+//   magic 0x4E | version 1 | seq u64 | timestamp u64 | 7个字符串字段
+// 字符串字段为 u16长度 + UTF-8内容，依次为 msg_type、username、room、text、id、target、data(JSON)，
+// target 与 data 为空表示没有该字段
+const BINARY_MAGIC: u8 = 0x4e;
+const BINARY_VERSION: u8 = 1;
+
+// 数据报编码方式，服务器按客户端第一个数据报的编码回复
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Encoding {
+    Json,
+    Binary,
+}
+
+// JSON数据报：在ChatMessage的字段之外附加序号
+#[derive(Serialize, Deserialize)]
+struct JsonDatagram {
+    seq: u64,
+    #[serde(flatten)]
+    message: ChatMessage,
+}
+
+// 单个客户端的序号统计
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SequenceStats {
+    pub received: u64,
+    pub lost: u64,       // 目前仍未收到的序号数，迟到的数据报到达后会扣除
+    pub duplicates: u64,
+    pub reordered: u64,  // 晚于更大序号到达的数据报
+}
+
+// 序号观察结果
+#[derive(PartialEq, Eq, Debug)]
+enum SequenceEvent {
+    InOrder,
+    Gap(u64),
+    Reordered,
+    Duplicate,
+}
+
+// 跟踪客户端发来的序号，检测丢包、重复与乱序
+#[derive(Default, Debug)]
+pub struct SequenceTracker {
+    pub stats: SequenceStats,
+    highest: Option<u64>,
+    missing: BTreeSet<u64>, // 窗口内尚未收到的序号
+}
+
+impl SequenceTracker {
+    fn observe(&mut self, seq: u64) -> SequenceEvent {
+        let highest = match self.highest {
+            // 序号远小于窗口，视为客户端重新开始计数
+            Some(highest) if highest.saturating_sub(seq) > SEQ_WINDOW => {
+                self.missing.clear();
+                None
+            }
+            highest => highest,
+        };
+        
+        let event = match highest {
+            None => SequenceEvent::InOrder,
+            Some(highest) if seq > highest => {
+                let gap = seq - highest - 1;
+                self.stats.lost += gap;
+                self.missing.extend((highest + 1).max(seq.saturating_sub(SEQ_WINDOW))..seq);
+                self.missing = self.missing.split_off(&seq.saturating_sub(SEQ_WINDOW));
+                if gap > 0 { SequenceEvent::Gap(gap) } else { SequenceEvent::InOrder }
+            }
+            Some(_) if self.missing.remove(&seq) => {
+                self.stats.lost -= 1;
+                self.stats.reordered += 1;
+                SequenceEvent::Reordered
+            }
+            Some(_) => {
+                self.stats.duplicates += 1;
+                return SequenceEvent::Duplicate;
+            }
+        };
+        
+        self.highest = Some(highest.map_or(seq, |highest| highest.max(seq)));
+        self.stats.received += 1;
+        event
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    // 超长字段截断到u16能表示的长度（按字符边界）
+    let mut end = value.len().min(u16::MAX as usize);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    out.extend_from_slice(&(end as u16).to_be_bytes());
+    out.extend_from_slice(&value.as_bytes()[..end]);
+}
+
+fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], String> {
+    if buf.len() < len {
+        return Err("数据报长度不足".to_string());
+    }
+    let (head, rest) = buf.split_at(len);
+    *buf = rest;
+    Ok(head)
+}
+
+fn take_u64(buf: &mut &[u8]) -> Result<u64, String> {
+    Ok(u64::from_be_bytes(take(buf, 8)?.try_into().unwrap()))
+}
+
+fn take_str(buf: &mut &[u8]) -> Result<String, String> {
+    let len = u16::from_be_bytes(take(buf, 2)?.try_into().unwrap()) as usize;
+    String::from_utf8(take(buf, len)?.to_vec()).map_err(|_| "字符串字段不是有效的UTF-8".to_string())
+}
+
+fn encode(seq: u64, message: &ChatMessage, encoding: Encoding) -> Vec<u8> {
+    match encoding {
+        Encoding::Json => serde_json::to_vec(&JsonDatagram { seq, message: message.clone() }).unwrap(),
+        Encoding::Binary => {
+            let mut out = vec![BINARY_MAGIC, BINARY_VERSION];
+            out.extend_from_slice(&seq.to_be_bytes());
+            out.extend_from_slice(&message.timestamp.to_be_bytes());
+            put_str(&mut out, &message.msg_type);
+            put_str(&mut out, &message.username);
+            put_str(&mut out, &message.room);
+            put_str(&mut out, &message.text);
+            put_str(&mut out, &message.id);
+            put_str(&mut out, message.target.as_deref().unwrap_or_default());
+            put_str(&mut out, &message.data.as_ref().map(|data| data.to_string()).unwrap_or_default());
+            out
+        }
+    }
+}
+
+fn decode(datagram: &[u8]) -> Result<(u64, ChatMessage, Encoding), String> {
+    if datagram.first() != Some(&BINARY_MAGIC) {
+        let envelope: JsonDatagram = serde_json::from_slice(datagram).map_err(|e| format!("无效的JSON数据报: {}", e))?;
+        return Ok((envelope.seq, envelope.message, Encoding::Json));
+    }
+    
+    let mut buf = &datagram[1..];
+    let version = take(&mut buf, 1)?[0];
+    if version != BINARY_VERSION {
+        return Err(format!("不支持的二进制版本: {}", version));
+    }
+    let seq = take_u64(&mut buf)?;
+    let timestamp = take_u64(&mut buf)?;
+    let message = ChatMessage {
+        msg_type: take_str(&mut buf)?,
+        username: take_str(&mut buf)?,
+        room: take_str(&mut buf)?,
+        text: take_str(&mut buf)?,
+        timestamp,
+        id: take_str(&mut buf)?,
+        target: Some(take_str(&mut buf)?).filter(|target| !target.is_empty()),
+        data: match take_str(&mut buf)? {
+            data if data.is_empty() => None,
+            data => Some(serde_json::from_str(&data).map_err(|e| format!("无效的data字段: {}", e))?),
+        },
+    };
+    Ok((seq, message, Encoding::Binary))
+}
+
+// 无状态的地址cookie：用只在本进程中有效的密钥对客户端地址计算HMAC，
+// 只有真的能在该地址收到数据报的客户端才能取得，服务器在握手完成前不为该地址保存任何状态
+struct Cookies {
+    key: hmac::Key,
+}
+
+impl Cookies {
+    fn new() -> Self {
+        Cookies { key: hmac::Key::new(hmac::HMAC_SHA256, &rand::random::<[u8; 32]>()) }
+    }
+    
+    fn issue(&self, peer: SocketAddr, clock: &dyn Clock) -> String {
+        self.sign(peer, clock.timestamp() / COOKIE_EPOCH_SECS)
+    }
+    
+    fn check(&self, peer: SocketAddr, cookie: &str, clock: &dyn Clock) -> bool {
+        let epoch = clock.timestamp() / COOKIE_EPOCH_SECS;
+        self.sign(peer, epoch) == cookie || self.sign(peer, epoch.saturating_sub(1)) == cookie
+    }
+    
+    fn sign(&self, peer: SocketAddr, epoch: u64) -> String {
+        webhook::hex(&hmac::sign(&self.key, format!("{}|{}", peer, epoch).as_bytes()).as_ref()[..16])
+    }
+}
+
+// 监听UDP端口，每个客户端地址对应一个会话；超过心跳超时没有数据报的客户端会被移除。
+// 新地址需先完成cookie往返：任意数据报（不少于 MIN_HELLO 字节）得到 cookie 回复，
+// 再发送 text 为该cookie的 hello 数据报后才建立会话
+pub async fn run(addr: String, app_state: Arc<AppState>) {
+    let socket = match UdpSocket::bind(&addr).await {
+        Ok(socket) => Arc::new(socket),
+        Err(e) => {
+            log::error!("Failed to bind UDP transport on {}: {:?}", addr, e);
+            return;
+        }
+    };
+    log::info!("UDP transport listening on {}", addr);
+    let server_host = socket.local_addr().map(|addr| addr.to_string()).unwrap_or_default();
+    
+    let peers: Arc<Mutex<HashMap<SocketAddr, String>>> = Arc::default(); // 客户端地址 -> 会话ID，会话移除时删除，单独加锁
+    let cookies = Cookies::new();
+    let mut buf = vec![0u8; 65536];
+    
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                log::error!("UDP receive error: {:?}", e);
+                continue;
+            }
+        };
+        
+        let (seq, message, encoding) = match decode(&buf[..len]) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                log::warn!("Dropping malformed datagram from {}: {}", peer, e);
+                continue;
+            }
+        };
+        
+        // 新客户端或会话已超时清理时重新登记
+        let known = peers.lock().unwrap().get(&peer).cloned()
+            .filter(|id| app_state.sessions.lock().unwrap().contains_key(id));
+        let id = match known {
+            Some(id) => id,
+            None if message.msg_type == "hello" && cookies.check(peer, &message.text, app_state.clock.as_ref()) => {
+                let id = register(&socket, peer, encoding, &server_host, &peers, &app_state).await;
+                peers.lock().unwrap().insert(peer, id.clone());
+                id
+            }
+            None => {
+                send_cookie(&socket, peer, encoding, len, &cookies, &app_state).await;
+                continue;
+            }
+        };
+        
+        let event = app_state.sessions.lock().unwrap().get_mut(&id)
+            .and_then(|user_session| user_session.sequence.as_mut())
+            .map(|tracker| tracker.observe(seq));
+        match event {
+            Some(SequenceEvent::Duplicate) => {
+                log::info!("Dropping duplicate datagram seq {} from {}", seq, peer);
+                traffic::record_dropped(&app_state, &id);
+                continue;
+            }
+            Some(SequenceEvent::Gap(lost)) => log::info!("UDP client {} skipped {} datagrams before seq {}", peer, lost, seq),
+            Some(SequenceEvent::Reordered) => log::info!("UDP client {} datagram seq {} arrived out of order", peer, seq),
+            _ => {}
+        }
+        
+        traffic::record_inbound(&app_state, &id, &message.msg_type, len);
+        
+        // 客户端主动离开
+        if message.msg_type == "leave" {
+            peers.lock().unwrap().remove(&peer);
+            handle_disconnect(&id, &app_state).await;
+            continue;
+        }
+        // 重发的 hello 只用于建立会话
+        if message.msg_type == "hello" {
+            continue;
+        }
+        
+        handle_chat_message(message, &id, &app_state).await;
+    }
+}
+
+// 回复未登记地址的cookie。请求短于 MIN_HELLO 时不回复
+async fn send_cookie(socket: &UdpSocket, peer: SocketAddr, encoding: Encoding, len: usize, cookies: &Cookies, app_state: &AppState) {
+    let message = ChatMessage {
+        msg_type: "cookie".to_string(),
+        username: String::new(),
+        room: String::new(),
+        text: cookies.issue(peer, app_state.clock.as_ref()),
+        timestamp: app_state.clock.timestamp(),
+        id: String::new(),
+        target: None,
+        data: None,
+    };
+    let datagram = encode(0, &message, encoding);
+    if len < MIN_HELLO || datagram.len() > len {
+        log::debug!("Ignoring {} byte datagram from unverified UDP client {}", len, peer);
+        return;
+    }
+    if let Err(e) = socket.send_to(&datagram, peer).await {
+        log::warn!("Error sending cookie to {}: {:?}", peer, e);
+    }
+}
+
+// 为新的客户端地址创建会话，启动发送任务与心跳任务
+async fn register(
+    socket: &Arc<UdpSocket>,
+    peer: SocketAddr,
+    encoding: Encoding,
+    server_host: &str,
+    peers: &Arc<Mutex<HashMap<SocketAddr, String>>>,
+    app_state: &Arc<AppState>,
+) -> String {
+    let id = Uuid::new_v4().to_string();
+    log::info!("New UDP client {} ({:?}), session_id: {}", peer, encoding, &id);
+    
+    // 发送任务：为每个发往客户端的数据报加上服务器端序号，会话移除后通道关闭，任务随之结束
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    let send_socket = socket.clone();
+    actix_web::rt::spawn(async move {
+        let mut seq = 0u64;
+        while let Some(json) = receiver.recv().await {
+            let Ok(message) = serde_json::from_str::<ChatMessage>(&json) else {
+                continue;
+            };
+            seq += 1;
+            let datagram = encode(seq, &message, encoding);
+            if datagram.len() > MAX_DATAGRAM {
+                log::warn!("Dropping {} byte {} datagram to {}: exceeds UDP payload limit", datagram.len(), message.msg_type, peer);
+                continue;
+            }
+            if let Err(e) = send_socket.send_to(&datagram, peer).await {
+                log::warn!("Error sending datagram to {}: {:?}", peer, e);
+            }
+        }
+    });
+    
+    let mut user_session = UserSession::new(id.clone(), peer.ip().to_string(), SessionSink::Channel(sender), Transport::Udp, app_state.clock.as_ref());
+    user_session.sequence = Some(SequenceTracker::default());
+    open_session(app_state, user_session, server_host).await;
+    
+    // 心跳任务：与WebSocket相同，定时发送ping，超时没有收到数据报则移除会话。
+    // 会话以任何方式结束后都删除地址登记，该地址之后需重新完成cookie往返
+    let heartbeat_state = app_state.clone();
+    let heartbeat_id = id.clone();
+    let peers = peers.clone();
+    actix_web::rt::spawn(async move {
+        loop {
+            heartbeat::wait(&heartbeat_state, &heartbeat_id).await;
+            if !heartbeat(&heartbeat_id, &heartbeat_state).await {
+                break;
+            }
+        }
+        handle_disconnect(&heartbeat_id, &heartbeat_state).await;
+        let mut peers = peers.lock().unwrap();
+        if peers.get(&peer) == Some(&heartbeat_id) {
+            peers.remove(&peer);
+        }
+    });
+    
+    id
+}
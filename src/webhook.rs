@@ -0,0 +1,364 @@
+// 外发Webhook：把房间事件（消息、加入、离开、房间创建、房间清空）以HTTP POST推送给外部地址。
+// 请求体为JSON，X-NetApp-Signature 为用订阅密钥对请求体计算的 HMAC-SHA256；失败时按指数退避重试。
+// 每个订阅有独立的投递任务，同一订阅的事件按发生顺序投递。每个节点只推送本节点上发生的事件。
+// 订阅通过 /api/webhooks 管理，需要管理员口令；目前只支持 http:// 地址
+use crate::clock::Clock;
+use crate::{is_admin_request, AppState, ChatMessage};
+use actix_web::{web, HttpRequest, HttpResponse};
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+// 单次请求（连接、发送与读取状态行）的超时
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// 重试间隔的上限
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_RETRY_MS: u64 = 1000;
+
+// 事件类型
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EventKind {
+    Message,     // 房间中的聊天消息
+    Join,        // 用户进入房间（包括连接后进入大厅）
+    Leave,       // 用户离开房间（包括断开连接）
+    RoomCreated, // 第一个用户加入时创建了房间
+    RoomEmptied, // 最后一个用户断开后房间为空
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Message => "message",
+            EventKind::Join => "join",
+            EventKind::Leave => "leave",
+            EventKind::RoomCreated => "room_created",
+            EventKind::RoomEmptied => "room_emptied",
+        }
+    }
+}
+
+// 推送的一条事件，即请求体
+#[derive(Serialize)]
+pub(crate) struct Event {
+    event: EventKind,
+    timestamp_ms: i64, // Unix时间（毫秒）
+    node: String,
+    room: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<ChatMessage>,
+}
+
+impl Event {
+    pub(crate) fn new(event: EventKind, app_state: &AppState, room: &str) -> Event {
+        Event {
+            event,
+            timestamp_ms: app_state.clock.utc().timestamp_millis(),
+            node: app_state.cluster.node_id().to_string(),
+            room: room.to_string(),
+            session: None,
+            user: None,
+            message: None,
+        }
+    }
+    
+    // 触发事件的会话
+    pub(crate) fn by(mut self, session: &str, user: &str) -> Event {
+        self.session = Some(session.to_string());
+        self.user = Some(user.to_string());
+        self
+    }
+    
+    pub(crate) fn with_message(mut self, message: &ChatMessage) -> Event {
+        self.message = Some(message.clone());
+        self
+    }
+}
+
+// 投递结果的统计
+#[derive(Serialize, Default)]
+struct Stats {
+    delivered: u64,             // 收到2xx响应的事件数
+    failed: u64,                // 重试用尽或不可重试而放弃的事件数
+    retries: u64,               // 重试的次数
+    last_status: Option<u16>,   // 最近一次请求的响应状态码
+    last_error: Option<String>, // 最近一次连接或请求失败的原因
+}
+
+// 投递地址：host:port 与请求路径
+#[derive(Clone)]
+struct Target {
+    authority: String,
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Target, String> {
+        let rest = url.strip_prefix("http://").ok_or_else(|| "只支持 http:// 地址".to_string())?;
+        // 主机名和路径原样写入请求头，空白和控制字符会拆开请求行或注入额外的请求头
+        if rest.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err("地址不能包含空白或控制字符".to_string());
+        }
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err("地址缺少主机名".to_string());
+        }
+        // 没有端口时使用80，IPv6地址写在方括号中
+        let has_port = authority.rsplit_once(':').is_some_and(|(host, port)| !host.ends_with(':') && port.parse::<u16>().is_ok());
+        let authority = if has_port { authority.to_string() } else { format!("{}:80", authority) };
+        Ok(Target { authority, path: path.to_string() })
+    }
+}
+
+struct Subscription {
+    id: String,
+    url: String,
+    events: Vec<EventKind>, // 为空时订阅全部事件
+    rooms: Vec<String>,     // 为空时订阅全部房间
+    max_attempts: u32,
+    retry_ms: u64,
+    stats: Arc<Mutex<Stats>>,
+    sender: mpsc::UnboundedSender<(EventKind, String)>, // 交给投递任务的事件类型与请求体
+}
+
+impl Subscription {
+    fn matches(&self, event: &Event) -> bool {
+        (self.events.is_empty() || self.events.contains(&event.event)) && (self.rooms.is_empty() || self.rooms.contains(&event.room))
+    }
+    
+    fn describe(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "url": self.url,
+            "events": self.events,
+            "rooms": self.rooms,
+            "max_attempts": self.max_attempts,
+            "retry_ms": self.retry_ms,
+            "stats": *self.stats.lock().unwrap(),
+        })
+    }
+}
+
+// 订阅列表，与 Monitor 一样在所有其他锁之后加锁。没有订阅时各处跳过事件的构造
+#[derive(Default)]
+pub(crate) struct Webhooks {
+    subscriptions: Mutex<Vec<Subscription>>,
+    count: AtomicUsize,
+}
+
+impl Webhooks {
+    pub(crate) fn active(&self) -> bool {
+        self.count.load(Ordering::Relaxed) > 0
+    }
+    
+    // 交给订阅了该事件的投递任务，请求体只序列化一次
+    pub(crate) fn emit(&self, event: Event) {
+        let subscriptions = self.subscriptions.lock().unwrap();
+        let mut body = None;
+        for subscription in subscriptions.iter().filter(|subscription| subscription.matches(&event)) {
+            let body = body.get_or_insert_with(|| serde_json::to_string(&event).unwrap_or_default());
+            let _ = subscription.sender.send((event.event, body.clone()));
+        }
+    }
+    
+    fn add(&self, subscription: Subscription) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.push(subscription);
+        self.count.store(subscriptions.len(), Ordering::Relaxed);
+    }
+    
+    // 移除后投递任务发送完已排队的事件即结束
+    fn remove(&self, id: &str) -> bool {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let before = subscriptions.len();
+        subscriptions.retain(|subscription| subscription.id != id);
+        self.count.store(subscriptions.len(), Ordering::Relaxed);
+        subscriptions.len() != before
+    }
+}
+
+// 小写十六进制
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// 请求体的签名，接收方用同一密钥计算后比较
+fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    format!("sha256={}", hex(hmac::sign(&key, body).as_ref()))
+}
+
+// 发送一次POST请求（Connection: close），只读取响应的状态行
+async fn post(target: &Target, headers: &[(&str, String)], body: &[u8]) -> Result<u16, String> {
+    let mut stream = TcpStream::connect(&target.authority).await.map_err(|e| format!("连接失败: {}", e))?;
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        target.path, target.authority, body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await.map_err(|e| format!("发送失败: {}", e))?;
+    stream.write_all(body).await.map_err(|e| format!("发送失败: {}", e))?;
+    
+    let mut response = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !response.windows(2).any(|window| window == b"\r\n") {
+        let n = stream.read(&mut buf).await.map_err(|e| format!("读取响应失败: {}", e))?;
+        if n == 0 || response.len() > 8192 {
+            return Err("响应不完整".to_string());
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+    String::from_utf8_lossy(&response).split(' ').nth(1)
+        .and_then(|code| code.trim().parse().ok())
+        .ok_or_else(|| "无法解析响应状态行".to_string())
+}
+
+// 投递任务：逐个发送事件，失败时等待 retry_ms、2×retry_ms、4×retry_ms……后重试。
+// 连接失败、超时、5xx、408 与 429 会重试，其他状态码直接放弃
+async fn deliver(
+    target: Target,
+    secret: String,
+    max_attempts: u32,
+    retry_ms: u64,
+    stats: Arc<Mutex<Stats>>,
+    clock: Arc<dyn Clock>,
+    mut receiver: mpsc::UnboundedReceiver<(EventKind, String)>,
+) {
+    while let Some((event, body)) = receiver.recv().await {
+        let delivery = Uuid::new_v4().to_string();
+        let signature = sign(&secret, body.as_bytes());
+        let mut attempt = 1;
+        loop {
+            let headers = [
+                ("X-NetApp-Event", event.name().to_string()),
+                ("X-NetApp-Delivery", delivery.clone()),
+                ("X-NetApp-Attempt", attempt.to_string()),
+                ("X-NetApp-Signature", signature.clone()),
+            ];
+            let result = tokio::time::timeout(REQUEST_TIMEOUT, post(&target, &headers, body.as_bytes())).await
+                .unwrap_or_else(|_| Err("请求超时".to_string()));
+            let retryable = {
+                let mut stats = stats.lock().unwrap();
+                match &result {
+                    Ok(status) => {
+                        stats.last_status = Some(*status);
+                        if (200..300).contains(status) {
+                            stats.delivered += 1;
+                            break;
+                        }
+                        *status >= 500 || *status == 408 || *status == 429
+                    }
+                    Err(e) => {
+                        stats.last_error = Some(e.clone());
+                        true
+                    }
+                }
+            };
+            
+            if !retryable || attempt >= max_attempts {
+                stats.lock().unwrap().failed += 1;
+                log::warn!("Webhook delivery {} ({}) to {} failed after {} attempts: {:?}", delivery, event.name(), target.authority, attempt, result);
+                break;
+            }
+            let delay = Duration::from_millis(retry_ms.saturating_mul(1 << (attempt - 1).min(20))).min(MAX_RETRY_DELAY);
+            stats.lock().unwrap().retries += 1;
+            clock.sleep_until(clock.now() + delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+// 创建订阅的请求体
+#[derive(Deserialize)]
+pub(crate) struct CreateWebhook {
+    url: String,
+    secret: Option<String>, // 不提供时随机生成，只在创建时返回
+    #[serde(default)]
+    events: Vec<EventKind>,
+    #[serde(default)]
+    rooms: Vec<String>,
+    max_attempts: Option<u32>, // 包括第一次在内的最多请求次数
+    retry_ms: Option<u64>,     // 第一次重试前的等待时间
+}
+
+fn forbidden() -> HttpResponse {
+    HttpResponse::Forbidden().json(serde_json::json!({ "error": "需要管理员口令" }))
+}
+
+// 列出订阅与投递统计，不包括密钥
+pub(crate) async fn webhooks_route(req: HttpRequest, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    if !is_admin_request(&req, &app_state) {
+        return forbidden();
+    }
+    let subscriptions = app_state.webhooks.subscriptions.lock().unwrap();
+    let list: Vec<serde_json::Value> = subscriptions.iter().map(Subscription::describe).collect();
+    HttpResponse::Ok().json(list)
+}
+
+// 创建订阅并启动投递任务，返回订阅ID与密钥
+pub(crate) async fn create_webhook_route(req: HttpRequest, body: web::Json<CreateWebhook>, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    if !is_admin_request(&req, &app_state) {
+        return forbidden();
+    }
+    let body = body.into_inner();
+    let target = match Target::parse(&body.url) {
+        Ok(target) => target,
+        Err(e) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": e })),
+    };
+    let secret = body.secret.filter(|secret| !secret.is_empty()).unwrap_or_else(|| hex(&rand::random::<[u8; 16]>()));
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let subscription = Subscription {
+        id: Uuid::new_v4().to_string(),
+        url: body.url,
+        events: body.events,
+        rooms: body.rooms,
+        max_attempts: body.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+        retry_ms: body.retry_ms.unwrap_or(DEFAULT_RETRY_MS),
+        stats: Arc::new(Mutex::new(Stats::default())),
+        sender,
+    };
+    actix_web::rt::spawn(deliver(
+        target,
+        secret.clone(),
+        subscription.max_attempts,
+        subscription.retry_ms,
+        subscription.stats.clone(),
+        app_state.clock.clone(),
+        receiver,
+    ));
+    
+    let mut response = subscription.describe();
+    response["secret"] = serde_json::Value::String(secret);
+    log::info!("Webhook {} subscribed: {}", subscription.id, subscription.url);
+    app_state.webhooks.add(subscription);
+    HttpResponse::Created().json(response)
+}
+
+pub(crate) async fn delete_webhook_route(req: HttpRequest, id: web::Path<String>, app_state: web::Data<Arc<AppState>>) -> HttpResponse {
+    if !is_admin_request(&req, &app_state) {
+        return forbidden();
+    }
+    if app_state.webhooks.remove(&id) {
+        log::info!("Webhook {} removed", id);
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().json(serde_json::json!({ "error": format!("订阅 {} 不存在", id) }))
+    }
+}
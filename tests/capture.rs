@@ -0,0 +1,178 @@
+mod common;
+
+use common::TestServer;
+use net_app::capture::{self, Kind, Opcode, Record};
+use net_app::clock::ManualClock;
+use net_app::history::History;
+use net_app::pcap;
+use net_app::protocol::ChatMessage;
+use net_app::AppState;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn temp_capture() -> PathBuf {
+    std::env::temp_dir().join(format!("net_app-{}.netcap", uuid::Uuid::new_v4()))
+}
+
+// 等待服务器处理完断开后读取录制并删除文件
+async fn read_closed(path: &Path) -> (i64, Vec<Record>) {
+    let deadline = tokio::time::Instant::now() + common::TIMEOUT;
+    loop {
+        let (start, records) = capture::read(path).unwrap();
+        if records.iter().any(|record| record.kind == Kind::Close) {
+            std::fs::remove_file(path).unwrap();
+            return (start, records);
+        }
+        assert!(tokio::time::Instant::now() < deadline, "录制中没有会话关闭记录");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[actix_web::test]
+async fn websocket_frames_are_recorded_with_offsets() {
+    let path = temp_capture();
+    let clock = Arc::new(ManualClock::new());
+    let state = AppState::new(None, History::default()).with_clock(clock.clone()).with_capture(&path).unwrap();
+    let server = TestServer::start_with_state(state).await;
+    
+    let mut alice = server.connect().await;
+    clock.advance(Duration::from_secs(5));
+    alice.chat("录下来").await;
+    alice.expect("聊天回显", |message| message.msg_type == "chat" && message.text == "录下来").await;
+    alice.close().await;
+    let (_, records) = read_closed(&path).await;
+    
+    assert_eq!(records[0].kind, Kind::Open);
+    assert_eq!(records[0].offset, Duration::ZERO);
+    let info = records[0].session_info().unwrap();
+    assert_eq!(info.client_addr, "127.0.0.1");
+    assert_eq!(info.peer.unwrap().ip().to_string(), "127.0.0.1");
+    assert_eq!(info.local.unwrap(), server.addr);
+    assert!(records.iter().all(|record| record.session == 0));
+    
+    // 服务器分配用户名的初始化消息在连接时发出
+    let frames = |kind: Kind| records.iter().filter(move |record| record.kind == kind)
+        .filter(|record| record.opcode == Opcode::Text)
+        .map(|record| (record.offset, serde_json::from_str::<ChatMessage>(&record.text()).unwrap()));
+    assert!(frames(Kind::Outbound).any(|(offset, message)| {
+        offset == Duration::ZERO && message.msg_type == "chat" && message.username == alice.username
+    }));
+    
+    // 客户端的消息与服务器的回显都带有虚拟时钟推进后的时间
+    let (offset, inbound) = frames(Kind::Inbound).find(|(_, message)| message.text == "录下来").unwrap();
+    assert_eq!(offset, Duration::from_secs(5));
+    assert_eq!(inbound.msg_type, "chat");
+    let (offset, echo) = frames(Kind::Outbound).find(|(_, message)| message.text == "录下来").unwrap();
+    assert_eq!(offset, Duration::from_secs(5));
+    assert_eq!(echo.username, alice.username);
+    assert_eq!(records.last().unwrap().kind, Kind::Close);
+}
+
+// 从pcapng中取出每个数据包的 (源端口, TCP标志, TCP负载)，同时检查IPv4头校验和
+fn parse_pcapng(data: &[u8]) -> Vec<(u16, u8, Vec<u8>)> {
+    let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    assert_eq!(u32_at(0), 0x0a0d0d0a, "节头块");
+    assert_eq!(u32_at(8), 0x1a2b3c4d, "字节序标记");
+    
+    let mut packets = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let (block_type, length) = (u32_at(offset), u32_at(offset + 4) as usize);
+        assert_eq!(u32_at(offset + length - 4), length as u32, "块首尾长度一致");
+        match block_type {
+            // 接口描述块：链路类型为原始IP
+            1 => assert_eq!(u16::from_le_bytes([data[offset + 8], data[offset + 9]]), 101),
+            6 => {
+                let captured = u32_at(offset + 20) as usize;
+                let ip = &data[offset + 28..offset + 28 + captured];
+                assert_eq!(ip[0], 0x45);
+                assert_eq!(u16::from_be_bytes([ip[2], ip[3]]) as usize, ip.len());
+                let sum = ip[..20].chunks(2).map(|pair| u32::from(u16::from_be_bytes([pair[0], pair[1]]))).sum::<u32>();
+                assert_eq!((sum & 0xffff) + (sum >> 16), 0xffff, "IPv4头校验和");
+                let tcp = &ip[20..];
+                packets.push((u16::from_be_bytes([tcp[0], tcp[1]]), tcp[13], tcp[20..].to_vec()));
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+    packets
+}
+
+// 解码一个方向上的WebSocket帧，返回 (操作码, 是否带掩码, 去掉掩码的负载)
+fn parse_frames(mut stream: &[u8]) -> Vec<(u8, bool, Vec<u8>)> {
+    let mut frames = Vec::new();
+    while !stream.is_empty() {
+        let (opcode, masked) = (stream[0] & 0x0f, stream[1] & 0x80 != 0);
+        let (len, mut pos) = match stream[1] & 0x7f {
+            126 => (u16::from_be_bytes([stream[2], stream[3]]) as usize, 4),
+            127 => (u64::from_be_bytes(stream[2..10].try_into().unwrap()) as usize, 10),
+            len => (len as usize, 2),
+        };
+        let key = masked.then(|| [stream[pos], stream[pos + 1], stream[pos + 2], stream[pos + 3]]);
+        if masked {
+            pos += 4;
+        }
+        let payload = stream[pos..pos + len].iter().enumerate()
+            .map(|(i, byte)| key.map_or(*byte, |key| byte ^ key[i % 4]))
+            .collect();
+        frames.push((opcode, masked, payload));
+        stream = &stream[pos + len..];
+    }
+    frames
+}
+
+#[actix_web::test]
+async fn capture_exports_to_pcapng() {
+    let path = temp_capture();
+    let state = AppState::new(None, History::default()).with_capture(&path).unwrap();
+    let server = TestServer::start_with_state(state).await;
+    
+    let mut alice = server.connect().await;
+    alice.ping(b"lab").await;
+    let long_text = "长".repeat(1000);
+    alice.chat(&long_text).await;
+    alice.expect("聊天回显", |message| message.text == long_text).await;
+    alice.close().await;
+    let (start, records) = read_closed(&path).await;
+    assert!(records.iter().any(|record| record.kind == Kind::Inbound && record.opcode == Opcode::Ping && record.payload == b"lab"));
+    assert!(records.iter().any(|record| record.kind == Kind::Outbound && record.opcode == Opcode::Pong));
+    
+    let mut data = Vec::new();
+    let written = pcap::write_pcapng(&mut data, start, &records, None).unwrap();
+    let packets = parse_pcapng(&data);
+    assert_eq!(packets.len(), written);
+    
+    // 三次握手与四次挥手
+    let port = server.addr.port();
+    let flags: Vec<(bool, u8)> = packets.iter().map(|(src, flags, _)| (*src == port, *flags)).collect();
+    assert_eq!(flags[..3], [(false, 0x02), (true, 0x12), (false, 0x10)]);
+    assert_eq!(flags[flags.len() - 3..], [(true, 0x11), (false, 0x11), (true, 0x10)]);
+    
+    // 按方向拼接TCP负载：先是HTTP升级，之后是WebSocket帧
+    let stream = |from_server: bool| packets.iter()
+        .filter(|(src, _, _)| (*src == port) == from_server)
+        .flat_map(|(_, _, payload)| payload.clone())
+        .collect::<Vec<u8>>();
+    let split = |stream: Vec<u8>| {
+        let end = stream.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        (String::from_utf8(stream[..end].to_vec()).unwrap(), parse_frames(&stream[end..]))
+    };
+    let (request, inbound) = split(stream(false));
+    let (response, outbound) = split(stream(true));
+    assert!(request.starts_with("GET /ws HTTP/1.1\r\n") && request.contains("Upgrade: websocket"), "{}", request);
+    assert!(response.starts_with("HTTP/1.1 101 "), "{}", response);
+    
+    // 客户端的帧带掩码，服务器的帧不带
+    assert!(inbound.iter().all(|(_, masked, _)| *masked));
+    assert!(outbound.iter().all(|(_, masked, _)| !*masked));
+    assert!(inbound.contains(&(0x9, true, b"lab".to_vec())));
+    assert!(outbound.contains(&(0xa, false, b"lab".to_vec())));
+    let chat = inbound.iter().find(|(opcode, _, _)| *opcode == 0x1).unwrap();
+    let chat: ChatMessage = serde_json::from_slice(&chat.2).unwrap();
+    assert_eq!(chat.text, long_text);
+    assert_eq!(inbound.last().unwrap().0, 0x8);
+    assert_eq!(outbound.last().unwrap().0, 0x8);
+    assert!(outbound.iter().any(|(opcode, _, payload)| *opcode == 0x1 && serde_json::from_slice::<ChatMessage>(payload).unwrap().text == long_text));
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{TestClient, TestServer};
+use net_app::clock::{Clock, ManualClock, Ticker};
+use net_app::history::History;
+use net_app::{AppState, Timeouts};
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn start(clock: &Arc<ManualClock>) -> TestServer {
+    let state = AppState::new(None, History::default()).with_timeouts(Timeouts::default()).with_clock(clock.clone());
+    TestServer::start_with_state(state).await
+}
+
+// 推进时钟后用一次 /users 往返确认服务器已处理完此前的消息
+async fn advance(clock: &ManualClock, client: &mut TestClient, duration: Duration) {
+    clock.advance(duration);
+    client.command("/users").await;
+    client.expect_system("当前房间有").await;
+}
+
+#[actix_web::test]
+async fn ticker_follows_the_manual_clock() {
+    let clock = Arc::new(ManualClock::new());
+    let mut ticker = Ticker::new(clock.clone(), Duration::from_secs(30));
+    let start = clock.now();
+    
+    // 第一次立即触发，之后只在时钟推进满一个周期时触发
+    ticker.tick().await;
+    let next = tokio::time::timeout(Duration::from_millis(50), ticker.tick());
+    assert!(next.await.is_err());
+    clock.advance(Duration::from_secs(30));
+    ticker.tick().await;
+    assert_eq!(clock.elapsed(start), Duration::from_secs(30));
+    
+    // 错过多个周期只补一次，下一次从当前时刻重新计算
+    clock.advance(Duration::from_secs(100));
+    ticker.tick().await;
+    let next = tokio::time::timeout(Duration::from_millis(50), ticker.tick());
+    assert!(next.await.is_err());
+}
+
+#[actix_web::test]
+async fn silent_clients_time_out_after_the_heartbeat_timeout() {
+    let clock = Arc::new(ManualClock::new());
+    let server = start(&clock).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    bob.auto_pong = false;
+    
+    // 默认90秒没有心跳才断开，正好90秒时仍在线
+    for _ in 0..3 {
+        advance(&clock, &mut alice, Duration::from_secs(30)).await;
+    }
+    alice.expect_none("bob 离开", Duration::from_millis(200), |message| {
+        message.msg_type == "userlist" && message.data.as_ref().is_some_and(|data| data["op"] == "leave")
+    }).await;
+    
+    advance(&clock, &mut alice, Duration::from_secs(30)).await;
+    alice.expect_leave(&bob.id).await;
+    bob.expect_closed().await;
+}
+
+#[actix_web::test]
+async fn stale_sessions_are_evicted_after_a_minute() {
+    let clock = Arc::new(ManualClock::new());
+    let server = start(&clock).await;
+    let mut stale = server.connect().await;
+    let mut watcher = server.connect().await;
+    stale.auto_pong = false;
+    
+    // 不到60秒的旧连接不会被新连接清理
+    advance(&clock, &mut watcher, Duration::from_secs(59)).await;
+    let _first = server.connect().await;
+    watcher.expect_none("旧连接被清理", Duration::from_millis(200), |message| {
+        message.msg_type == "userlist" && message.data.as_ref().is_some_and(|data| data["op"] == "leave")
+    }).await;
+    
+    // 观察者刚刚发过消息，只有沉默超过60秒的连接被清理
+    advance(&clock, &mut watcher, Duration::from_secs(2)).await;
+    let _second = server.connect().await;
+    watcher.expect_leave(&stale.id).await;
+    stale.chat("还在吗").await;
+    stale.expect_closed().await;
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use common::{TestClient, TestServer, TIMEOUT};
+use net_app::clock::ManualClock;
+use net_app::cluster;
+use net_app::history::History;
+use net_app::AppState;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+// 启动两个节点：a 监听节点间连接，b 主动连接 a，等待双方互联
+async fn start_pair() -> (TestServer, TestServer) {
+    start_pair_with(AppState::new(None, History::default()), AppState::new(None, History::default())).await
+}
+
+async fn start_pair_with(a: AppState, b: AppState) -> (TestServer, TestServer) {
+    let a = TestServer::start_with_state(a.with_node_id("a".to_string())).await;
+    let b = TestServer::start_with_state(b.with_node_id("b".to_string())).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    actix_web::rt::spawn(cluster::listen(listener, a.state.clone()));
+    actix_web::rt::spawn(cluster::dial(addr.to_string(), b.state.clone()));
+    
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while cluster::nodes(&a.state).is_empty() || cluster::nodes(&b.state).is_empty() {
+        assert!(tokio::time::Instant::now() < deadline, "节点没有互联");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(cluster::nodes(&a.state), ["b"]);
+    assert_eq!(cluster::nodes(&b.state), ["a"]);
+    (a, b)
+}
+
+// 两个节点各自分配用户名，连接到与已有客户端重名时重新连接
+async fn connect_unique(server: &TestServer, others: &[&TestClient]) -> TestClient {
+    loop {
+        let mut client = server.connect().await;
+        if others.iter().all(|other| other.username != client.username) {
+            return client;
+        }
+        client.close().await;
+    }
+}
+
+#[actix_web::test]
+async fn users_on_different_nodes_share_rooms() {
+    let (a, b) = start_pair().await;
+    let mut alice = a.connect().await;
+    let mut bob = connect_unique(&b, &[&alice]).await;
+    
+    // 远端节点的会话出现在用户列表中
+    let joined = alice.expect_userlist("join").await;
+    assert_eq!(joined.data.unwrap()["user"]["id"], bob.id.as_str());
+    alice.command("/users").await;
+    let users = alice.expect_system("当前房间有 2 名用户").await;
+    assert!(users.text.contains(&bob.username), "{}", users.text);
+    
+    bob.chat("来自节点b").await;
+    let message = alice.expect("bob 的聊天消息", |message| message.msg_type == "chat" && message.text == "来自节点b").await;
+    assert_eq!(message.username, bob.username);
+    
+    alice.private(&bob.username, "跨节点私聊").await;
+    let received = bob.expect("私聊消息", |message| message.msg_type == "private").await;
+    assert_eq!(received.username, alice.username);
+    assert_eq!(received.text, "跨节点私聊");
+    
+    // 换房间后两个节点的成员仍能互相看到。目录同步是异步的，以对方节点发来的用户列表增量为准
+    alice.join("书房").await;
+    bob.expect_leave(&alice.id).await;
+    bob.join("书房").await;
+    alice.expect_system(&format!("{} 加入了房间", bob.username)).await;
+    let joined = alice.expect_userlist("join").await;
+    assert_eq!(joined.data.unwrap()["user"]["id"], bob.id.as_str());
+    bob.command("/users").await;
+    let users = bob.expect_system("当前房间有 2 名用户").await;
+    assert!(users.text.contains(&alice.username), "{}", users.text);
+    bob.command("/rooms").await;
+    let rooms = bob.expect_system("可用房间").await;
+    assert!(rooms.text.contains("书房 (2 人在线)"), "{}", rooms.text);
+    
+    alice.chat("书房见").await;
+    bob.expect("alice 的聊天消息", |message| message.msg_type == "chat" && message.text == "书房见" && message.room == "书房").await;
+    
+    bob.close().await;
+    alice.expect_system(&format!("{} 离开了聊天室", bob.username)).await;
+    alice.expect_leave(&bob.id).await;
+}
+
+#[actix_web::test]
+async fn sessions_of_a_failed_node_are_removed() {
+    let (a, b) = start_pair().await;
+    let mut alice = a.connect().await;
+    let bob = connect_unique(&b, &[&alice]).await;
+    alice.expect_userlist("join").await;
+    
+    // 节点b退出集群后，它的会话从节点a的用户列表中移除
+    cluster::shutdown(&b.state);
+    alice.expect_leave(&bob.id).await;
+    assert!(cluster::nodes(&a.state).is_empty());
+    alice.command("/users").await;
+    alice.expect_system("当前房间有 1 名用户").await;
+}
+
+#[actix_web::test]
+async fn periodic_directories_do_not_report_spurious_updates() {
+    let clock = Arc::new(ManualClock::new());
+    let (a, b) = start_pair_with(AppState::new(None, History::default()), AppState::new(None, History::default()).with_clock(clock.clone())).await;
+    let mut alice = a.connect().await;
+    let mut bob = connect_unique(&b, &[&alice]).await;
+    let joined = alice.expect_userlist("join").await;
+    let join_time = joined.data.unwrap()["user"]["join_time"].clone();
+    
+    // 节点b每5秒发送一次完整目录。加入时间只在建立会话时记录，不随目录发送的时刻抖动
+    for round in 0..10 {
+        clock.advance(Duration::from_millis(5_100));
+        // 房间消息经节点a转发给节点b，保持节点间连接不超时
+        let text = format!("第{}轮", round);
+        alice.chat(&text).await;
+        bob.expect("转发的聊天消息", |message| message.msg_type == "chat" && message.text == text).await;
+    }
+    // RTT变化会产生正常的更新，但加入时间不变
+    assert!(join_time.is_u64(), "{}", join_time);
+    alice.expect_none("加入时间变化的更新", Duration::from_millis(300), |message| {
+        message.msg_type == "userlist" && message.data.as_ref().is_some_and(|data| data["op"] == "update" && data["user"]["id"] == bob.id.as_str() && data["user"]["join_time"] != join_time)
+    }).await;
+    alice.command("/users").await;
+    alice.expect_system("当前房间有 2 名用户").await;
+}
+
+#[actix_web::test]
+async fn nodes_without_the_shared_secret_are_rejected() {
+    let state = |node: &str, secret: &str| AppState::new(None, History::default()).with_node_id(node.to_string()).with_cluster_secret(secret.to_string());
+    let a = TestServer::start_with_state(state("a", "口令")).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    actix_web::rt::spawn(cluster::listen(listener, a.state.clone()));
+    
+    // 口令不同的节点无法加入
+    let intruder = TestServer::start_with_state(state("x", "猜的")).await;
+    actix_web::rt::spawn(cluster::dial(addr.to_string(), intruder.state.clone()));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(cluster::nodes(&a.state).is_empty());
+    assert!(cluster::nodes(&intruder.state).is_empty());
+    cluster::shutdown(&intruder.state);
+    
+    // 口令相同的节点正常互联
+    let b = TestServer::start_with_state(state("b", "口令")).await;
+    actix_web::rt::spawn(cluster::dial(addr.to_string(), b.state.clone()));
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while cluster::nodes(&a.state).is_empty() {
+        assert!(tokio::time::Instant::now() < deadline, "节点没有互联");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(cluster::nodes(&a.state), ["b"]);
+    assert_eq!(cluster::nodes(&b.state), ["a"]);
+}
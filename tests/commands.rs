@@ -0,0 +1,146 @@
+mod common;
+
+use common::TestServer;
+use futures_util::future::BoxFuture;
+use net_app::bot::{self, Bot, Reply};
+use net_app::commands::{Arg, Call, Command, CommandSpec, Permission};
+use net_app::history::History;
+use net_app::protocol::ChatMessage;
+use net_app::AppState;
+use std::sync::Arc;
+use std::time::Duration;
+
+// 把参数转成大写后回复
+struct Shout {
+    spec: CommandSpec,
+}
+
+impl Command for Shout {
+    fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+    
+    fn run<'a>(&'a self, call: Call<'a>) -> BoxFuture<'a, String> {
+        Box::pin(async move { call.args.join(" ").to_uppercase() })
+    }
+}
+
+// 只有管理员可以使用，回复发出命令的用户名
+struct Whoami;
+
+impl Command for Whoami {
+    fn spec(&self) -> &CommandSpec {
+        &CommandSpec { name: "/whoami", args: &[], help: "显示自己的用户名", permission: Permission::Admin }
+    }
+    
+    fn run<'a>(&'a self, call: Call<'a>) -> BoxFuture<'a, String> {
+        Box::pin(async move { format!("你是 {}", call.username().unwrap_or_default()) })
+    }
+}
+
+// 房间中的 !count 回复消息长度，其余消息加上 👀
+struct Counter;
+
+impl Bot for Counter {
+    fn name(&self) -> &str {
+        "计数"
+    }
+    
+    fn on_message(&self, message: &ChatMessage) -> Vec<Reply> {
+        match message.text.strip_prefix("!count ") {
+            Some(text) => vec![Reply::Room(format!("{} 个字符", text.chars().count()))],
+            None if message.msg_type == "private" => vec![Reply::Private { to: message.username.clone(), text: "收到".to_string() }],
+            None => vec![Reply::Reaction("👀".to_string())],
+        }
+    }
+}
+
+#[actix_web::test]
+async fn registered_commands_are_dispatched_with_permissions_and_help() {
+    let shout = Shout {
+        spec: CommandSpec { name: "/shout", args: &[Arg::Required("文本")], help: "大声说", permission: Permission::User },
+    };
+    let state = AppState::new(Some("口令".to_string()), History::default()).with_command(shout).with_command(Whoami);
+    let server = TestServer::start_with_state(state).await;
+    let mut alice = server.connect().await;
+    
+    // /help 由注册表生成，包括内置命令、各模块注册的命令与自定义命令
+    alice.command("/help").await;
+    let help = alice.expect_system("可用命令:").await.text;
+    for name in ["/help", "/join", "/msg", "/heartbeat", "/speedtest", "/traceroute", "/net", "/stats", "/search", "/export", "/admin", "/bot", "/shout", "/whoami"] {
+        assert!(help.lines().any(|line| line.starts_with(name)), "/help 缺少 {}: {}", name, help);
+    }
+    assert!(help.contains("/shout <文本> - 大声说"), "{}", help);
+    assert!(help.contains("/whoami - 显示自己的用户名（管理员）"), "{}", help);
+    alice.command("/help msg").await;
+    alice.expect_system("用法: /msg <用户名> <消息>\n发送私聊消息").await;
+    
+    // 参数不足时回复用法
+    alice.command("/shout").await;
+    alice.expect_system("用法: /shout <文本>").await;
+    alice.command("/shout hello world").await;
+    alice.expect_system("HELLO WORLD").await;
+    
+    // 管理员命令
+    alice.command("/whoami").await;
+    alice.expect_system("只有管理员可以使用 /whoami").await;
+    alice.command("/bot list").await;
+    alice.expect_system("只有管理员可以使用 /bot").await;
+    alice.command("/admin 口令").await;
+    alice.expect_system("已获得管理员权限").await;
+    alice.command("/whoami").await;
+    alice.expect_system(&format!("你是 {}", alice.username)).await;
+    
+    alice.command("/nope").await;
+    alice.expect_system("未知命令: /nope").await;
+}
+
+#[actix_web::test]
+async fn bots_reply_through_the_normal_message_paths() {
+    let server = TestServer::start_with(Some("口令"), net_app::Timeouts::default()).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    
+    // 机器人像普通会话一样加入房间并出现在用户列表中
+    bot::spawn(server.state.clone(), Arc::new(Counter), "大厅").await.expect("spawn bot");
+    alice.expect("机器人加入", |message| {
+        message.msg_type == "userlist" && message.data.as_ref().is_some_and(|data| data["op"] == "join" && data["user"]["username"] == "计数")
+    }).await;
+    assert!(bot::spawn(server.state.clone(), Arc::new(Counter), "书房").await.is_err(), "同名机器人只能有一个");
+    
+    // 回复作为机器人的消息广播给房间
+    bob.chat("!count 你好世界").await;
+    alice.expect("机器人的回复", |message| message.msg_type == "chat" && message.username == "计数" && message.text == "4 个字符").await;
+    bob.expect("机器人的回复", |message| message.msg_type == "chat" && message.username == "计数" && message.text == "4 个字符").await;
+    
+    // 表情回应针对触发的消息
+    alice.chat("早上好").await;
+    let original = alice.expect("自己的消息", |message| message.msg_type == "chat" && message.text == "早上好").await;
+    let reaction = bob.expect("机器人的回应", |message| message.msg_type == "reaction" && message.username == "计数").await;
+    assert_eq!(reaction.id, original.id);
+    assert_eq!(reaction.text, "👀");
+    
+    // 私聊只回复给发送者
+    alice.private("计数", "在吗").await;
+    alice.expect("机器人的私聊回复", |message| message.msg_type == "private" && message.username == "计数" && message.text == "收到").await;
+    bob.expect_none("机器人的私聊回复", Duration::from_millis(300), |message| message.msg_type == "private").await;
+    
+    // 管理员通过 /bot 管理内置机器人
+    alice.command("/admin 口令").await;
+    alice.expect_system("已获得管理员权限").await;
+    alice.command("/bot add echo 书房").await;
+    alice.expect_system("机器人 回声 已加入房间 书房").await;
+    alice.command("/bot list").await;
+    let list = alice.expect_system("机器人:").await.text;
+    assert!(list.contains("回声 (房间 书房)") && list.contains("计数 (房间 大厅)"), "{}", list);
+    
+    bob.join("书房").await;
+    bob.chat("@回声 喂").await;
+    let expected = format!("{}: 喂", bob.username);
+    bob.expect("回声的回复", |message| message.username == "回声" && message.text == expected).await;
+    
+    alice.command("/bot remove 回声").await;
+    alice.expect_system("已移除机器人 回声").await;
+    alice.command("/bot add nope").await;
+    alice.expect_system("未知的机器人类型: nope").await;
+}
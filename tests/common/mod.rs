@@ -0,0 +1,384 @@
+// 集成测试工具：在进程内以临时端口启动服务器，并提供按脚本收发消息的WebSocket客户端。
+// 测试函数需使用 #[actix_web::test]，服务器内部依赖actix的运行时。
+#![allow(dead_code)]
+
+use actix_web::dev::ServerHandle;
+use actix_web::{web, App, HttpServer};
+use futures_util::{SinkExt, StreamExt};
+use net_app::history::History;
+use net_app::protocol::ChatMessage;
+use net_app::{AppState, Timeouts};
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+// 等待期望消息的默认时长
+pub const TIMEOUT: Duration = Duration::from_secs(3);
+
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub state: Arc<AppState>,
+    handle: ServerHandle,
+    usernames: Mutex<HashSet<String>>, // 已分配给客户端的用户名
+}
+
+impl TestServer {
+    pub async fn start() -> TestServer {
+        TestServer::start_with(None, Timeouts::default()).await
+    }
+    
+    // 使用指定的管理员口令与心跳参数启动，历史只保存在内存中
+    pub async fn start_with(admin_token: Option<&str>, timeouts: Timeouts) -> TestServer {
+        TestServer::start_with_state(AppState::new(admin_token.map(str::to_string), History::default()).with_timeouts(timeouts)).await
+    }
+    
+    // 使用自行构造的状态启动，例如换成手动推进的时钟
+    pub async fn start_with_state(state: AppState) -> TestServer {
+        let state = Arc::new(state);
+        let app_state = web::Data::new(state.clone());
+        let server = HttpServer::new(move || App::new().app_data(app_state.clone()).configure(net_app::configure))
+            .workers(1)
+            .disable_signals()
+            .bind("127.0.0.1:0")
+            .expect("bind test server");
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        
+        TestServer { addr, state, handle, usernames: Mutex::new(HashSet::new()) }
+    }
+    
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+    
+    // 以GET请求订阅SSE接口，返回状态码与事件流（状态码不是200时事件流为空）
+    pub async fn sse(&self, path: &str) -> (u16, SseClient) {
+        let stream = TcpStream::connect(self.addr).await.expect("connect http");
+        let mut reader = BufReader::new(stream);
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nAccept: text/event-stream\r\n\r\n", path, self.addr);
+        reader.get_mut().write_all(request.as_bytes()).await.expect("send request");
+        
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).await.expect("read status");
+        let status = status_line.split(' ').nth(1).and_then(|code| code.parse().ok()).expect("status code");
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.expect("read header");
+            if header == "\r\n" {
+                break;
+            }
+        }
+        (status, SseClient { reader, buffer: String::new() })
+    }
+    
+    // 发送一次HTTP请求（Connection: close），返回状态码与响应体。响应需带Content-Length而非分块编码
+    pub async fn http(&self, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        self.http_with(method, path, &[], body).await
+    }
+    
+    // 带额外请求头的HTTP请求，例如 ("Authorization", "Bearer <口令>")
+    pub async fn http_with(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(self.addr).await.expect("connect http");
+        let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+        let request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n", method, path, self.addr, headers, body.len());
+        stream.write_all(request.as_bytes()).await.expect("send request");
+        stream.write_all(body).await.expect("send body");
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.expect("read response");
+        
+        let split = response.windows(4).position(|window| window == b"\r\n\r\n").expect("end of headers");
+        let head = String::from_utf8_lossy(&response[..split]).to_string();
+        let status = head.split(' ').nth(1).and_then(|code| code.parse().ok()).expect("status code");
+        (status, response[split + 4..].to_vec())
+    }
+    
+    // 用管理员口令换取协议监视流的一次性订阅凭证
+    pub async fn monitor_ticket(&self, token: &str) -> String {
+        let bearer = format!("Bearer {}", token);
+        let (status, body) = self.http_with("POST", "/api/monitor/ticket", &[("Authorization", &bearer)], b"").await;
+        assert_eq!(status, 200);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["ticket"].as_str().expect("ticket").to_string()
+    }
+    
+    // 建立WebSocket连接，等待服务器分配用户名并发送大厅的用户列表。
+    // 服务器的默认用户名只有三位随机数字，与本服务器上已有客户端重名时重新连接，保证测试中的用户名唯一
+    pub async fn connect(&self) -> TestClient {
+        loop {
+            let mut client = TestClient::connect(self.addr).await;
+            if self.usernames.lock().unwrap().insert(client.username.clone()) {
+                return client;
+            }
+            client.close().await;
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let handle = self.handle.clone();
+        actix_web::rt::spawn(async move { handle.stop(false).await });
+    }
+}
+
+pub struct TestClient {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    pub id: String,
+    pub username: String,
+    pub room: String,
+    // 自动回复服务器的心跳ping，关闭后可模拟不响应心跳的客户端
+    pub auto_pong: bool,
+    // 正在进行的下载测速ID，设置后自动确认测速的数据帧（文本与二进制）并跳过它们
+    pub speedtest: Option<String>,
+    pending: VecDeque<ChatMessage>, // expect 跳过的消息，留给后续的读取
+}
+
+impl TestClient {
+    async fn connect(addr: SocketAddr) -> TestClient {
+        let (socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr)).await.expect("connect websocket");
+        let mut client = TestClient {
+            socket,
+            id: String::new(),
+            username: String::new(),
+            room: "大厅".to_string(),
+            auto_pong: true,
+            speedtest: None,
+            pending: VecDeque::new(),
+        };
+        
+        let init = client.expect("用户名初始化消息", |message| message.msg_type == "chat" && message.text.is_empty()).await;
+        client.username = init.username;
+        // 完整用户列表中包含自己，从中取得会话ID
+        let snapshot = client.expect_userlist("snapshot").await;
+        let users = snapshot.data.unwrap()["users"].as_array().cloned().unwrap_or_default();
+        client.id = users.iter()
+            .find(|user| user["username"] == client.username.as_str())
+            .and_then(|user| user["id"].as_str())
+            .expect("own entry in user list")
+            .to_string();
+        client
+    }
+    
+    pub async fn send(&mut self, message: ChatMessage) {
+        let json = serde_json::to_string(&message).unwrap();
+        self.socket.send(Message::Text(json)).await.expect("send frame");
+    }
+    
+    pub async fn chat(&mut self, text: &str) {
+        let message = ChatMessage::new("chat", &self.username, &self.room, text);
+        self.send(message).await;
+    }
+    
+    pub async fn command(&mut self, text: &str) {
+        let message = ChatMessage::new("command", &self.username, &self.room, text);
+        self.send(message).await;
+    }
+    
+    pub async fn private(&mut self, target: &str, text: &str) {
+        let mut message = ChatMessage::new("private", &self.username, "私聊", text);
+        message.target = Some(target.to_string());
+        self.send(message).await;
+    }
+    
+    // 加入房间并等待该房间的完整用户列表
+    pub async fn join(&mut self, room: &str) {
+        let message = ChatMessage::new("join", &self.username, room, "");
+        self.send(message).await;
+        let room = room.to_string();
+        self.expect(&format!("{} 的用户列表", room), |message| {
+            message.msg_type == "userlist" && message.room == room && message.data.as_ref().is_some_and(|data| data["op"] == "snapshot")
+        }).await;
+        self.room = room;
+    }
+    
+    // 发送WebSocket协议层的ping，服务器的pong由 next_frame 跳过
+    pub async fn ping(&mut self, data: &[u8]) {
+        self.socket.send(Message::Ping(data.to_vec())).await.expect("send ping");
+    }
+    
+    // 发送任意文本帧，用于测试服务器如何处理格式错误的消息
+    pub async fn text(&mut self, text: &str) {
+        self.socket.send(Message::Text(text.to_string())).await.expect("send text");
+    }
+    
+    pub async fn binary(&mut self, data: &[u8]) {
+        self.socket.send(Message::Binary(data.to_vec())).await.expect("send binary");
+    }
+    
+    // 确认一帧测速数据
+    async fn ack_speedtest(&mut self, test: String) {
+        let mut ack = ChatMessage::new("speedtest", &self.username, &self.room, "");
+        ack.data = Some(serde_json::json!({ "op": "ack", "test": test }));
+        self.send(ack).await;
+    }
+    
+    pub async fn close(&mut self) {
+        let _ = self.socket.close(None).await;
+    }
+    
+    // 读取下一条JSON消息，先返回之前被 expect 跳过的消息；连接关闭或超时返回None。
+    // auto_pong 为真时自动回复并跳过服务器心跳
+    pub async fn next_frame(&mut self, timeout: Duration) -> Option<ChatMessage> {
+        if let Some(frame) = self.pending.pop_front() {
+            return Some(frame);
+        }
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let message = tokio::time::timeout_at(deadline, self.socket.next()).await.ok()??.ok()?;
+            let Message::Text(text) = message else {
+                if let (Message::Binary(_), Some(test)) = (&message, self.speedtest.clone()) {
+                    self.ack_speedtest(test).await;
+                    continue;
+                }
+                if message.is_close() {
+                    return None;
+                }
+                continue;
+            };
+            let frame: ChatMessage = serde_json::from_str(&text).expect("server frame is a ChatMessage");
+            if frame.msg_type == "ping" && self.auto_pong {
+                let pong = ChatMessage::new("pong", &self.username, &self.room, &frame.text);
+                self.send(pong).await;
+                continue;
+            }
+            let test = frame.data.as_ref().filter(|data| frame.msg_type == "speedtest" && data["op"] == "data").and_then(|data| data["test"].as_str());
+            if let Some(test) = test.filter(|test| self.speedtest.as_deref() == Some(*test)) {
+                self.ack_speedtest(test.to_string()).await;
+                continue;
+            }
+            return Some(frame);
+        }
+    }
+    
+    // 等待满足条件的消息。其间的其他消息按顺序保留，后续的 expect 仍能匹配到；超时则带着这些消息失败
+    pub async fn expect(&mut self, description: &str, predicate: impl Fn(&ChatMessage) -> bool) -> ChatMessage {
+        if let Some(index) = self.pending.iter().position(&predicate) {
+            return self.pending.remove(index).unwrap();
+        }
+        let deadline = tokio::time::Instant::now() + TIMEOUT;
+        let mut skipped = std::mem::take(&mut self.pending);
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            match self.next_frame(remaining).await {
+                Some(frame) if predicate(&frame) => {
+                    self.pending = skipped;
+                    return frame;
+                }
+                Some(frame) => skipped.push_back(frame),
+                None => {
+                    let skipped: Vec<_> = skipped.iter().map(|frame| format!("{} {:?}", frame.msg_type, frame.text)).collect();
+                    panic!("{} 没有收到期望的消息: {}，期间收到: {:#?}", self.username, description, skipped)
+                }
+            }
+        }
+    }
+    
+    pub async fn expect_system(&mut self, contains: &str) -> ChatMessage {
+        self.expect(&format!("包含 {:?} 的系统消息", contains), |message| {
+            message.msg_type == "system" && message.text.contains(contains)
+        }).await
+    }
+    
+    pub async fn expect_userlist(&mut self, op: &str) -> ChatMessage {
+        self.expect(&format!("{} 用户列表消息", op), |message| {
+            message.msg_type == "userlist" && message.data.as_ref().is_some_and(|data| data["op"] == op)
+        }).await
+    }
+    
+    // 等待某个会话离开当前房间的用户列表增量
+    pub async fn expect_leave(&mut self, id: &str) -> ChatMessage {
+        self.expect(&format!("会话 {} 离开", id), |message| {
+            message.msg_type == "userlist" && message.data.as_ref().is_some_and(|data| data["op"] == "leave" && data["id"] == id)
+        }).await
+    }
+    
+    // 在给定时间内不应收到满足条件的消息
+    pub async fn expect_none(&mut self, description: &str, within: Duration, predicate: impl Fn(&ChatMessage) -> bool) {
+        let deadline = tokio::time::Instant::now() + within;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if remaining.is_zero() {
+                return;
+            }
+            match self.next_frame(remaining).await {
+                Some(frame) if predicate(&frame) => panic!("{} 收到了不应出现的消息 {}: {:?}", self.username, description, frame),
+                Some(_) => {}
+                None => return,
+            }
+        }
+    }
+    
+    // 等待服务器关闭连接
+    pub async fn expect_closed(&mut self) {
+        let deadline = tokio::time::Instant::now() + TIMEOUT;
+        loop {
+            match tokio::time::timeout_at(deadline, self.socket.next()).await {
+                Err(_) => panic!("{} 的连接没有被服务器关闭", self.username),
+                Ok(None) | Ok(Some(Err(_))) => return,
+                Ok(Some(Ok(message))) if message.is_close() => return,
+                Ok(Some(Ok(_))) => {}
+            }
+        }
+    }
+}
+
+// SSE事件流，响应体为分块传输编码
+pub struct SseClient {
+    reader: BufReader<TcpStream>,
+    buffer: String,
+}
+
+impl SseClient {
+    // 读取下一个带数据的事件，返回 (事件名, 数据)，跳过注释行；连接关闭或超时返回None
+    pub async fn next_event(&mut self, timeout: Duration) -> Option<(String, serde_json::Value)> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            while let Some(end) = self.buffer.find("\n\n") {
+                let block: String = self.buffer.drain(..end + 2).collect();
+                let mut name = "message".to_string();
+                let mut data = None;
+                for line in block.lines() {
+                    if let Some(value) = line.strip_prefix("event: ") {
+                        name = value.to_string();
+                    } else if let Some(value) = line.strip_prefix("data: ") {
+                        data = Some(serde_json::from_str(value).expect("event data is JSON"));
+                    }
+                }
+                if let Some(data) = data {
+                    return Some((name, data));
+                }
+            }
+            tokio::time::timeout_at(deadline, self.read_chunk()).await.ok()??;
+        }
+    }
+    
+    // 等待满足条件的事件，其间的其他事件被丢弃
+    pub async fn expect(&mut self, description: &str, predicate: impl Fn(&serde_json::Value) -> bool) -> serde_json::Value {
+        let deadline = tokio::time::Instant::now() + TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            match self.next_event(remaining).await {
+                Some((_, data)) if predicate(&data) => return data,
+                Some(_) => {}
+                None => panic!("没有收到期望的事件: {}", description),
+            }
+        }
+    }
+    
+    async fn read_chunk(&mut self) -> Option<()> {
+        let mut size_line = String::new();
+        self.reader.read_line(&mut size_line).await.ok()?;
+        let size = usize::from_str_radix(size_line.trim(), 16).ok().filter(|size| *size > 0)?;
+        let mut chunk = vec![0u8; size + 2];
+        self.reader.read_exact(&mut chunk).await.ok()?;
+        self.buffer.push_str(std::str::from_utf8(&chunk[..size]).expect("utf-8 chunk"));
+        Some(())
+    }
+}
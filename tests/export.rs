@@ -0,0 +1,37 @@
+mod common;
+
+use common::TestServer;
+use net_app::Timeouts;
+
+#[actix_web::test]
+async fn private_messages_are_exported_only_to_participants() {
+    let server = TestServer::start_with(Some("secret"), Timeouts::default()).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    let mut carol = server.connect().await;
+    
+    alice.chat("room hello").await;
+    alice.private(&bob.username, "secret plan").await;
+    bob.expect("私聊消息", |message| message.msg_type == "private" && message.text == "secret plan").await;
+    
+    // 参与者导出的记录包含私聊
+    for client in [&mut alice, &mut bob] {
+        client.command("/export json").await;
+        let export = client.expect("导出结果", |message| message.msg_type == "export").await;
+        let content = export.data.unwrap()["content"].as_str().unwrap().to_string();
+        assert!(content.contains("room hello") && content.contains("secret plan"), "{}", content);
+    }
+    
+    // 管理员不是参与者，同样看不到
+    carol.command("/admin secret").await;
+    carol.expect_system("已获得管理员权限").await;
+    carol.command("/export json").await;
+    let export = carol.expect("导出结果", |message| message.msg_type == "export").await;
+    let content = export.data.unwrap()["content"].as_str().unwrap().to_string();
+    assert!(content.contains("room hello") && !content.contains("secret plan"), "{}", content);
+    
+    let (status, body) = server.http_with("GET", "/api/rooms/%E5%A4%A7%E5%8E%85/export?format=json", &[("Authorization", "Bearer secret")], b"").await;
+    assert_eq!(status, 200);
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains("room hello") && !body.contains("secret plan"), "{}", body);
+}
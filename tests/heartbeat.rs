@@ -0,0 +1,127 @@
+mod common;
+
+use common::TestServer;
+use net_app::clock::ManualClock;
+use net_app::heartbeat::{HeartbeatMode, HeartbeatPolicy};
+use net_app::history::History;
+use net_app::protocol::ChatMessage;
+use net_app::AppState;
+use std::sync::Arc;
+use std::time::Duration;
+
+const TOKEN: &str = "secret";
+
+async fn start(clock: Arc<ManualClock>, policy: HeartbeatPolicy) -> TestServer {
+    let state = AppState::new(Some(TOKEN.to_string()), History::default()).with_clock(clock).with_heartbeat(policy);
+    TestServer::start_with_state(state).await
+}
+
+async fn subscribe(server: &TestServer, query: &str) -> common::SseClient {
+    let (status, mut monitor) = server.sse(&format!("/api/monitor?ticket={}&{}", server.monitor_ticket(TOKEN).await, query)).await;
+    assert_eq!(status, 200);
+    monitor.next_event(common::TIMEOUT).await.unwrap();
+    monitor
+}
+
+#[actix_web::test]
+async fn adaptive_heartbeat_backs_off_when_idle_and_probes_after_a_missed_pong() {
+    let clock = Arc::new(ManualClock::new());
+    let server = start(clock.clone(), HeartbeatPolicy { mode: HeartbeatMode::Adaptive, ..HeartbeatPolicy::default() }).await;
+    let mut monitor = subscribe(&server, "event=heartbeat,pong,timeout").await;
+    let mut alice = server.connect().await;
+    let id = alice.id.clone();
+    let is_alice = |kind: &'static str| {
+        let id = id.clone();
+        move |event: &serde_json::Value| event["event"] == kind && event["session"] == id.as_str()
+    };
+    
+    // 不读取消息，手动回复pong（不算主动操作），间隔随空闲逐次翻倍
+    for (interval_ms, advance) in [(30_000, 30), (60_000, 60), (120_000, 120)] {
+        let heartbeat = monitor.expect("alice 的心跳", is_alice("heartbeat")).await;
+        assert_eq!(heartbeat["data"]["mode"], "adaptive");
+        assert_eq!(heartbeat["data"]["interval_ms"], interval_ms);
+        if interval_ms < 120_000 {
+            alice.send(ChatMessage::new("pong", &alice.username, &alice.room, "")).await;
+            monitor.expect("alice 的pong", is_alice("pong")).await;
+        }
+        clock.advance(Duration::from_secs(advance));
+    }
+    
+    // 上一次心跳没有响应，间隔缩短到5秒的下限；超时随之回到90秒，再过5秒断开
+    let probe = monitor.expect("缩短间隔后的心跳", is_alice("heartbeat")).await;
+    assert_eq!(probe["data"]["interval_ms"], 5_000);
+    assert_eq!(probe["data"]["timeout_ms"], 90_000);
+    assert_eq!(probe["data"]["unanswered"], 1);
+    clock.advance(Duration::from_secs(5));
+    let timeout = monitor.expect("alice 超时", is_alice("timeout")).await;
+    assert_eq!(timeout["data"]["mode"], "adaptive");
+    assert_eq!(timeout["data"]["unanswered"], 2);
+    assert_eq!(timeout["data"]["silent_ms"], 185_000);
+    assert!(timeout["data"]["reason"].as_str().unwrap().contains("连续 2 次心跳未响应"));
+    alice.expect_closed().await;
+}
+
+#[actix_web::test]
+async fn clients_negotiate_protocol_pings_and_can_disable_heartbeats() {
+    let clock = Arc::new(ManualClock::new());
+    let server = start(clock.clone(), HeartbeatPolicy { allow_disabled: true, ..HeartbeatPolicy::default() }).await;
+    let mut monitor = subscribe(&server, "event=heartbeat,pong,timeout").await;
+    let mut alice = server.connect().await;
+    let id = alice.id.clone();
+    monitor.expect("第一次心跳", |event| event["event"] == "heartbeat" && event["data"]["ping"] == "app").await;
+    
+    let mut request = ChatMessage::new("heartbeat", &alice.username, &alice.room, "");
+    request.data = Some(serde_json::json!({ "ping": "protocol", "interval_ms": 10_000 }));
+    alice.send(request).await;
+    let reply = alice.expect("心跳协商结果", |message| message.msg_type == "heartbeat").await;
+    let data = reply.data.unwrap();
+    assert_eq!(data["mode"], "fixed");
+    assert_eq!(data["ping"], "protocol");
+    assert_eq!(data["interval_ms"], 10_000);
+    
+    // 协议层Ping帧由WebSocket库自动回复Pong，服务器据此计算RTT
+    clock.advance(Duration::from_secs(10));
+    let heartbeat = monitor.expect("协议层心跳", |event| event["event"] == "heartbeat" && event["session"] == id.as_str()).await;
+    assert_eq!(heartbeat["data"]["ping"], "protocol");
+    alice.command("/users").await;
+    alice.expect_system("当前房间有").await;
+    monitor.expect("协议层Pong", |event| event["event"] == "pong" && event["data"]["rtt_ms"].is_u64()).await;
+    
+    // 间隔限制在5秒到300秒之间；关闭心跳后长时间沉默也不会断开
+    alice.command("/heartbeat adaptive 1").await;
+    alice.expect_system("当前间隔 5.0 秒").await;
+    alice.command("/heartbeat off").await;
+    alice.expect_system("心跳已关闭").await;
+    clock.advance(Duration::from_secs(600));
+    alice.command("/stats").await;
+    let stats = alice.expect_system("网络统计信息").await;
+    assert!(stats.text.contains("心跳 fixed: 发送 2 次，超时断开 0 次"), "{}", stats.text);
+}
+
+#[actix_web::test]
+async fn fixed_timeout_covers_a_long_negotiated_interval() {
+    let clock = Arc::new(ManualClock::new());
+    let server = start(clock.clone(), HeartbeatPolicy::default()).await;
+    let mut monitor = subscribe(&server, "event=heartbeat,timeout").await;
+    let mut alice = server.connect().await;
+    let id = alice.id.clone();
+    monitor.expect("第一次心跳", |event| event["event"] == "heartbeat" && event["session"] == id.as_str()).await;
+    
+    // 间隔长于90秒时，超时取3倍间隔
+    alice.command("/heartbeat fixed 120").await;
+    alice.expect_system("当前间隔 120.0 秒，超时 360.0 秒").await;
+    
+    // 默认策略不允许客户端关闭心跳
+    alice.command("/heartbeat off").await;
+    alice.expect_system("服务器不允许关闭心跳").await;
+    
+    // 两次心跳之间沉默了120秒，仍然发送心跳而不是断开
+    clock.advance(Duration::from_secs(120));
+    let heartbeat = monitor.expect("协商间隔后的心跳", |event| {
+        (event["event"] == "heartbeat" || event["event"] == "timeout") && event["session"] == id.as_str()
+    }).await;
+    assert_eq!(heartbeat["event"], "heartbeat", "{}", heartbeat);
+    assert_eq!(heartbeat["data"]["timeout_ms"], 360_000);
+    alice.command("/users").await;
+    alice.expect_system("当前房间有").await;
+}
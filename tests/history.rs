@@ -0,0 +1,52 @@
+use net_app::history::History;
+use net_app::protocol::ChatMessage;
+
+#[test]
+fn read_markers_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("net_app-{}.jsonl", uuid::Uuid::new_v4()));
+    let first = ChatMessage::new("chat", "用户001", "大厅", "第一条");
+    let second = ChatMessage::new("chat", "用户001", "大厅", "第二条");
+    let mut whisper = ChatMessage::new("private", "用户001", "大厅", "悄悄话");
+    whisper.target = Some("用户002".to_string());
+    {
+        let mut history = History::open(&path).expect("open history");
+        history.record(&first);
+        history.record(&second);
+        history.record_private(&whisper, ["会话1", "会话2"]);
+        assert!(history.mark_read("用户002", "会话2", "大厅", &first.id).is_some());
+        assert!(history.mark_read("用户002", "会话2", "大厅", &whisper.id).is_some());
+        // 旧消息的回执不会让已读位置后退
+        assert!(history.mark_read("用户002", "会话2", "大厅", &first.id).is_some());
+    }
+    
+    let history = History::open(&path).expect("reopen history");
+    let counts = history.unread_counts("用户002", "会话2", "大厅");
+    assert_eq!(counts.rooms.get("大厅"), Some(&1));
+    assert!(counts.private.is_empty(), "{:?}", counts);
+    assert_eq!(history.unread_counts("用户003", "会话3", "大厅").rooms.get("大厅"), Some(&2));
+    
+    // 私聊已读位置按会话记录，不写入日志；位置没有前进的回执也不写入日志
+    let lines = std::fs::read_to_string(&path).unwrap().lines().filter(|line| line.contains("\"kind\":\"read\"")).count();
+    assert_eq!(lines, 1);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn a_reused_username_does_not_inherit_private_read_state() {
+    let mut history = History::default();
+    let mut whisper = ChatMessage::new("private", "用户001", "大厅", "悄悄话");
+    whisper.target = Some("用户002".to_string());
+    history.record_private(&whisper, ["会话1", "会话2"]);
+    assert_eq!(history.unread_counts("用户002", "会话2", "大厅").private.get("用户001"), Some(&1));
+    
+    // 原来的用户002断开后，新连接分到了同一个用户名
+    let counts = history.unread_counts("用户002", "会话3", "大厅");
+    assert!(counts.private.is_empty(), "{:?}", counts);
+    assert!(history.mark_read("用户002", "会话3", "大厅", &whisper.id).is_none());
+    // 发送方也不能替接收方确认已读
+    assert!(history.mark_read("用户001", "会话1", "大厅", &whisper.id).is_none());
+    assert_eq!(history.unread_counts("用户002", "会话2", "大厅").private.get("用户001"), Some(&1));
+    
+    assert!(history.mark_read("用户002", "会话2", "大厅", &whisper.id).is_some());
+    assert!(history.unread_counts("用户002", "会话2", "大厅").private.is_empty());
+}
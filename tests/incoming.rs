@@ -0,0 +1,79 @@
+mod common;
+
+use common::TestServer;
+use net_app::Timeouts;
+use std::time::Duration;
+
+const TOKEN: &str = "secret";
+
+async fn post(server: &TestServer, token: &str, body: &str) -> (u16, serde_json::Value) {
+    let (status, response) = server.http_with("POST", &format!("/api/hooks/{}", token), &[("Content-Type", "application/json")], body.as_bytes()).await;
+    (status, serde_json::from_slice(&response).unwrap_or_default())
+}
+
+#[actix_web::test]
+async fn tokens_post_rate_limited_messages_into_a_room() {
+    let server = TestServer::start_with(Some(TOKEN), Timeouts::default()).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    bob.join("实验室").await;
+    
+    // 令牌只能由管理员管理
+    bob.command("/hook add 实验室 评分 2").await;
+    bob.expect_system("只有管理员可以使用 /hook").await;
+    alice.command(&format!("/admin {}", TOKEN)).await;
+    alice.expect_system("已获得管理员权限").await;
+    alice.command("/hook add 实验室 评分 2").await;
+    let created = alice.expect_system("已为房间 实验室 创建入站Webhook 评分（每分钟 2 条）").await.text;
+    let token = created.split("/api/hooks/").nth(1).and_then(|rest| rest.lines().next()).expect("token").to_string();
+    alice.command("/hook add 大厅 评分").await;
+    alice.expect_system("已有名为 评分 的入站Webhook").await;
+    
+    // 不能冒充服务器或用户
+    for name in ["服务器", "用户999", bob.username.as_str()] {
+        alice.command(&format!("/hook add 大厅 {}", name)).await;
+        alice.expect_system(&format!("名称 {} 与服务器或用户的名称冲突", name)).await;
+    }
+    
+    // 消息以 webhook 类型广播到令牌对应的房间
+    let (status, body) = post(&server, &token, r#"{"text": "实验一：通过 9/10"}"#).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["room"], "实验室");
+    let message = bob.expect("webhook消息", |message| message.msg_type == "webhook").await;
+    assert_eq!(message.username, "评分");
+    assert_eq!(message.room, "实验室");
+    assert_eq!(message.text, "实验一：通过 9/10");
+    assert_eq!(message.id, body["id"].as_str().unwrap());
+    alice.expect_none("其他房间的webhook消息", Duration::from_millis(200), |message| message.msg_type == "webhook").await;
+    
+    // 消息记入房间历史，之后加入的用户能看到
+    alice.join("实验室").await;
+    alice.expect("历史中的webhook消息", |message| {
+        message.msg_type == "history" && message.data.as_ref().is_some_and(|data| {
+            data["messages"].as_array().is_some_and(|messages| messages.iter().any(|m| m["msg_type"] == "webhook" && m["text"] == "实验一：通过 9/10"))
+        })
+    }).await;
+    
+    // 请求体错误与速率限制
+    assert_eq!(post(&server, &token, r#"{"message": "缺少text"}"#).await.0, 400);
+    assert_eq!(post(&server, &token, r#"{"text": "  "}"#).await.0, 400);
+    assert_eq!(post(&server, &token, r#"{"text": "实验二：通过"}"#).await.0, 200);
+    let (status, body) = post(&server, &token, r#"{"text": "实验三：通过"}"#).await;
+    assert_eq!(status, 429);
+    assert!(body["error"].as_str().unwrap().contains("超过速率限制"), "{}", body);
+    
+    alice.command("/hook list").await;
+    let list = alice.expect_system("入站Webhook:").await.text;
+    assert!(list.contains(&format!("评分 → 房间 实验室，令牌 {}…，每分钟 2 条，已发送 2 条，限流 1 次", &token[..8])), "{}", list);
+    assert!(!list.contains(&token), "{}", list);
+    
+    // 撤销后令牌失效
+    alice.command("/hook revoke 评分").await;
+    alice.expect_system("已撤销入站Webhook 评分（房间 实验室）").await;
+    let (status, body) = post(&server, &token, r#"{"text": "实验四"}"#).await;
+    assert_eq!(status, 404);
+    assert_eq!(body["error"], "令牌无效或已撤销");
+    assert_eq!(post(&server, "not-a-token", r#"{"text": "你好"}"#).await.0, 404);
+    alice.command("/hook list").await;
+    alice.expect_system("当前没有入站Webhook").await;
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::TestServer;
+use net_app::clock::ManualClock;
+use net_app::history::History;
+use net_app::{AppState, Timeouts};
+use std::sync::Arc;
+use std::time::Duration;
+
+const TOKEN: &str = "secret";
+
+async fn start(clock: Arc<ManualClock>) -> TestServer {
+    let state = AppState::new(Some(TOKEN.to_string()), History::default()).with_timeouts(Timeouts::default()).with_clock(clock);
+    TestServer::start_with_state(state).await
+}
+
+// 查询参数中的非ASCII字符按UTF-8百分号编码
+fn encode(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b',' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+// 订阅协议监视流，等待回显过滤条件的第一个事件
+async fn subscribe(server: &TestServer, query: &str) -> common::SseClient {
+    let (status, mut monitor) = server.sse(&format!("/api/monitor?ticket={}&{}", server.monitor_ticket(TOKEN).await, query)).await;
+    assert_eq!(status, 200);
+    let (name, _) = monitor.next_event(common::TIMEOUT).await.unwrap();
+    assert_eq!(name, "subscribed");
+    monitor
+}
+
+#[actix_web::test]
+async fn monitor_requires_the_admin_token() {
+    let clock = Arc::new(ManualClock::new());
+    let server = start(clock.clone()).await;
+    assert_eq!(server.sse("/api/monitor").await.0, 403);
+    assert_eq!(server.http("POST", "/api/monitor/ticket", b"").await.0, 403);
+    // 口令不能放在URL中，以免写入访问日志
+    assert_eq!(server.sse(&format!("/api/monitor?token={}", TOKEN)).await.0, 403);
+    assert_eq!(server.sse("/api/monitor?ticket=wrong").await.0, 403);
+    
+    let ticket = server.monitor_ticket(TOKEN).await;
+    assert_eq!(server.sse(&format!("/api/monitor?ticket={}&event=frame,bogus", ticket)).await.0, 400);
+    let ticket = server.monitor_ticket(TOKEN).await;
+    let (status, _monitor) = server.sse(&format!("/api/monitor?ticket={}", ticket)).await;
+    assert_eq!(status, 200);
+    // 凭证只能使用一次
+    assert_eq!(server.sse(&format!("/api/monitor?ticket={}", ticket)).await.0, 403);
+    
+    let ticket = server.monitor_ticket(TOKEN).await;
+    clock.advance(Duration::from_secs(31));
+    assert_eq!(server.sse(&format!("/api/monitor?ticket={}", ticket)).await.0, 403);
+}
+
+#[actix_web::test]
+async fn monitor_streams_frames_and_routing_filtered_by_user_and_room() {
+    let server = start(Arc::new(ManualClock::new())).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    let mut frames = subscribe(&server, &format!("user={}&event=frame,route", encode(&alice.username))).await;
+    let mut study = subscribe(&server, &format!("room={}&event=route", encode("书房"))).await;
+    
+    alice.chat("大家好").await;
+    bob.expect("alice 的聊天消息", |message| message.text == "大家好").await;
+    
+    // 客户端发来的帧、路由决策与回显帧依次出现，只包含 alice 的事件
+    let inbound = frames.expect("alice 发出的帧", |event| event["event"] == "frame").await;
+    assert_eq!(inbound["session"], alice.id.as_str());
+    assert_eq!(inbound["data"]["direction"], "in");
+    assert_eq!(inbound["data"]["opcode"], "text");
+    assert_eq!(inbound["data"]["msg_type"], "chat");
+    assert!(inbound["data"]["bytes"].as_u64().unwrap() > 0);
+    let route = frames.expect("广播路由", |event| event["event"] == "route").await;
+    assert_eq!(route["room"], "大厅");
+    assert_eq!(route["user"], alice.username.as_str());
+    assert_eq!(route["data"]["local_recipients"], 2);
+    assert_eq!(route["data"]["forwarded_nodes"], 0);
+    let echo = frames.expect("回显帧", |event| event["event"] == "frame").await;
+    assert_eq!(echo["data"]["direction"], "out");
+    assert_eq!(echo["session"], alice.id.as_str());
+    
+    // 书房的订阅只看到书房里的广播
+    bob.join("书房").await;
+    bob.chat("书房里").await;
+    bob.expect("书房的聊天回显", |message| message.text == "书房里").await;
+    loop {
+        let (_, route) = study.next_event(common::TIMEOUT).await.unwrap();
+        assert_eq!(route["room"], "书房");
+        assert_eq!(route["event"], "route");
+        // 先是服务器广播的加入通知，之后是 bob 的聊天
+        if route["user"] == bob.username.as_str() {
+            assert_eq!(route["data"]["msg_type"], "chat");
+            assert_eq!(route["data"]["local_recipients"], 1);
+            break;
+        }
+    }
+}
+
+#[actix_web::test]
+async fn monitor_reports_heartbeats_and_timeouts() {
+    let clock = Arc::new(ManualClock::new());
+    let server = start(clock.clone()).await;
+    let mut monitor = subscribe(&server, "event=heartbeat,timeout,close").await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    bob.auto_pong = false;
+    let is_bob = |kind: &'static str, id: String| move |event: &serde_json::Value| event["event"] == kind && event["session"] == id.as_str();
+    
+    let heartbeat = monitor.expect("bob 的第一次心跳", is_bob("heartbeat", bob.id.clone())).await;
+    assert_eq!(heartbeat["data"]["interval_ms"], 30_000);
+    assert_eq!(heartbeat["data"]["timeout_ms"], 90_000);
+    
+    // 每推进30秒发送一次心跳，沉默超过90秒后断开
+    for _ in 0..4 {
+        clock.advance(Duration::from_secs(30));
+        alice.command("/users").await;
+        alice.expect_system("当前房间有").await;
+    }
+    let last = is_bob("heartbeat", bob.id.clone());
+    monitor.expect("bob 沉默90秒时的心跳", |event| last(event) && event["data"]["since_heartbeat_ms"] == 90_000).await;
+    let timeout = monitor.expect("bob 超时", is_bob("timeout", bob.id.clone())).await;
+    assert_eq!(timeout["data"]["silent_ms"], 120_000);
+    assert!(timeout["summary"].as_str().unwrap().contains("断开连接"));
+    monitor.expect("bob 的会话关闭", is_bob("close", bob.id.clone())).await;
+    bob.expect_closed().await;
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::TestServer;
+use net_app::protocol::ChatMessage;
+use net_app::Timeouts;
+use std::time::Duration;
+
+#[actix_web::test]
+async fn join_and_leave_are_announced_to_the_room() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    
+    alice.join("测试房").await;
+    bob.join("测试房").await;
+    alice.expect_system(&format!("{} 加入了房间", bob.username)).await;
+    let joined = alice.expect_userlist("join").await;
+    assert_eq!(joined.data.unwrap()["user"]["id"], bob.id.as_str());
+    
+    // 房间内的聊天消息广播给所有成员，发送者信息由服务器填写
+    bob.chat("大家好").await;
+    let message = alice.expect("bob 的聊天消息", |message| message.msg_type == "chat" && message.text == "大家好").await;
+    assert_eq!(message.username, bob.username);
+    assert_eq!(message.room, "测试房");
+    
+    bob.join("大厅").await;
+    alice.expect_system(&format!("{} 离开了房间", bob.username)).await;
+    alice.expect_leave(&bob.id).await;
+    
+    bob.join("测试房").await;
+    alice.expect_userlist("join").await;
+    bob.close().await;
+    alice.expect_system(&format!("{} 离开了聊天室", bob.username)).await;
+    alice.expect_leave(&bob.id).await;
+}
+
+#[actix_web::test]
+async fn rejoining_the_current_room_is_reported() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect().await;
+    
+    alice.send(ChatMessage::new("join", &alice.username, "大厅", "")).await;
+    alice.expect_system("您已经在房间 大厅 中").await;
+}
+
+#[actix_web::test]
+async fn private_messages_reach_only_the_target() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    let mut carol = server.connect().await;
+    
+    alice.private(&bob.username, "悄悄话").await;
+    let received = bob.expect("私聊消息", |message| message.msg_type == "private").await;
+    assert_eq!(received.username, alice.username);
+    assert_eq!(received.target.as_deref(), Some(bob.username.as_str()));
+    assert_eq!(received.text, "悄悄话");
+    
+    // 发送方收到回显，其他人收不到
+    alice.expect("私聊回显", |message| message.msg_type == "private" && message.text == "悄悄话").await;
+    carol.expect_none("私聊消息", Duration::from_millis(300), |message| message.msg_type == "private").await;
+    
+    alice.private("不存在的用户", "你好").await;
+    alice.expect_system("用户 不存在的用户 不在线或不存在").await;
+}
+
+#[actix_web::test]
+async fn commands_are_answered_with_system_messages() {
+    let server = TestServer::start_with(Some("口令"), Timeouts::default()).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    
+    // /help 列出全部命令
+    alice.command("/help").await;
+    let help = alice.expect_system("可用命令").await;
+    for name in ["/help", "/rooms", "/join", "/users", "/msg", "/ping", "/stats", "/search", "/export", "/admin"] {
+        assert!(help.text.contains(name), "/help 缺少 {}", name);
+    }
+    
+    bob.join("书房").await;
+    alice.command("/rooms").await;
+    let rooms = alice.expect_system("可用房间").await;
+    assert!(rooms.text.contains("书房 (1 人在线)"), "{}", rooms.text);
+    
+    alice.command("/users").await;
+    let users = alice.expect_system("当前房间有").await;
+    assert!(users.text.contains(&alice.username));
+    assert!(!users.text.contains(&bob.username));
+    
+    alice.command("/nope").await;
+    alice.expect_system("未知命令: /nope").await;
+    
+    alice.command("/admin 错误").await;
+    alice.expect_system("管理员口令错误").await;
+    alice.command("/admin 口令").await;
+    alice.expect_system("已获得管理员权限").await;
+    
+    // 管理员的 /users 带有客户端地址
+    alice.command("/users").await;
+    let users = alice.expect_system("当前房间有").await;
+    assert!(users.text.contains("127.0.0.1"), "{}", users.text);
+}
+
+#[actix_web::test]
+async fn silent_clients_time_out() {
+    let server = TestServer::start_with(None, Timeouts {
+        heartbeat_interval: Duration::from_millis(100),
+        heartbeat_timeout: Duration::from_millis(300),
+        ..Timeouts::default()
+    }).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    bob.auto_pong = false;
+    
+    // 只读取消息而不回复心跳的客户端会被断开，房间内其他人收到离开通知
+    alice.expect_leave(&bob.id).await;
+    bob.expect_closed().await;
+    
+    // 回复心跳的客户端保持在线
+    alice.command("/users").await;
+    let users = alice.expect_system("当前房间有 1 名用户").await;
+    assert!(users.text.contains(&alice.username));
+}
+
+#[actix_web::test]
+async fn stale_sessions_from_the_same_address_are_evicted() {
+    let server = TestServer::start_with(None, Timeouts {
+        heartbeat_interval: Duration::from_secs(60),
+        heartbeat_timeout: Duration::from_secs(120),
+        stale_after: Duration::from_millis(300),
+    }).await;
+    let mut stale = server.connect().await;
+    let mut watcher = server.connect().await;
+    
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    // 观察者刚刚发过消息，不会被当作陈旧连接
+    watcher.command("/ping").await;
+    let _newcomer = server.connect().await;
+    
+    watcher.expect_leave(&stale.id).await;
+    
+    // 被清理的会话再发消息时，服务器关闭连接
+    stale.chat("还在吗").await;
+    stale.expect_closed().await;
+}
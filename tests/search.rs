@@ -0,0 +1,92 @@
+mod common;
+
+use common::TestServer;
+use net_app::history::History;
+use net_app::protocol::ChatMessage;
+use net_app::{AppState, Timeouts};
+
+async fn search(server: &TestServer, query: &str) -> (u16, serde_json::Value) {
+    let (status, body) = server.http("GET", &format!("/api/search?{}", query), b"").await;
+    (status, serde_json::from_slice(&body).unwrap_or_default())
+}
+
+#[actix_web::test]
+async fn huge_page_numbers_are_clamped() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect().await;
+    alice.chat("hello paging").await;
+    alice.expect("自己的消息", |message| message.msg_type == "chat" && message.text == "hello paging").await;
+    
+    // 页码超出范围时返回空的一页，而不是在持有历史锁时溢出
+    alice.command("/search hello page:18446744073709551615").await;
+    alice.expect_system("找到 1 条消息（第 10000/1 页）").await;
+    let (status, page) = search(&server, "q=hello&page=18446744073709551615&per_page=18446744073709551615").await;
+    assert_eq!(status, 200);
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["page"], 10000);
+    assert_eq!(page["per_page"], 100);
+    assert_eq!(page["results"].as_array().map(Vec::len), Some(0));
+    
+    // 之后的搜索和聊天不受影响
+    alice.command("/search hello page:0").await;
+    alice.expect_system("找到 1 条消息（第 1/1 页）").await;
+    let (status, page) = search(&server, "q=hello").await;
+    assert_eq!(status, 200);
+    assert_eq!(page["results"][0]["text"], "hello paging");
+    alice.chat("still alive").await;
+    alice.expect("之后的消息", |message| message.msg_type == "chat" && message.text == "still alive").await;
+}
+
+#[actix_web::test]
+async fn private_messages_are_searchable_only_by_their_sessions() {
+    let server = TestServer::start_with(Some("口令"), Timeouts::default()).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    let mut carol = server.connect().await;
+    
+    alice.private(&bob.username, "secret plan").await;
+    bob.expect("私聊消息", |message| message.msg_type == "private" && message.text == "secret plan").await;
+    
+    // 收发双方都能搜索到，其他用户和匿名REST请求搜索不到
+    for client in [&mut alice, &mut bob] {
+        client.command("/search secret").await;
+        client.expect_system("找到 1 条消息").await;
+    }
+    carol.command("/search secret").await;
+    carol.expect_system("没有找到匹配的消息").await;
+    assert_eq!(search(&server, "q=secret").await.1["total"], 0);
+    
+    // 重新连接的会话即使拿到同样的用户名，也看不到之前会话的私聊
+    bob.close().await;
+    let mut bob = server.connect().await;
+    bob.command("/search secret").await;
+    bob.expect_system("没有找到匹配的消息").await;
+    
+    carol.command("/admin 口令").await;
+    carol.expect_system("已获得管理员权限").await;
+    carol.command("/search secret").await;
+    carol.expect_system("找到 1 条消息").await;
+}
+
+#[actix_web::test]
+async fn persisted_private_messages_are_hidden_from_users() {
+    let path = std::env::temp_dir().join(format!("net_app-{}.jsonl", uuid::Uuid::new_v4()));
+    {
+        let mut history = History::open(&path).expect("open history");
+        let mut message = ChatMessage::new("private", "用户001", "大厅", "old secret");
+        message.target = Some("用户002".to_string());
+        history.record_private(&message, ["会话1", "会话2"]);
+    }
+    
+    // 重启后恢复的私聊不再对应任何会话，只有管理员能搜索到
+    let history = History::open(&path).expect("reopen history");
+    let server = TestServer::start_with_state(AppState::new(Some("secret".to_string()), history)).await;
+    let mut alice = server.connect().await;
+    alice.command("/search old secret").await;
+    alice.expect_system("没有找到匹配的消息").await;
+    let (status, body) = server.http_with("GET", "/api/search?q=secret", &[("Authorization", "Bearer secret")], b"").await;
+    assert_eq!(status, 200);
+    let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(page["results"][0]["text"], "old secret");
+    let _ = std::fs::remove_file(&path);
+}
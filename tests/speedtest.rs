@@ -0,0 +1,114 @@
+mod common;
+
+use common::{TestClient, TestServer};
+use net_app::protocol::ChatMessage;
+
+fn speedtest_op<'a>(op: &'a str) -> impl Fn(&ChatMessage) -> bool + 'a {
+    move |message| message.msg_type == "speedtest" && message.data.as_ref().is_some_and(|data| data["op"] == op)
+}
+
+// 发送测速命令，返回服务器的开始消息中的测试参数
+async fn start_test(client: &mut TestClient, command: &str) -> serde_json::Value {
+    client.command(command).await;
+    client.expect("测速开始", speedtest_op("begin")).await.data.unwrap()
+}
+
+async fn expect_report(client: &mut TestClient) -> serde_json::Value {
+    let report = client.expect("测速结果", speedtest_op("report")).await;
+    assert!(report.text.contains("Mbps"));
+    report.data.unwrap()
+}
+
+#[actix_web::test]
+async fn download_counts_acknowledged_text_and_binary_frames() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    
+    for (mode, binary) in [("text", false), ("binary", true)] {
+        let begin = start_test(&mut client, &format!("/speedtest download 4096 1 {}", mode)).await;
+        assert_eq!(begin["direction"], "download");
+        assert_eq!(begin["size"], 4096);
+        assert_eq!(begin["duration_ms"], 1000);
+        assert_eq!(begin["binary"], binary);
+        client.speedtest = Some(begin["test"].as_str().unwrap().to_string());
+        
+        let report = expect_report(&mut client).await;
+        client.speedtest = None;
+        let frames = report["frames"].as_u64().unwrap();
+        assert!(frames > 0);
+        assert_eq!(report["bytes"], frames * 4096);
+        assert_eq!(report["binary"], binary);
+        assert!(report["mbps"].as_f64().unwrap() > 0.0);
+        // 确认的到达时间不晚于测试时长加上等待剩余确认的时间
+        let elapsed_ms = report["elapsed_ms"].as_f64().unwrap();
+        assert!(elapsed_ms > 0.0 && elapsed_ms < 3000.0, "elapsed {}", elapsed_ms);
+        let samples = report["samples"].as_array().unwrap();
+        assert!(!samples.is_empty());
+        assert_eq!(samples.iter().map(|sample| sample["bytes"].as_u64().unwrap()).sum::<u64>(), frames * 4096);
+    }
+}
+
+#[actix_web::test]
+async fn upload_reports_frames_received_before_the_end_message() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    
+    let begin = start_test(&mut client, "/speedtest upload 1024 1 binary").await;
+    assert_eq!(begin["direction"], "upload");
+    for _ in 0..20 {
+        client.binary(&[7; 1024]).await;
+    }
+    let mut end = ChatMessage::new("speedtest", &client.username, &client.room, "");
+    end.data = Some(serde_json::json!({ "op": "end", "test": begin["test"] }));
+    client.send(end.clone()).await;
+    let report = expect_report(&mut client).await;
+    assert_eq!(report["direction"], "upload");
+    assert_eq!(report["frames"], 20);
+    assert_eq!(report["bytes"], 20 * 1024);
+    
+    // 文本帧的字节数为负载文本的长度
+    let begin = start_test(&mut client, "/speedtest upload 100 1").await;
+    for _ in 0..5 {
+        let mut frame = ChatMessage::new("speedtest", &client.username, &client.room, &"x".repeat(100));
+        frame.data = Some(serde_json::json!({ "op": "data", "test": begin["test"] }));
+        client.send(frame).await;
+    }
+    end.data = Some(serde_json::json!({ "op": "end", "test": begin["test"] }));
+    client.send(end).await;
+    let report = expect_report(&mut client).await;
+    assert_eq!(report["binary"], false);
+    assert_eq!(report["bytes"], 500);
+}
+
+#[actix_web::test]
+async fn speedtest_rejects_invalid_arguments() {
+    let server = TestServer::start().await;
+    let mut client = server.connect().await;
+    
+    client.command("/speedtest 0").await;
+    client.expect_system("每帧字节数应在").await;
+    client.command("/speedtest upload 1024 60").await;
+    client.expect_system("测试时长应在").await;
+    client.command("/speedtest sideways").await;
+    client.expect_system("无法识别的参数").await;
+    client.command("/speedtest http").await;
+    client.expect_system("/api/speedtest/upload").await;
+}
+
+#[actix_web::test]
+async fn http_speedtest_streams_and_measures_bodies() {
+    let server = TestServer::start().await;
+    
+    let (status, body) = server.http("GET", "/api/speedtest/download?size=200000", b"").await;
+    assert_eq!(status, 200);
+    assert_eq!(body.len(), 200_000);
+    assert_eq!(server.http("GET", "/api/speedtest/download?size=999999999999", b"").await.0, 400);
+    
+    let (status, body) = server.http("POST", "/api/speedtest/upload", &vec![1; 300_000]).await;
+    assert_eq!(status, 200);
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["transport"], "http");
+    assert_eq!(report["direction"], "upload");
+    assert_eq!(report["bytes"], 300_000);
+    assert!(report["text"].as_str().unwrap().contains("上传测速完成"));
+}
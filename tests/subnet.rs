@@ -0,0 +1,84 @@
+mod common;
+
+use common::TestServer;
+use net_app::clock::ManualClock;
+use net_app::history::History;
+use net_app::AppState;
+use std::sync::Arc;
+use std::time::Duration;
+
+const TOKEN: &str = "secret";
+
+// 大厅属于 office，书房属于 lab，两者之间经 r1、r2 连接，单程共60毫秒
+const TOPOLOGY: &[&str] = &[
+    "/net subnet office 10.0.1.0/24",
+    "/net subnet lab 10.0.2.7/24",
+    "/net router r1",
+    "/net router r2",
+    "/net link office r1 10",
+    "/net link r1 r2 20",
+    "/net link r2 lab 30",
+    "/net room 大厅 office",
+    "/net room 书房 lab",
+];
+
+#[actix_web::test]
+async fn private_messages_are_routed_hop_by_hop_between_subnets() {
+    let clock = Arc::new(ManualClock::new());
+    let server = TestServer::start_with_state(AppState::new(Some(TOKEN.to_string()), History::default()).with_clock(clock.clone())).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    
+    bob.command("/net router r9").await;
+    bob.expect_system("只有管理员可以修改网络拓扑").await;
+    alice.command(&format!("/admin {}", TOKEN)).await;
+    alice.expect_system("已获得管理员权限").await;
+    for command in TOPOLOGY {
+        alice.command(command).await;
+        alice.expect(command, |message| message.msg_type == "system" && message.text.starts_with("已")).await;
+    }
+    alice.command("/net subnet dorm 10.0.1.128/25").await;
+    alice.expect_system("与子网 office (10.0.1.0/24) 重叠").await;
+    alice.command("/net link office lab 5").await;
+    alice.expect_system("子网之间需要通过路由器连接").await;
+    bob.join("书房").await;
+    
+    // 发送方立即收到带路径的回显，接收方在三跳的延迟都经过后才收到
+    alice.private(&bob.username, "跨子网").await;
+    let echo = alice.expect("私聊回显", |message| message.msg_type == "private").await;
+    let route = echo.data.unwrap();
+    assert_eq!(route["route"], serde_json::json!(["office", "r1", "r2", "lab"]));
+    assert_eq!(route["delay_ms"], 60);
+    for _ in 0..3 {
+        bob.expect_none("链路延迟未到时的私聊", Duration::from_millis(100), |message| message.msg_type == "private").await;
+        clock.advance(Duration::from_millis(30));
+    }
+    bob.expect("经路由转发的私聊", |message| message.msg_type == "private" && message.text == "跨子网").await;
+    
+    alice.command(&format!("/traceroute {}", bob.username)).await;
+    let trace = alice.expect_system("共 3 跳").await;
+    assert!(trace.text.contains(" 1  r1  20 毫秒  20 毫秒  20 毫秒"), "{}", trace.text);
+    assert!(trace.text.contains(" 3  lab (10.0.2.0/24)  120 毫秒"), "{}", trace.text);
+    bob.command(&format!("/traceroute {}", bob.username)).await;
+    bob.expect_system("位于同一子网").await;
+    
+    // 丢包率100%的链路上私聊与探测全部丢失
+    alice.command("/net link r2 lab 30 100").await;
+    alice.expect_system("丢包率 100%").await;
+    alice.private(&bob.username, "会丢失").await;
+    alice.expect("私聊回显", |message| message.msg_type == "private" && message.text == "会丢失").await;
+    // 每次推进的时间足以走完任意一跳，转发任务稍晚才开始等待下一跳也不影响结果
+    for _ in 0..4 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        clock.advance(Duration::from_millis(60));
+    }
+    alice.expect_system("私聊在链路 r2 → lab 上丢失").await;
+    alice.command(&format!("/traceroute {}", bob.username)).await;
+    alice.expect_system(" 3  lab (10.0.2.0/24)  *  *  *").await;
+    
+    alice.command("/net unlink r1 r2").await;
+    alice.expect_system("已断开").await;
+    alice.private(&bob.username, "不可达").await;
+    alice.expect_system("目标不可达：从子网 office (10.0.1.0/24) 到 lab (10.0.2.0/24) 没有路由").await;
+    bob.expect_none("丢失或不可达的私聊", Duration::from_millis(200), |message| message.msg_type == "private").await;
+}
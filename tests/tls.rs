@@ -0,0 +1,24 @@
+use net_app::tls::TlsSettings;
+
+#[test]
+fn self_signed_certificates_are_generated_with_a_private_key_file() {
+    let dir = std::env::temp_dir().join(format!("net_app-{}", uuid::Uuid::new_v4()));
+    std::env::set_var("NET_APP_TLS_ADDR", "127.0.0.1:8443");
+    // 重复的主机名只保留一个
+    std::env::set_var("NET_APP_TLS_HOSTS", "example.test,localhost,example.test");
+    let settings = TlsSettings::from_env(&dir).expect("generate certificate").expect("TLS enabled");
+    assert_eq!(settings.port, 8443);
+    
+    let key_path = dir.join("tls").join("key.pem");
+    assert!(std::fs::read_to_string(&key_path).unwrap().contains("PRIVATE KEY"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&key_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "私钥文件权限为 {:o}", mode & 0o777);
+    }
+    
+    // 已生成的证书在重启后直接读取
+    assert!(TlsSettings::from_env(&dir).unwrap().is_some());
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::{TestServer, TIMEOUT};
+use net_app::Timeouts;
+use std::time::Duration;
+
+const TOKEN: &str = "secret";
+const AUTH: (&str, &str) = ("Authorization", "Bearer secret");
+
+async fn get_json(server: &TestServer, path: &str) -> (u16, serde_json::Value) {
+    let (status, body) = server.http_with("GET", path, &[AUTH], b"").await;
+    (status, serde_json::from_slice(&body).expect("JSON body"))
+}
+
+#[actix_web::test]
+async fn sessions_count_frames_by_type_and_opcode() {
+    let server = TestServer::start_with(Some(TOKEN), Timeouts::default()).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    
+    // 协议层ping与无法解析的文本帧
+    alice.ping(b"abc").await;
+    alice.text("not json").await;
+    alice.expect_system("消息格式错误").await;
+    alice.command("/stats me").await;
+    let report = alice.expect_system("的流量统计").await.text;
+    assert!(report.starts_with(&format!("{} 的流量统计（WebSocket", alice.username)), "{}", report);
+    assert!(report.contains("发送失败 0 次，丢弃 1 条"), "{}", report);
+    assert!(report.contains("command: 收 1 帧"), "{}", report);
+    let opcodes = report.split("按帧类型:").nth(1).expect("opcode section");
+    assert!(opcodes.contains("ping: 收 1 帧 3 字节，发 0 帧 0 字节"), "{}", report);
+    assert!(opcodes.contains("pong: 收 0 帧 0 字节，发 1 帧 3 字节"), "{}", report);
+    
+    // 查看其他用户需要管理员权限
+    bob.command(&format!("/stats {}", alice.username)).await;
+    bob.expect_system("只有管理员可以查看其他用户的流量统计").await;
+    alice.command(&format!("/admin {}", TOKEN)).await;
+    alice.expect_system("已获得管理员权限").await;
+    alice.command(&format!("/stats {}", bob.username)).await;
+    alice.expect_system(&format!("{} 的流量统计", bob.username)).await;
+    alice.command("/stats nobody").await;
+    alice.expect_system("用户 nobody 不在线或不在本节点").await;
+}
+
+#[actix_web::test]
+async fn traffic_api_reports_counters_and_reconnects() {
+    let server = TestServer::start_with(Some(TOKEN), Timeouts::default()).await;
+    let mut alice = server.connect().await;
+    
+    let (status, _) = server.http("GET", "/api/traffic", b"").await;
+    assert_eq!(status, 403);
+    
+    alice.chat("hello").await;
+    alice.expect("hello 的回显", |message| message.msg_type == "chat" && message.text == "hello").await;
+    // 路径参数可以是用户名（需百分号编码）或会话ID
+    let encoded: String = alice.username.bytes().map(|byte| format!("%{:02X}", byte)).collect();
+    let (status, entry) = get_json(&server, &format!("/api/traffic/{}", encoded)).await;
+    assert_eq!(status, 200);
+    assert_eq!(entry["id"], alice.id.as_str());
+    let chat = &entry["traffic"]["by_msg_type"]["chat"];
+    assert_eq!(chat["frames_in"], 1);
+    assert_eq!(chat["frames_out"], 2); // 初始化用户名的空消息与回显
+    assert!(entry["traffic"]["frames_in"].as_u64().unwrap() >= 1);
+    assert!(entry["traffic"]["by_opcode"]["text"]["bytes_out"].as_u64().unwrap() > 0);
+    assert_eq!(entry["traffic"]["reconnects"], 0);
+    
+    // 同一地址的会话结束后很快建立的新会话计为重连
+    alice.close().await;
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while !get_json(&server, "/api/traffic").await.1.as_array().unwrap().is_empty() {
+        assert!(tokio::time::Instant::now() < deadline, "alice 的会话没有被移除");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    let bob = server.connect().await;
+    let (_, entry) = get_json(&server, &format!("/api/traffic/{}", bob.id)).await;
+    assert!(entry["traffic"]["reconnects"].as_u64().unwrap() >= 1, "{}", entry);
+    
+    let (status, _) = get_json(&server, "/api/traffic/nobody").await;
+    assert_eq!(status, 404);
+}
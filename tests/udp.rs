@@ -0,0 +1,95 @@
+mod common;
+
+use common::{TestServer, TIMEOUT};
+use net_app::clock::ManualClock;
+use net_app::history::History;
+use net_app::{udp, AppState};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+fn datagram(seq: u64, msg_type: &str, text: &str) -> Vec<u8> {
+    serde_json::to_vec(&serde_json::json!({
+        "seq": seq, "msg_type": msg_type, "username": "", "room": "", "text": text, "timestamp": 0, "id": seq.to_string(), "target": null,
+    })).unwrap()
+}
+
+async fn receive(socket: &UdpSocket, within: Duration) -> Option<serde_json::Value> {
+    let mut buf = vec![0u8; 65536];
+    let (len, _) = tokio::time::timeout(within, socket.recv_from(&mut buf)).await.ok()?.ok()?;
+    Some(serde_json::from_slice(&buf[..len]).unwrap())
+}
+
+// 启动UDP传输，返回已连接到它的客户端套接字
+async fn start_udp(server: &TestServer) -> UdpSocket {
+    let addr = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    actix_web::rt::spawn(udp::run(addr.to_string(), server.state.clone()));
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(addr).await.unwrap();
+    client
+}
+
+// 发送足够长的数据报换取cookie，UDP传输刚启动时可能还没开始监听，因此重试
+async fn request_cookie(client: &UdpSocket) -> String {
+    let padded = datagram(1, "chat", &" ".repeat(256));
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    let cookie = loop {
+        assert!(tokio::time::Instant::now() < deadline, "没有收到cookie");
+        client.send(&padded).await.unwrap();
+        if let Some(reply) = receive(client, Duration::from_millis(100)).await {
+            break reply;
+        }
+    };
+    assert_eq!(cookie["msg_type"], "cookie");
+    assert!(serde_json::to_vec(&cookie).unwrap().len() <= padded.len());
+    cookie["text"].as_str().unwrap().to_string()
+}
+
+#[actix_web::test]
+async fn new_addresses_complete_a_cookie_round_trip_first() {
+    let server = TestServer::start().await;
+    let mut alice = server.connect().await;
+    let client = start_udp(&server).await;
+    
+    // 足够长的数据报只换来一个不比它大的cookie，不会建立会话
+    let cookie = request_cookie(&client).await;
+    
+    // 短数据报和错误的cookie都得不到会话，短数据报也得不到回复
+    client.send(&datagram(2, "chat", "你好")).await.unwrap();
+    assert!(receive(&client, Duration::from_millis(200)).await.is_none(), "短数据报不应有回复");
+    client.send(&datagram(3, "hello", "0123456789abcdef")).await.unwrap();
+    assert!(receive(&client, Duration::from_millis(200)).await.is_none(), "错误的cookie不应建立会话");
+    alice.command("/users").await;
+    alice.expect_system("当前房间有 1 名用户").await;
+    
+    // 带正确cookie的 hello 建立会话
+    client.send(&datagram(4, "hello", &cookie)).await.unwrap();
+    let welcome = receive(&client, TIMEOUT).await.expect("欢迎消息");
+    assert_eq!(welcome["seq"], 1);
+    let joined = alice.expect_userlist("join").await;
+    assert_eq!(joined.data.unwrap()["user"]["transport"], "udp");
+    
+    // 主动离开后需要重新握手
+    client.send(&datagram(5, "leave", "")).await.unwrap();
+    alice.expect_userlist("leave").await;
+    client.send(&datagram(6, "chat", "还在吗")).await.unwrap();
+    alice.expect_none("离开后的消息", Duration::from_millis(200), |message| message.text == "还在吗").await;
+}
+
+#[actix_web::test]
+async fn expired_cookies_are_rejected() {
+    let clock = Arc::new(ManualClock::new());
+    let server = TestServer::start_with_state(AppState::new(None, History::default()).with_clock(clock.clone())).await;
+    let client = start_udp(&server).await;
+    let stale = request_cookie(&client).await;
+    
+    // 两个时间段之后旧cookie失效，需要重新换取
+    clock.advance(Duration::from_secs(60));
+    client.send(&datagram(2, "hello", &stale)).await.unwrap();
+    assert!(receive(&client, Duration::from_millis(200)).await.is_none(), "过期的cookie不应建立会话");
+    let fresh = request_cookie(&client).await;
+    assert_ne!(fresh, stale);
+    client.send(&datagram(3, "hello", &fresh)).await.unwrap();
+    let welcome = receive(&client, TIMEOUT).await.expect("欢迎消息");
+    assert_eq!(welcome["seq"], 1);
+}
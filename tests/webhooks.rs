@@ -0,0 +1,221 @@
+mod common;
+
+use common::{TestServer, TIMEOUT};
+use net_app::Timeouts;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+const TOKEN: &str = "secret";
+const AUTH: (&str, &str) = ("Authorization", "Bearer secret");
+
+// 接收端收到的一个请求，请求头名称为小写
+struct Request {
+    headers: HashMap<String, String>,
+    body: serde_json::Value,
+    raw_body: Vec<u8>,
+    received: Instant,
+}
+
+// 本地的Webhook接收端：按预设的状态码依次响应，用完后一律返回200
+struct Hook {
+    addr: SocketAddr,
+    requests: mpsc::UnboundedReceiver<Request>,
+}
+
+impl Hook {
+    async fn start(statuses: Vec<u16>) -> Hook {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind receiver");
+        let addr = listener.local_addr().unwrap();
+        let (sender, requests) = mpsc::unbounded_channel();
+        actix_web::rt::spawn(async move {
+            let mut statuses = statuses.into_iter();
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let request = read_request(&mut stream).await;
+                let status = statuses.next().unwrap_or(200);
+                let response = format!("HTTP/1.1 {} Test\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = sender.send(request);
+            }
+        });
+        Hook { addr, requests }
+    }
+    
+    fn url(&self) -> String {
+        format!("http://{}/hook", self.addr)
+    }
+    
+    // 等待满足条件的请求，跳过其他请求
+    async fn expect(&mut self, description: &str, predicate: impl Fn(&Request) -> bool) -> Request {
+        let deadline = tokio::time::Instant::now() + TIMEOUT;
+        loop {
+            match tokio::time::timeout_at(deadline, self.requests.recv()).await {
+                Ok(Some(request)) if predicate(&request) => return request,
+                Ok(Some(_)) => {}
+                _ => panic!("等待 {} 超时", description),
+            }
+        }
+    }
+    
+    async fn expect_event(&mut self, event: &str, room: &str) -> Request {
+        self.expect(&format!("{} {}", event, room), |request| request.body["event"] == event && request.body["room"] == room).await
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Request {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let split = loop {
+        if let Some(split) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+            break split;
+        }
+        let n = stream.read(&mut buf).await.expect("read request");
+        assert!(n > 0, "请求不完整");
+        data.extend_from_slice(&buf[..n]);
+    };
+    let head = String::from_utf8_lossy(&data[..split]).to_string();
+    let headers: HashMap<String, String> = head.lines().skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    let length: usize = headers["content-length"].parse().unwrap();
+    let mut body = data[split + 4..].to_vec();
+    while body.len() < length {
+        let n = stream.read(&mut buf).await.expect("read body");
+        assert!(n > 0, "请求体不完整");
+        body.extend_from_slice(&buf[..n]);
+    }
+    Request {
+        headers,
+        body: serde_json::from_slice(&body).expect("JSON body"),
+        raw_body: body,
+        received: Instant::now(),
+    }
+}
+
+fn signature(secret: &str, body: &[u8]) -> String {
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    let hex: String = ring::hmac::sign(&key, body).as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+async fn subscribe(server: &TestServer, body: serde_json::Value) -> (u16, serde_json::Value) {
+    let (status, response) = server.http_with("POST", "/api/webhooks", &[AUTH, ("Content-Type", "application/json")], body.to_string().as_bytes()).await;
+    (status, serde_json::from_slice(&response).unwrap_or_default())
+}
+
+async fn subscriptions(server: &TestServer) -> serde_json::Value {
+    let (status, body) = server.http_with("GET", "/api/webhooks", &[AUTH], b"").await;
+    assert_eq!(status, 200);
+    serde_json::from_slice(&body).expect("JSON body")
+}
+
+#[actix_web::test]
+async fn room_events_are_posted_with_signatures() {
+    let server = TestServer::start_with(Some(TOKEN), Timeouts::default()).await;
+    let mut hook = Hook::start(Vec::new()).await;
+    
+    // 管理接口需要管理员口令，只支持 http:// 地址
+    assert_eq!(server.http("GET", "/api/webhooks", b"").await.0, 403);
+    assert_eq!(server.http_with("POST", "/api/webhooks", &[("Content-Type", "application/json")], br#"{"url":"http://127.0.0.1:1/"}"#).await.0, 403);
+    let (status, error) = subscribe(&server, serde_json::json!({ "url": "https://example.com/hook" })).await;
+    assert_eq!(status, 400);
+    assert_eq!(error["error"], "只支持 http:// 地址");
+    for url in ["http://127.0.0.1:1/hook\r\nX-Injected: 1", "http://127.0.0.1:1/a b", "http://127.0.0.1\r\n:1/"] {
+        let (status, error) = subscribe(&server, serde_json::json!({ "url": url })).await;
+        assert_eq!(status, 400);
+        assert_eq!(error["error"], "地址不能包含空白或控制字符");
+    }
+    
+    let (status, created) = subscribe(&server, serde_json::json!({ "url": hook.url(), "secret": "口令密钥" })).await;
+    assert_eq!(status, 201);
+    assert_eq!(created["secret"], "口令密钥");
+    let id = created["id"].as_str().unwrap().to_string();
+    
+    let mut alice = server.connect().await;
+    let join = hook.expect_event("join", "大厅").await;
+    assert_eq!(join.body["user"], alice.username.as_str());
+    assert_eq!(join.body["session"], alice.id.as_str());
+    assert_eq!(join.headers["x-netapp-event"], "join");
+    assert_eq!(join.headers["x-netapp-attempt"], "1");
+    assert_eq!(join.headers["x-netapp-signature"], signature("口令密钥", &join.raw_body));
+    
+    // 换房间：离开旧房间、创建新房间、加入新房间
+    alice.join("书房").await;
+    hook.expect_event("leave", "大厅").await;
+    let created_room = hook.expect_event("room_created", "书房").await;
+    assert_eq!(created_room.body["user"], alice.username.as_str());
+    hook.expect_event("join", "书房").await;
+    
+    alice.chat("你好").await;
+    let message = hook.expect_event("message", "书房").await;
+    assert_eq!(message.body["message"]["text"], "你好");
+    assert_eq!(message.body["message"]["username"], alice.username.as_str());
+    assert_eq!(message.headers["x-netapp-signature"], signature("口令密钥", &message.raw_body));
+    
+    // 最后一个用户断开后房间清空
+    alice.close().await;
+    hook.expect_event("leave", "书房").await;
+    let emptied = hook.expect_event("room_emptied", "书房").await;
+    assert!(emptied.body.get("user").is_none());
+    
+    let list = subscriptions(&server).await;
+    assert_eq!(list[0]["id"], id.as_str());
+    assert!(list[0].get("secret").is_none(), "{}", list);
+    assert!(list[0]["stats"]["delivered"].as_u64().unwrap() >= 6, "{}", list);
+    
+    // 取消订阅后不再推送
+    assert_eq!(server.http_with("DELETE", &format!("/api/webhooks/{}", id), &[AUTH], b"").await.0, 204);
+    assert_eq!(server.http_with("DELETE", &format!("/api/webhooks/{}", id), &[AUTH], b"").await.0, 404);
+    let _bob = server.connect().await;
+    assert!(tokio::time::timeout(Duration::from_millis(300), hook.requests.recv()).await.is_err(), "取消订阅后仍然收到请求");
+}
+
+#[actix_web::test]
+async fn failed_deliveries_are_retried_with_backoff() {
+    let server = TestServer::start_with(Some(TOKEN), Timeouts::default()).await;
+    // 第一个事件前两次失败，第二个事件遇到不可重试的400
+    let mut hook = Hook::start(vec![500, 503, 200, 400]).await;
+    let (status, created) = subscribe(&server, serde_json::json!({ "url": hook.url(), "events": ["join"], "rooms": ["大厅"], "max_attempts": 3, "retry_ms": 50 })).await;
+    assert_eq!(status, 201);
+    let secret = created["secret"].as_str().unwrap().to_string();
+    assert_eq!(secret.len(), 32);
+    
+    let alice = server.connect().await;
+    let first = hook.expect("第1次投递", |request| request.body["event"] == "join").await;
+    let second = hook.expect("第2次投递", |_| true).await;
+    let third = hook.expect("第3次投递", |_| true).await;
+    for (attempt, request) in [&first, &second, &third].into_iter().enumerate() {
+        assert_eq!(request.body["user"], alice.username.as_str());
+        assert_eq!(request.headers["x-netapp-attempt"], (attempt + 1).to_string());
+        assert_eq!(request.headers["x-netapp-delivery"], first.headers["x-netapp-delivery"]);
+        assert_eq!(request.headers["x-netapp-signature"], signature(&secret, &request.raw_body));
+    }
+    // 重试间隔按指数增长
+    assert!(second.received - first.received >= Duration::from_millis(50));
+    assert!(third.received - second.received >= Duration::from_millis(100));
+    
+    let bob = server.connect().await;
+    let rejected = hook.expect("第二个事件", |_| true).await;
+    assert_eq!(rejected.body["user"], bob.username.as_str());
+    assert_eq!(rejected.headers["x-netapp-attempt"], "1");
+    assert_ne!(rejected.headers["x-netapp-delivery"], first.headers["x-netapp-delivery"]);
+    
+    // 投递任务在收到响应后才更新统计
+    let deadline = Instant::now() + TIMEOUT;
+    let stats = loop {
+        let list = subscriptions(&server).await;
+        if list[0]["stats"]["failed"] == 1 || Instant::now() > deadline {
+            break list[0]["stats"].clone();
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    assert_eq!(stats["delivered"], 1, "{}", stats);
+    assert_eq!(stats["failed"], 1, "{}", stats);
+    assert_eq!(stats["retries"], 2, "{}", stats);
+    assert_eq!(stats["last_status"], 400, "{}", stats);
+    assert!(tokio::time::timeout(Duration::from_millis(300), hook.requests.recv()).await.is_err(), "400 不应重试");
+}
@@ -295,12 +295,31 @@ export default createStore({
             break;
             
           case 'userlist': {
-            const users = message.text.split(',')
-              .filter(u => u.trim() !== '')
-              .map(u => {
-                const [username, address] = u.split(':');
-                return { username, address };
-              });
+            const data = message.data || {};
+            const toUser = (entry) => ({
+              id: entry.id,
+              username: entry.username,
+              address: entry.addr || '',
+              role: entry.role,
+              presence: entry.presence,
+              joinTime: entry.join_time,
+              rtt: entry.rtt_ms
+            });
+            let users = this.state.users;
+            switch (data.op) {
+              case 'snapshot':
+                users = data.users.map(toUser);
+                break;
+              case 'join':
+                users = [...users.filter(u => u.id !== data.user.id), toUser(data.user)];
+                break;
+              case 'update':
+                users = users.map(u => u.id === data.user.id ? toUser(data.user) : u);
+                break;
+              case 'leave':
+                users = users.filter(u => u.id !== data.id);
+                break;
+            }
             commit('setUsers', users);
             commit('addNetworkLog', { type: 'info', message: `更新用户列表: ${users.length}个用户` });
             break;
@@ -0,0 +1,114 @@
+// WebSocket被代理阻断时使用的回退传输。
+// 两个类都模仿浏览器WebSocket的接口（readyState、send、close以及onopen/onmessage/onclose/onerror），
+// ChatView无需区分当前使用的传输方式。
+
+const CONNECTING = 0
+const OPEN = 1
+const CLOSED = 3
+
+class HttpSocket {
+  constructor(baseUrl) {
+    this.baseUrl = baseUrl
+    this.readyState = CONNECTING
+    this.token = null
+    this.onopen = null
+    this.onmessage = null
+    this.onclose = null
+    this.onerror = null
+  }
+
+  _open(token) {
+    this.token = token
+    this.readyState = OPEN
+    if (this.onopen) this.onopen()
+  }
+
+  _deliver(data) {
+    if (this.onmessage) this.onmessage({ data })
+  }
+
+  _closed(code, reason) {
+    if (this.readyState === CLOSED) return
+    this.readyState = CLOSED
+    if (this.onclose) this.onclose({ code, reason })
+  }
+
+  send(data) {
+    if (this.readyState !== OPEN) return
+    fetch(`${this.baseUrl}/send/${this.token}`, {
+      method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: data
+    }).then(response => {
+      if (response.status === 404) this._closed(1006, '会话已过期')
+    }).catch(() => {
+      if (this.onerror) this.onerror(new Error('发送失败'))
+    })
+  }
+
+  close(code = 1000, reason = '') {
+    if (this.readyState === OPEN) {
+      this.send(JSON.stringify({
+        msg_type: 'leave', username: '', room: '', text: '', timestamp: Date.now(), id: '', target: null
+      }))
+    }
+    this._closed(code, reason)
+  }
+}
+
+// 服务器到客户端使用Server-Sent Events，客户端到服务器使用POST
+export class SseSocket extends HttpSocket {
+  constructor(baseUrl) {
+    super(baseUrl)
+    this.source = new EventSource(`${baseUrl}/sse`)
+    this.source.addEventListener('session', event => {
+      this._open(JSON.parse(event.data).token)
+    })
+    this.source.onmessage = event => this._deliver(event.data)
+    this.source.onerror = () => {
+      // EventSource会自动重连并建立新会话，这里直接关闭交给ChatView的重连逻辑处理
+      this.source.close()
+      if (this.onerror) this.onerror(new Error('SSE连接错误'))
+      this._closed(1006, 'SSE连接断开')
+    }
+  }
+
+  close(code, reason) {
+    super.close(code, reason)
+    this.source.close()
+  }
+}
+
+// 长轮询：建立会话后不断发起GET请求，服务器在有消息或超时后返回
+export class PollSocket extends HttpSocket {
+  constructor(baseUrl) {
+    super(baseUrl)
+    fetch(`${baseUrl}/poll`, { method: 'POST' })
+      .then(response => response.json())
+      .then(({ token }) => {
+        this._open(token)
+        this._poll()
+      })
+      .catch(() => {
+        if (this.onerror) this.onerror(new Error('长轮询连接失败'))
+        this._closed(1006, '长轮询连接失败')
+      })
+  }
+
+  _poll() {
+    if (this.readyState !== OPEN) return
+    fetch(`${this.baseUrl}/poll/${this.token}`)
+      .then(response => {
+        if (!response.ok) throw new Error(`HTTP ${response.status}`)
+        return response.json()
+      })
+      .then(frames => {
+        frames.forEach(frame => this._deliver(JSON.stringify(frame)))
+        this._poll()
+      })
+      .catch(() => {
+        if (this.onerror) this.onerror(new Error('长轮询请求失败'))
+        this._closed(1006, '长轮询请求失败')
+      })
+  }
+}